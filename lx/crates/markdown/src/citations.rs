@@ -0,0 +1,674 @@
+//! Citations of works in a bibliography, using Pandoc-style syntax:
+//!
+//! - `[@key]`: a citation of a single work
+//! - `[@key, p. 12]`: with a locator; a bare number (`[@key, 12]`) is a page
+//! - `[@one; @two, chap. 3]`: several works at once
+//!
+//! Like internal links, citations are found during the first pass, and the caller
+//! supplies the bibliography and style to format them with before the second pass. For
+//! note styles (e.g. Chicago notes-bibliography), each citation becomes a footnote. For
+//! in-text styles (e.g. Chicago author-date), it is rendered in place. Either way, the
+//! works cited are listed in a bibliography after the footnotes.
+//!
+//! Formatting is done by [`hayagriva`], entirely offline, using its bundled copies of
+//! the official CSL styles and locales.
+//!
+//! Citations in footnote definitions are formatted in place, as part of that note.
+//!
+//! Prefixes and suffixes (`[see @key, p. 12, for details]`) and suppressing the author
+//! (`[-@key]`) are not supported, and so are simply left as text.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use hayagriva::archive::{self, ArchivedStyle};
+use hayagriva::citationberg::{
+   self, taxonomy::Locator, Display, FontStyle, FontVariant, FontWeight,
+   IndependentStyle, Locale, StyleClass, TextDecoration, VerticalAlign,
+};
+use hayagriva::{
+   BibliographyDriver, BibliographyRequest, CitationItem, CitationRequest, ElemChild,
+   ElemChildren, Formatted, Library, LocatorPayload, SpecificLocator,
+};
+use pulldown_cmark::{CowStr, Event as CmarkEvent, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use thiserror::Error;
+
+use super::crossrefs;
+use super::first_pass::{Definitions, Event, TextRun};
+use super::location::line_and_column;
+
+/// Characters allowed *inside* a citation key, in addition to alphanumerics and `_`.
+/// As in Pandoc, they cannot end a key, so `[@smith.]` is not a citation of `smith.`.
+const KEY_PUNCTUATION: &str = ":.#$%&-+?<>~/";
+
+#[derive(Debug)]
+pub(super) struct Citation {
+   items: Vec<Item>,
+   /// The citation as written, for reporting it and for emitting it unchanged when it
+   /// is never formatted.
+   text: String,
+   /// Byte offset of the start of the citation in the original source.
+   pub(super) offset: usize,
+}
+
+impl fmt::Display for Citation {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      f.write_str(&self.text)
+   }
+}
+
+#[derive(Debug)]
+struct Item {
+   key: String,
+   locator: Option<(Locator, String)>,
+}
+
+/// The works available to cite, loaded from BibTeX/BibLaTeX or CSL-JSON.
+pub struct Bibliography {
+   entries: Entries,
+}
+
+enum Entries {
+   BibLaTeX(Library),
+   CslJson(HashMap<String, citationberg::json::Item>),
+}
+
+impl Bibliography {
+   /// Load a bibliography, using the extension to pick the format: `.bib` for
+   /// BibTeX/BibLaTeX and `.json` for CSL-JSON.
+   pub fn from_path(path: &Path) -> Result<Bibliography, BibliographyError> {
+      let contents =
+         std::fs::read_to_string(path).map_err(|source| BibliographyError::Read {
+            path: path.to_owned(),
+            source,
+         })?;
+
+      match path.extension().and_then(|ext| ext.to_str()) {
+         Some("bib") => Bibliography::from_biblatex(&contents),
+         Some("json") => Bibliography::from_csl_json(&contents),
+         _ => Err(BibliographyError::Format {
+            path: path.to_owned(),
+         }),
+      }
+   }
+
+   pub fn from_biblatex(src: &str) -> Result<Bibliography, BibliographyError> {
+      let library = hayagriva::io::from_biblatex_str(src).map_err(|errors| {
+         BibliographyError::BibLaTeX(
+            errors
+               .iter()
+               .map(ToString::to_string)
+               .collect::<Vec<_>>()
+               .join("; "),
+         )
+      })?;
+
+      Ok(Bibliography {
+         entries: Entries::BibLaTeX(library),
+      })
+   }
+
+   pub fn from_csl_json(src: &str) -> Result<Bibliography, BibliographyError> {
+      let items: Vec<citationberg::json::Item> = serde_json::from_str(src)?;
+      let by_id = items
+         .into_iter()
+         .map(|item| match item.id() {
+            Some(id) => Ok((id.to_string(), item)),
+            None => Err(BibliographyError::MissingId),
+         })
+         .collect::<Result<_, _>>()?;
+
+      Ok(Bibliography {
+         entries: Entries::CslJson(by_id),
+      })
+   }
+
+   fn contains(&self, key: &str) -> bool {
+      match &self.entries {
+         Entries::BibLaTeX(library) => library.get(key).is_some(),
+         Entries::CslJson(items) => items.contains_key(key),
+      }
+   }
+
+   /// Run every citation through `hayagriva`. Its `EntryLike` trait is not exported,
+   /// so this cannot be generic over the kinds of entries; the macro stands in for that.
+   fn render(
+      &self,
+      requests: &[(&[Item], Option<usize>)],
+      style: &Style,
+   ) -> hayagriva::Rendered {
+      macro_rules! render {
+         ($entries:expr) => {{
+            let mut driver = BibliographyDriver::new();
+            for (items, note_number) in requests {
+               let items = items
+                  .iter()
+                  .map(|item| {
+                     CitationItem::new(
+                        $entries.get(&item.key).expect("every key is checked first"),
+                        item.locator.as_ref().map(|(locator, value)| {
+                           SpecificLocator(*locator, LocatorPayload::Str(value))
+                        }),
+                        None,
+                        false,
+                        None,
+                     )
+                  })
+                  .collect();
+
+               driver.citation(CitationRequest::new(
+                  items,
+                  &style.csl,
+                  None,
+                  &style.locales,
+                  *note_number,
+               ));
+            }
+
+            driver.finish(BibliographyRequest::new(&style.csl, None, &style.locales))
+         }};
+      }
+
+      match &self.entries {
+         Entries::BibLaTeX(library) => render!(library),
+         Entries::CslJson(items) => render!(items),
+      }
+   }
+}
+
+#[derive(Error, Debug)]
+pub enum BibliographyError {
+   #[error("could not read bibliography '{path}'")]
+   Read {
+      path: PathBuf,
+      source: std::io::Error,
+   },
+
+   #[error("unknown bibliography format for '{path}' (expected '.bib' or '.json')")]
+   Format { path: PathBuf },
+
+   #[error("could not parse BibTeX: {0}")]
+   BibLaTeX(String),
+
+   #[error("could not parse CSL-JSON")]
+   CslJson {
+      #[from]
+      source: serde_json::Error,
+   },
+
+   #[error("every item in a CSL-JSON bibliography needs an 'id'")]
+   MissingId,
+}
+
+/// A CSL style to format citations and the bibliography with, along with the locales
+/// which supply its terms ("p.", "ed.", "and", etc.).
+pub struct Style {
+   csl: IndependentStyle,
+   locales: Vec<Locale>,
+}
+
+impl Style {
+   /// One of the styles bundled with `hayagriva`, by its short name, e.g.
+   /// `chicago-author-date`, `chicago-notes`, or `apa`.
+   pub fn named(name: &str) -> Result<Style, StyleError> {
+      let style = ArchivedStyle::by_name(name)
+         .ok_or_else(|| StyleError::Unknown(name.to_string()))?
+         .get();
+
+      Style::from_style(style)
+   }
+
+   /// A style from a CSL file.
+   pub fn from_path(path: &Path) -> Result<Style, StyleError> {
+      let xml = std::fs::read_to_string(path).map_err(|source| StyleError::Read {
+         path: path.to_owned(),
+         source,
+      })?;
+
+      Style::from_csl(&xml)
+   }
+
+   /// A style from the XML of a CSL file.
+   pub fn from_csl(xml: &str) -> Result<Style, StyleError> {
+      let style = citationberg::Style::from_xml(xml)
+         .map_err(|source| StyleError::Parse { source })?;
+
+      Style::from_style(style)
+   }
+
+   fn from_style(style: citationberg::Style) -> Result<Style, StyleError> {
+      match style {
+         citationberg::Style::Independent(csl) => Ok(Style {
+            csl,
+            locales: archive::locales(),
+         }),
+         citationberg::Style::Dependent(_) => Err(StyleError::Dependent),
+      }
+   }
+
+   fn is_note(&self) -> bool {
+      self.csl.settings.class == StyleClass::Note
+   }
+}
+
+#[derive(Error, Debug)]
+pub enum StyleError {
+   #[error("no citation style named '{0}'")]
+   Unknown(String),
+
+   #[error("could not read CSL style '{path}'")]
+   Read {
+      path: PathBuf,
+      source: std::io::Error,
+   },
+
+   #[error("could not parse CSL style")]
+   Parse { source: citationberg::XmlError },
+
+   #[error("dependent CSL styles are not supported; use the style they depend on")]
+   Dependent,
+}
+
+#[derive(Error, Debug)]
+#[error("no bibliography entry for '@{key}' at line {line}, column {column}")]
+pub struct UnknownCitation {
+   pub key: String,
+   pub line: usize,
+   pub column: usize,
+}
+
+#[derive(Error, Debug)]
+pub struct UnknownCitations(pub Vec<UnknownCitation>);
+
+impl fmt::Display for UnknownCitations {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      writeln!(f, "could not find {} cited works", self.0.len())?;
+      for citation in &self.0 {
+         writeln!(f, "\t{citation}")?;
+      }
+      Ok(())
+   }
+}
+
+pub(super) enum Piece<'e> {
+   Text(Vec<(CowStr<'e>, usize)>),
+   Citation(Citation),
+}
+
+/// Given a run of adjacent text nodes and their offsets in the original source, find
+/// any citations in them. Text between citations is kept as runs, so that other syntax
+/// (i.e. wiki-style links) can still be found in it.
+pub(super) fn split_citations(run: Vec<(CowStr<'_>, usize)>) -> Vec<Piece<'_>> {
+   let run = TextRun::new(run);
+   if !run.joined.contains('@') {
+      return vec![Piece::Text(run.into_parts().collect())];
+   }
+   let joined = run.joined.as_str();
+
+   let text = |start: usize, end: usize| {
+      let offset = run.source_offset(start);
+      Piece::Text(vec![(joined[start..end].to_string().into(), offset)])
+   };
+
+   let mut pieces = Vec::new();
+   let mut rest_start = 0;
+   let mut search_from = 0;
+   while let Some(open) = joined[search_from..].find('[').map(|i| i + search_from) {
+      search_from = open + 1;
+
+      let Some(close) = joined[open + 1..].find(']').map(|i| i + open + 1) else {
+         break;
+      };
+
+      let inner = &joined[open + 1..close];
+      if inner.contains('[') {
+         continue;
+      }
+
+      let Some(items) = parse(inner) else {
+         continue;
+      };
+
+      if open > rest_start {
+         pieces.push(text(rest_start, open));
+      }
+
+      pieces.push(Piece::Citation(Citation {
+         items,
+         text: joined[open..=close].to_string(),
+         offset: run.source_offset(open),
+      }));
+
+      rest_start = close + 1;
+      search_from = rest_start;
+   }
+
+   if rest_start == 0 {
+      return vec![Piece::Text(run.into_parts().collect())];
+   }
+
+   if rest_start < joined.len() {
+      pieces.push(text(rest_start, joined.len()));
+   }
+
+   pieces
+}
+
+/// Parse the inside of a bracketed citation. Everything in it must be a citation item
+/// for it to count: `[see @key]` is left as text.
+fn parse(inner: &str) -> Option<Vec<Item>> {
+   inner
+      .split(';')
+      .map(|item| parse_item(item.trim()))
+      .collect()
+}
+
+fn parse_item(item: &str) -> Option<Item> {
+   let item = item.strip_prefix('@')?;
+   if !item.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+      return None;
+   }
+
+   let key_end = item
+      .find(|c: char| !(c.is_alphanumeric() || c == '_' || KEY_PUNCTUATION.contains(c)))
+      .unwrap_or(item.len());
+   let key = item[..key_end].trim_end_matches(|c| KEY_PUNCTUATION.contains(c));
+   if crossrefs::is_label(key) {
+      return None;
+   }
+
+   let rest = item[key.len()..].trim();
+   let locator = if rest.is_empty() {
+      None
+   } else {
+      Some(parse_locator(rest.strip_prefix(',')?.trim())?)
+   };
+
+   Some(Item {
+      key: key.to_string(),
+      locator,
+   })
+}
+
+/// A locator is an optional label followed by its value, e.g. `chap. 3` or `§ 12`.
+/// Without a (known) label, the whole thing is a page.
+fn parse_locator(locator: &str) -> Option<(Locator, String)> {
+   if locator.is_empty() {
+      return None;
+   }
+
+   let labelled = locator
+      .split_once(char::is_whitespace)
+      .and_then(|(label, value)| {
+         let label = label_for(&label.to_lowercase())?;
+         let value = value.trim();
+         (!value.is_empty()).then(|| (label, value.to_string()))
+      });
+
+   Some(labelled.unwrap_or_else(|| (Locator::Page, locator.to_string())))
+}
+
+fn label_for(label: &str) -> Option<Locator> {
+   let locator = match label {
+      "p." | "pp." | "page" | "pages" => Locator::Page,
+      "bk." | "bks." | "book" | "books" => Locator::Book,
+      "chap." | "chaps." | "ch." | "chapter" | "chapters" => Locator::Chapter,
+      "col." | "cols." | "column" | "columns" => Locator::Column,
+      "fig." | "figs." | "figure" | "figures" => Locator::Figure,
+      "l." | "ll." | "line" | "lines" => Locator::Line,
+      "n." | "nn." | "note" | "notes" => Locator::Note,
+      "para." | "paras." | "¶" | "¶¶" | "paragraph" | "paragraphs" => {
+         Locator::Paragraph
+      }
+      "pt." | "pts." | "part" | "parts" => Locator::Part,
+      "sec." | "secs." | "§" | "§§" | "section" | "sections" => Locator::Section,
+      "v." | "vv." | "verse" | "verses" => Locator::Verse,
+      "vol." | "vols." | "volume" | "volumes" => Locator::Volume,
+      _ => return None,
+   };
+   Some(locator)
+}
+
+/// Format every citation in `events` and the footnote definitions with the bibliography
+/// and style, returning the HTML for the bibliography itself (if the style has one and
+/// anything was cited). Every citation of a work missing from the bibliography is
+/// collected, rather than stopping at the first.
+pub(super) fn format<'e>(
+   events: &mut [Event<'e>],
+   footnote_definitions: &mut Definitions<'e>,
+   source: &str,
+   bibliography: &Bibliography,
+   style: &Style,
+) -> Result<Option<String>, UnknownCitations> {
+   // Find every citation, and the number of the note it will be in for note styles,
+   // which `hayagriva` uses to decide on e.g. "ibid." and short forms. A citation in the
+   // content becomes a note of its own; one in a footnote is part of that note.
+   let mut cited = Vec::new();
+   let mut note_numbers = HashMap::new();
+   let mut note_number = 0;
+   for (index, event) in events.iter().enumerate() {
+      match event {
+         Event::FootnoteReference(name) if footnote_definitions.contains_key(name) => {
+            note_number += 1;
+            note_numbers.entry(name.clone()).or_insert(note_number);
+         }
+
+         Event::Citation(_) => {
+            if style.is_note() {
+               note_number += 1;
+            }
+            cited.push((
+               Cited::InContent(index),
+               style.is_note().then_some(note_number),
+            ));
+         }
+
+         _ => {}
+      }
+   }
+
+   let mut names = footnote_definitions.keys().cloned().collect::<Vec<_>>();
+   names.sort_by_key(|name| (note_numbers.get(name).copied(), name.to_string()));
+   for name in names {
+      let number = note_numbers.get(&name).copied();
+      for (index, event) in footnote_definitions[&name].iter().enumerate() {
+         if let Event::Citation(_) = event {
+            let note_number = style.is_note().then_some(number).flatten();
+            cited.push((Cited::InNote(name.clone(), index), note_number));
+         }
+      }
+   }
+
+   // The notes come in the order they are numbered, not in the order they are written.
+   cited.sort_by_key(|(_, note_number)| *note_number);
+
+   let mut unknown = Vec::new();
+   let mut requests = Vec::with_capacity(cited.len());
+   for (cited, note_number) in &cited {
+      let citation = match cited {
+         Cited::InContent(index) => &events[*index],
+         Cited::InNote(name, index) => &footnote_definitions[name][*index],
+      };
+      let Event::Citation(citation) = citation else {
+         unreachable!("only citations are collected");
+      };
+
+      for item in &citation.items {
+         if !bibliography.contains(&item.key) {
+            let (line, column) = line_and_column(source, citation.offset);
+            unknown.push(UnknownCitation {
+               key: item.key.clone(),
+               line,
+               column,
+            });
+         }
+      }
+
+      requests.push((citation.items.as_slice(), *note_number));
+   }
+
+   if !unknown.is_empty() {
+      unknown.sort_by_key(|citation| (citation.line, citation.column));
+      return Err(UnknownCitations(unknown));
+   }
+
+   if cited.is_empty() {
+      return Ok(None);
+   }
+
+   let rendered = bibliography.render(&requests, style);
+
+   for ((cited, note_number), formatted) in cited.into_iter().zip(rendered.citations) {
+      let Event::Citation(citation) = cited.event(events, footnote_definitions) else {
+         unreachable!("only citations are collected");
+      };
+
+      let mut html = String::from(r#"<span class="citation" data-cites=""#);
+      let keys = citation
+         .items
+         .iter()
+         .map(|item| item.key.as_str())
+         .collect::<Vec<_>>()
+         .join(" ");
+      push_escaped(&mut html, &keys);
+      html.push_str(r#"">"#);
+      push_html(&mut html, &formatted.citation);
+      html.push_str("</span>");
+      let offset = citation.offset;
+
+      *cited.event(events, footnote_definitions) = match (&cited, note_number) {
+         (Cited::InContent(_), Some(note_number)) => {
+            let name: CowStr<'e> = format!("lx-citation-{note_number}").into();
+            footnote_definitions.insert(
+               name.clone(),
+               vec![
+                  Event::Basic(CmarkEvent::Start(Tag::Paragraph), None),
+                  Event::Basic(CmarkEvent::InlineHtml(html.into()), Some(offset)),
+                  Event::Basic(CmarkEvent::End(TagEnd::Paragraph), None),
+               ],
+            );
+            Event::FootnoteReference(name)
+         }
+         _ => Event::Basic(CmarkEvent::InlineHtml(html.into()), Some(offset)),
+      };
+   }
+
+   Ok(rendered.bibliography.map(|bibliography| {
+      let mut html =
+         String::from(r#"<section class="bibliography"><ul class="bibliography-list">"#);
+      for item in bibliography.items {
+         html.push_str(r#"<li id="ref-"#);
+         push_escaped(&mut html, &item.key);
+         html.push_str(r#"">"#);
+         if let Some(first_field) = &item.first_field {
+            push_child(&mut html, first_field);
+         }
+         push_html(&mut html, &item.content);
+         html.push_str("</li>");
+      }
+      html.push_str("</ul></section>");
+      html
+   }))
+}
+
+/// Where a citation is: at an index in the content, or in a footnote definition.
+enum Cited<'e> {
+   InContent(usize),
+   InNote(CowStr<'e>, usize),
+}
+
+impl<'e> Cited<'e> {
+   fn event<'a>(
+      &self,
+      events: &'a mut [Event<'e>],
+      footnote_definitions: &'a mut Definitions<'e>,
+   ) -> &'a mut Event<'e> {
+      match self {
+         Cited::InContent(index) => &mut events[*index],
+         Cited::InNote(name, index) => &mut footnote_definitions
+            .get_mut(name)
+            .expect("only citations in existing notes are collected")[*index],
+      }
+   }
+}
+
+/// `hayagriva` can write its own HTML, but does not escape any of the text in it, so an
+/// entry for e.g. *AT&T: A History* would produce invalid HTML.
+fn push_html(html: &mut String, children: &ElemChildren) {
+   for child in &children.0 {
+      push_child(html, child);
+   }
+}
+
+fn push_child(html: &mut String, child: &ElemChild) {
+   match child {
+      ElemChild::Text(formatted) => push_formatted(html, formatted),
+
+      // "Markup" is text intended for Typst, which means nothing in HTML.
+      ElemChild::Markup(markup) => push_escaped(html, markup),
+
+      ElemChild::Link { text, url } => {
+         html.push_str(r#"<a href=""#);
+         escape_href(&mut *html, url).expect("writing to a String cannot fail");
+         html.push_str(r#"">"#);
+         push_formatted(html, text);
+         html.push_str("</a>");
+      }
+
+      ElemChild::Elem(elem) => {
+         let class = elem.display.map(|display| match display {
+            Display::Block => "csl-block",
+            Display::LeftMargin => "csl-left-margin",
+            Display::RightInline => "csl-right-inline",
+            Display::Indent => "csl-indent",
+         });
+
+         match class {
+            Some(class) => {
+               html.push_str(&format!(r#"<div class="{class}">"#));
+               push_html(html, &elem.children);
+               html.push_str("</div>");
+            }
+            None => push_html(html, &elem.children),
+         }
+      }
+
+      // Only produced for `LocatorPayload::Transparent`, which is never used here.
+      ElemChild::Transparent { .. } => {}
+   }
+}
+
+fn push_formatted(html: &mut String, Formatted { text, formatting }: &Formatted) {
+   let mut tags = Vec::new();
+   if formatting.font_style == FontStyle::Italic {
+      tags.push(("<i>", "</i>"));
+   }
+   if formatting.font_weight == FontWeight::Bold {
+      tags.push(("<b>", "</b>"));
+   }
+   if formatting.font_variant == FontVariant::SmallCaps {
+      tags.push((r#"<span style="font-variant: small-caps">"#, "</span>"));
+   }
+   if formatting.text_decoration == TextDecoration::Underline {
+      tags.push(("<u>", "</u>"));
+   }
+   match formatting.vertical_align {
+      VerticalAlign::Sup => tags.push(("<sup>", "</sup>")),
+      VerticalAlign::Sub => tags.push(("<sub>", "</sub>")),
+      VerticalAlign::None | VerticalAlign::Baseline => {}
+   }
+
+   for (open, _) in &tags {
+      html.push_str(open);
+   }
+   push_escaped(html, text);
+   for (_, close) in tags.iter().rev() {
+      html.push_str(close);
+   }
+}
+
+fn push_escaped(html: &mut String, text: &str) {
+   escape_html(&mut *html, text).expect("writing to a String cannot fail");
+}
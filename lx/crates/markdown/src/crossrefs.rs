@@ -0,0 +1,649 @@
+//! Numbered figures, tables, equations, and sections, and references to them.
+//!
+//! - A paragraph containing only an image with a title becomes a `<figure>`, with the
+//!   title as its caption: `![alt](map.png "The map"){#fig:map}`.
+//! - A paragraph starting with `Table:` directly after a table is its caption:
+//!   `Table: Results {#tbl:results}`.
+//! - Display math can be labelled: `$$E = mc^2$$ {#eq:energy}`.
+//! - Headings use the usual attribute syntax: `## Method {#sec:method}`.
+//!
+//! Labels are all optional, but only labelled items can be referenced, with e.g.
+//! `@fig:map`, which becomes a link reading "Figure 1". Every reference must resolve to a
+//! label in the same document, and every label must follow something it can label.
+//! Footnote definitions can have both: anything labelled in them is numbered after the
+//! rest of the document, in the order the definitions appear in the source.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use pulldown_cmark::{CowStr, Event as CmarkEvent, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use thiserror::Error;
+
+use super::first_pass::{Definitions, Event, TextRun};
+use super::location::{at, Location};
+use super::math::Expression;
+
+/// Characters allowed in a label, in addition to alphanumerics. As with citation keys,
+/// they cannot end a label, so `see @fig:map.` refers to `fig:map`.
+const LABEL_PUNCTUATION: &str = "_-:.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+   Figure,
+   Table,
+   Equation,
+   Section,
+}
+
+impl Kind {
+   fn from_prefix(prefix: &str) -> Option<Kind> {
+      match prefix {
+         "fig" => Some(Kind::Figure),
+         "tbl" => Some(Kind::Table),
+         "eq" => Some(Kind::Equation),
+         "sec" => Some(Kind::Section),
+         _ => None,
+      }
+   }
+
+   fn name(self) -> &'static str {
+      match self {
+         Kind::Figure => "Figure",
+         Kind::Table => "Table",
+         Kind::Equation => "Equation",
+         Kind::Section => "Section",
+      }
+   }
+}
+
+/// `@fig:map`: a reference to a labelled item.
+#[derive(Debug)]
+pub(super) struct Reference {
+   label: String,
+   /// Byte offset of the start of the reference in the original source.
+   pub(super) offset: usize,
+}
+
+impl fmt::Display for Reference {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "@{}", self.label)
+   }
+}
+
+/// `{#fig:map}`: the label for the figure, table, or equation it follows.
+#[derive(Debug)]
+pub(super) struct Label {
+   kind: Kind,
+   label: String,
+   /// Byte offset of the start of the label in the original source.
+   offset: usize,
+}
+
+impl fmt::Display for Label {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{{#{}}}", self.label)
+   }
+}
+
+#[derive(Error, Debug)]
+pub enum CrossReferenceError {
+   #[error("no figure, table, equation, or section labelled '{label}' (referenced at {location})")]
+   Unresolved { label: String, location: Location },
+
+   #[error(
+      "the label '{label}' at {location} does not follow a figure, table, or equation"
+   )]
+   Unattached { label: String, location: Location },
+
+   /// The location is that of the second use of the label, if it is known.
+   #[error("the label '{label}' is used more than once{}", at(.location))]
+   Duplicate {
+      label: String,
+      location: Option<Location>,
+   },
+}
+
+#[derive(Error, Debug)]
+pub struct CrossReferenceErrors(pub Vec<CrossReferenceError>);
+
+impl fmt::Display for CrossReferenceErrors {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      writeln!(f, "{} problems with cross-references", self.0.len())?;
+      for error in &self.0 {
+         writeln!(f, "\t{}", error.to_string().replace('\n', "\n\t"))?;
+      }
+      Ok(())
+   }
+}
+
+pub(super) enum Piece<'e> {
+   Text(Vec<(CowStr<'e>, usize)>),
+   Reference(Reference),
+   Label(Label),
+}
+
+/// Given a run of adjacent text nodes and their offsets in the original source, find
+/// any references in them, along with a label at the very end of the run.
+pub(super) fn split_references(run: Vec<(CowStr<'_>, usize)>) -> Vec<Piece<'_>> {
+   let run = TextRun::new(run);
+   if !run.joined.contains('@') && !run.joined.contains("{#") {
+      return vec![Piece::Text(run.into_parts().collect())];
+   }
+   let joined = run.joined.as_str();
+
+   let text = |start: usize, end: usize| {
+      let offset = run.source_offset(start);
+      Piece::Text(vec![(joined[start..end].to_string().into(), offset)])
+   };
+
+   let label = trailing_label(&run);
+   let end = label.as_ref().map_or(joined.len(), |(start, _)| *start);
+
+   let mut pieces = Vec::new();
+   let mut rest_start = 0;
+   let mut search_from = 0;
+   while let Some(at) = joined[search_from..end].find('@').map(|i| i + search_from) {
+      search_from = at + 1;
+
+      // Skip e.g. email addresses.
+      if joined[..at].ends_with(char::is_alphanumeric) {
+         continue;
+      }
+
+      let Some((_, label, len)) = parse_label(&joined[at + 1..end]) else {
+         continue;
+      };
+
+      if at > rest_start {
+         pieces.push(text(rest_start, at));
+      }
+
+      pieces.push(Piece::Reference(Reference {
+         label,
+         offset: run.source_offset(at),
+      }));
+
+      rest_start = at + 1 + len;
+      search_from = rest_start;
+   }
+
+   if rest_start == 0 && label.is_none() {
+      return vec![Piece::Text(run.into_parts().collect())];
+   }
+
+   if rest_start < end {
+      pieces.push(text(rest_start, end));
+   }
+
+   if let Some((_, label)) = label {
+      pieces.push(Piece::Label(label));
+   }
+
+   pieces
+}
+
+/// A `{#kind:name}` label at the end of `text` (ignoring trailing whitespace), and where
+/// it starts.
+fn trailing_label(run: &TextRun<'_>) -> Option<(usize, Label)> {
+   let inner = run.joined.trim_end().strip_suffix('}')?;
+   let start = inner.rfind("{#")?;
+   let (kind, label, len) = parse_label(&inner[start + 2..])?;
+   let offset = run.source_offset(start);
+   (start + 2 + len == inner.len()).then_some((
+      start,
+      Label {
+         kind,
+         label,
+         offset,
+      },
+   ))
+}
+
+/// Whether `text` is exactly a `kind:name` label, e.g. so that `[@fig:map]` is not
+/// mistaken for a citation.
+pub(super) fn is_label(text: &str) -> bool {
+   parse_label(text).is_some_and(|(_, _, len)| len == text.len())
+}
+
+/// Parse a `kind:name` label from the start of `text`, returning it along with its length.
+fn parse_label(text: &str) -> Option<(Kind, String, usize)> {
+   let (prefix, rest) = text.split_once(':')?;
+   let kind = Kind::from_prefix(prefix)?;
+
+   let name_end = rest
+      .find(|c: char| !(c.is_alphanumeric() || LABEL_PUNCTUATION.contains(c)))
+      .unwrap_or(rest.len());
+   let name = rest[..name_end].trim_end_matches(|c| LABEL_PUNCTUATION.contains(c));
+   if name.is_empty() {
+      return None;
+   }
+
+   let len = prefix.len() + 1 + name.len();
+   Some((kind, text[..len].to_string(), len))
+}
+
+/// Number every figure, table, labelled equation, and section, and replace every
+/// reference with a link to its target, in footnote definitions as well as the rest of
+/// the content. All unresolved references, duplicate labels, and labels which follow
+/// nothing they can label are collected, rather than stopping at the first.
+pub(super) fn number<'e>(
+   events: &mut Vec<Event<'e>>,
+   footnote_definitions: &mut Definitions<'e>,
+   source: &str,
+) -> Result<(), CrossReferenceErrors> {
+   let mut numbering = Numbering::new(events);
+   *events = numbering.walk(std::mem::take(events));
+
+   let mut definitions = footnote_definitions.values_mut().collect::<Vec<_>>();
+   definitions.sort_by_key(|definition| first_offset(definition));
+   for definition in &mut definitions {
+      **definition = numbering.walk(std::mem::take(*definition));
+   }
+
+   let mut errors = numbering
+      .duplicates
+      .drain(..)
+      .map(|(label, offset)| CrossReferenceError::Duplicate {
+         label,
+         location: offset.map(|offset| Location::in_source(source, offset)),
+      })
+      .collect::<Vec<_>>();
+
+   let all_events = events.iter_mut().chain(definitions.into_iter().flatten());
+   for event in all_events {
+      match event {
+         Event::CrossReference(reference) => {
+            match numbering.targets.get(&reference.label) {
+               Some(text) => {
+                  let mut html =
+                     format!(r##"<a href="#{}" class="cross-ref">"##, reference.label);
+                  push_escaped(&mut html, text);
+                  html.push_str("</a>");
+                  *event = Event::Basic(
+                     CmarkEvent::InlineHtml(html.into()),
+                     Some(reference.offset),
+                  );
+               }
+               None => errors.push(CrossReferenceError::Unresolved {
+                  label: reference.label.clone(),
+                  location: Location::in_source(source, reference.offset),
+               }),
+            }
+         }
+
+         Event::Label(label) => errors.push(CrossReferenceError::Unattached {
+            label: label.label.clone(),
+            location: Location::in_source(source, label.offset),
+         }),
+
+         _ => {}
+      }
+   }
+
+   if errors.is_empty() {
+      Ok(())
+   } else {
+      Err(CrossReferenceErrors(errors))
+   }
+}
+
+/// The offset of the first event from the source, for putting footnote definitions in
+/// source order.
+fn first_offset(events: &[Event<'_>]) -> Option<usize> {
+   events.iter().find_map(|event| match event {
+      Event::Basic(_, offset) => *offset,
+      _ => None,
+   })
+}
+
+/// The title of the image, if the paragraph whose start was just removed from `rest`
+/// contains only that image (and optionally a figure label).
+fn figure_title<'e>(rest: &VecDeque<Event<'e>>) -> Option<CowStr<'e>> {
+   let Some(Event::Basic(CmarkEvent::Start(Tag::Image { title, .. }), _)) = rest.front()
+   else {
+      return None;
+   };
+
+   if title.is_empty() {
+      return None;
+   }
+
+   let image_end = rest.iter().position(|event| {
+      matches!(event, Event::Basic(CmarkEvent::End(TagEnd::Image), _))
+   })?;
+
+   let after_image = rest
+      .iter()
+      .skip(image_end + 1)
+      .find(|event| !is_whitespace(event))?;
+
+   let end_of_paragraph = match after_image {
+      Event::Label(Label {
+         kind: Kind::Figure, ..
+      }) => rest
+         .iter()
+         .skip(image_end + 1)
+         .filter(|event| !is_whitespace(event))
+         .nth(1)?,
+      other => other,
+   };
+
+   matches!(
+      end_of_paragraph,
+      Event::Basic(CmarkEvent::End(TagEnd::Paragraph), _)
+   )
+   .then(|| title.clone())
+}
+
+/// Take a label of the given kind from the front of `rest`, if there is one, skipping any
+/// whitespace before it.
+fn take_label(kind: Kind, rest: &mut VecDeque<Event<'_>>) -> Option<Label> {
+   let next = rest.iter().position(|event| !is_whitespace(event))?;
+   if !matches!(rest.get(next), Some(Event::Label(label)) if label.kind == kind) {
+      return None;
+   }
+
+   match rest.drain(..=next).last() {
+      Some(Event::Label(label)) => Some(label),
+      _ => unreachable!("just checked that it is a label"),
+   }
+}
+
+fn is_whitespace(event: &Event<'_>) -> bool {
+   match event {
+      Event::Basic(CmarkEvent::Text(text), _) => text.trim().is_empty(),
+      Event::Basic(CmarkEvent::SoftBreak, _) => true,
+      _ => false,
+   }
+}
+
+struct Numbering {
+   figures: usize,
+   tables: usize,
+   equations: usize,
+   /// The current number at each heading level.
+   sections: [usize; 6],
+   /// The level of the highest-level heading in the document, which sections are
+   /// numbered from, so that a document using `##` for its top-level sections does not
+   /// number them all "0.1", "0.2", etc.
+   top_level: usize,
+   targets: HashMap<String, String>,
+   /// Labels used more than once, with the offset of each later use, if known.
+   duplicates: Vec<(String, Option<usize>)>,
+}
+
+impl Numbering {
+   fn new(events: &[Event<'_>]) -> Numbering {
+      let top_level = events
+         .iter()
+         .filter_map(|event| match event {
+            Event::Basic(CmarkEvent::Start(Tag::Heading { level, .. }), _) => {
+               Some(*level as usize)
+            }
+            _ => None,
+         })
+         .min()
+         .unwrap_or(1);
+
+      Numbering {
+         figures: 0,
+         tables: 0,
+         equations: 0,
+         sections: [0; 6],
+         top_level,
+         targets: HashMap::new(),
+         duplicates: Vec::new(),
+      }
+   }
+
+   /// Number everything in `events`, continuing from whatever has already been numbered.
+   fn walk<'e>(&mut self, events: Vec<Event<'e>>) -> Vec<Event<'e>> {
+      let mut numbered = Vec::with_capacity(events.len());
+      let mut rest = VecDeque::from(events);
+
+      while let Some(event) = rest.pop_front() {
+         match event {
+            Event::Basic(CmarkEvent::Start(Tag::Paragraph), offset) => {
+               if let Some(title) = figure_title(&rest) {
+                  self.figure(title, &mut rest, &mut numbered);
+               } else {
+                  numbered.push(Event::Basic(CmarkEvent::Start(Tag::Paragraph), offset));
+               }
+            }
+
+            Event::Basic(CmarkEvent::Start(Tag::Table(alignments)), offset) => {
+               self.table(alignments, offset, &mut rest, &mut numbered);
+            }
+
+            Event::Math(math) if math.display => {
+               self.equation(math, &mut rest, &mut numbered);
+            }
+
+            Event::Basic(
+               CmarkEvent::Start(Tag::Heading {
+                  level,
+                  id,
+                  classes,
+                  mut attrs,
+               }),
+               offset,
+            ) => {
+               let number = self.section(level as usize);
+               if let Some(id) = id.as_deref() {
+                  if let Some((Kind::Section, label, len)) = parse_label(id) {
+                     if len == id.len() {
+                        attrs.push(("data-number".into(), Some(number.clone().into())));
+                        self.target(label, offset, Kind::Section, number);
+                     }
+                  }
+               }
+
+               numbered.push(Event::Basic(
+                  CmarkEvent::Start(Tag::Heading {
+                     level,
+                     id,
+                     classes,
+                     attrs,
+                  }),
+                  offset,
+               ));
+            }
+
+            other => numbered.push(other),
+         }
+      }
+
+      numbered
+   }
+
+   fn target(
+      &mut self,
+      label: String,
+      offset: Option<usize>,
+      kind: Kind,
+      number: String,
+   ) {
+      let text = format!("{} {number}", kind.name());
+      if self.targets.insert(label.clone(), text).is_some() {
+         self.duplicates.push((label, offset));
+      }
+   }
+
+   fn figure<'e>(
+      &mut self,
+      title: CowStr<'e>,
+      rest: &mut VecDeque<Event<'e>>,
+      numbered: &mut Vec<Event<'e>>,
+   ) {
+      self.figures += 1;
+      let number = self.figures.to_string();
+
+      let image_end = rest
+         .iter()
+         .position(|event| {
+            matches!(event, Event::Basic(CmarkEvent::End(TagEnd::Image), _))
+         })
+         .expect("figures always have an image");
+      let image = rest.drain(..=image_end).collect::<Vec<_>>();
+
+      let label = take_label(Kind::Figure, rest);
+      while rest.front().is_some_and(is_whitespace) {
+         rest.pop_front();
+      }
+      let end_of_paragraph = rest.pop_front();
+      debug_assert!(matches!(
+         end_of_paragraph,
+         Some(Event::Basic(CmarkEvent::End(TagEnd::Paragraph), _))
+      ));
+
+      numbered.push(html(open_figure("figure", label.as_ref())));
+      numbered.extend(image);
+
+      let mut caption = format!("<figcaption>Figure {number}: ");
+      push_escaped(&mut caption, &title);
+      caption.push_str("</figcaption></figure>");
+      numbered.push(html(caption));
+
+      if let Some(Label { label, offset, .. }) = label {
+         self.target(label, Some(offset), Kind::Figure, number);
+      }
+   }
+
+   fn table<'e>(
+      &mut self,
+      alignments: Vec<pulldown_cmark::Alignment>,
+      offset: Option<usize>,
+      rest: &mut VecDeque<Event<'e>>,
+      numbered: &mut Vec<Event<'e>>,
+   ) {
+      let table_end = rest
+         .iter()
+         .position(|event| {
+            matches!(event, Event::Basic(CmarkEvent::End(TagEnd::Table), _))
+         })
+         .expect("tables are always closed");
+
+      let has_caption = matches!(
+         (rest.get(table_end + 1), rest.get(table_end + 2)),
+         (
+            Some(Event::Basic(CmarkEvent::Start(Tag::Paragraph), _)),
+            Some(Event::Basic(CmarkEvent::Text(text), _)),
+         ) if text.starts_with(TABLE_CAPTION)
+      );
+
+      let start = Event::Basic(CmarkEvent::Start(Tag::Table(alignments)), offset);
+      if !has_caption {
+         numbered.push(start);
+         return;
+      }
+
+      self.tables += 1;
+      let number = self.tables.to_string();
+
+      let table = rest.drain(..=table_end).collect::<Vec<_>>();
+      let caption_end = rest
+         .iter()
+         .position(|event| {
+            matches!(event, Event::Basic(CmarkEvent::End(TagEnd::Paragraph), _))
+         })
+         .expect("paragraphs are always closed");
+      let mut caption = rest.drain(..=caption_end).collect::<VecDeque<_>>();
+
+      // Drop the paragraph, and the `Table:` prefix on its text.
+      caption.pop_front();
+      caption.pop_back();
+      if let Some(Event::Basic(CmarkEvent::Text(text), offset)) = caption.pop_front() {
+         let trimmed = text[TABLE_CAPTION.len()..].trim_start();
+         if !trimmed.is_empty() {
+            let offset = offset.map(|offset| offset + (text.len() - trimmed.len()));
+            caption.push_front(Event::Basic(
+               CmarkEvent::Text(trimmed.to_string().into()),
+               offset,
+            ));
+         }
+      }
+
+      let label = match caption.pop_back() {
+         Some(Event::Label(label)) if label.kind == Kind::Table => Some(label),
+         Some(other) => {
+            caption.push_back(other);
+            None
+         }
+         None => None,
+      };
+
+      if let Some(Event::Basic(CmarkEvent::Text(text), _)) = caption.back_mut() {
+         *text = text.trim_end().to_string().into();
+      }
+
+      numbered.push(html(open_figure("table", label.as_ref())));
+      numbered.push(start);
+      numbered.extend(table);
+      numbered.push(html(format!("<figcaption>Table {number}: ")));
+      numbered.extend(caption);
+      numbered.push(html(String::from("</figcaption></figure>")));
+
+      if let Some(Label { label, offset, .. }) = label {
+         self.target(label, Some(offset), Kind::Table, number);
+      }
+   }
+
+   /// Only labelled equations are numbered.
+   fn equation<'e>(
+      &mut self,
+      math: Expression<'e>,
+      rest: &mut VecDeque<Event<'e>>,
+      numbered: &mut Vec<Event<'e>>,
+   ) {
+      let math = Event::Math(math);
+      let Some(Label { label, offset, .. }) = take_label(Kind::Equation, rest) else {
+         numbered.push(math);
+         return;
+      };
+
+      self.equations += 1;
+      let number = self.equations.to_string();
+
+      numbered.push(html(format!(r#"<span class="equation" id="{label}">"#)));
+      numbered.push(math);
+      numbered.push(html(format!(
+         r#"<span class="equation-number">({number})</span></span>"#
+      )));
+
+      self.target(label, Some(offset), Kind::Equation, number);
+   }
+
+   /// Every heading is numbered, so that the number of a labelled section is always the
+   /// same as its position in the document's outline.
+   fn section(&mut self, level: usize) -> String {
+      let index = level - 1;
+      self.sections[index] += 1;
+      for deeper in &mut self.sections[index + 1..] {
+         *deeper = 0;
+      }
+
+      self.sections[self.top_level - 1..=index]
+         .iter()
+         .map(ToString::to_string)
+         .collect::<Vec<_>>()
+         .join(".")
+   }
+}
+
+const TABLE_CAPTION: &str = "Table:";
+
+fn open_figure(class: &str, label: Option<&Label>) -> String {
+   match label {
+      Some(Label { label, .. }) => format!(r#"<figure class="{class}" id="{label}">"#),
+      None => format!(r#"<figure class="{class}">"#),
+   }
+}
+
+fn html<'e>(html: String) -> Event<'e> {
+   Event::Basic(CmarkEvent::Html(html.into()), None)
+}
+
+fn push_escaped(html: &mut String, text: &str) {
+   escape_html(&mut *html, text).expect("writing to a String cannot fail");
+}
@@ -0,0 +1,106 @@
+//! Diagrams: fenced `svgbob` (or `bob`) blocks of ASCII art are drawn as inline SVG with
+//! [svgbob](https://github.com/ivanceras/svgbob), instead of being highlighted as code.
+//!
+//! ````markdown
+//! ```svgbob Request flow
+//! +--------+     +--------+
+//! | client |---->| server |
+//! +--------+     +--------+
+//! ```
+//! ````
+//!
+//! Anything after the fence name is the diagram's accessible title (here, "Request
+//! flow"); without one, it is just "Diagram". Lines, text, and filled shapes are all
+//! drawn in `currentColor` on a transparent background, so diagrams follow the color of
+//! the surrounding text, including in dark themes. The `<svg>` has the class `svgbob`
+//! for any further styling.
+
+use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html_body_text;
+use svgbob::Settings;
+
+use super::transform::{Context, Located, Transform};
+
+/// The fence names which mark a block as a diagram.
+const FENCES: [&str; 2] = ["svgbob", "bob"];
+
+pub(super) struct Diagrams;
+
+impl Transform for Diagrams {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let mut transformed = Vec::with_capacity(events.len());
+      let mut diagram: Option<(String, String, Option<usize>)> = None;
+      for located in events {
+         match (located.event, &mut diagram) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), None)
+               if FENCES.contains(&fence(&info).0) =>
+            {
+               let title = fence(&info).1.unwrap_or("Diagram").to_string();
+               diagram = Some((title, String::new(), located.offset));
+            }
+
+            (Event::Text(text), Some((_, source, _))) => source.push_str(&text),
+
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+               if let Some((title, source, offset)) = diagram.take() {
+                  transformed.push(Located {
+                     event: Event::Html(render(&title, &source).into()),
+                     offset,
+                  });
+               }
+            }
+
+            (event, _) => transformed.push(Located {
+               event,
+               offset: located.offset,
+            }),
+         }
+      }
+
+      Ok(transformed)
+   }
+}
+
+/// The fence name, and the rest of the info string (if any), without surrounding
+/// quotes.
+fn fence(info: &str) -> (&str, Option<&str>) {
+   let info = info.trim();
+   match info.split_once(char::is_whitespace) {
+      Some((name, rest)) => {
+         let rest = rest.trim().trim_matches('"');
+         (name, (!rest.is_empty()).then_some(rest))
+      }
+      None => (info, None),
+   }
+}
+
+fn render(title: &str, source: &str) -> String {
+   let settings = Settings {
+      fill_color: String::from("currentColor"),
+      stroke_color: String::from("currentColor"),
+      background: String::from("transparent"),
+      font_family: String::from("monospace"),
+      include_backdrop: false,
+      ..Settings::default()
+   };
+   let svg = svgbob::to_svg_with_settings(source, &settings);
+
+   // svgbob always starts with an `<svg …>` element: mark it as an image, and put the
+   // title first inside it.
+   let Some(end) = svg.find('>') else {
+      return svg;
+   };
+   let mut accessible = String::with_capacity(svg.len() + title.len() + 32);
+   accessible.push_str(&svg[..end]);
+   accessible.push_str(r#" role="img"><title>"#);
+   escape_html_body_text(&mut accessible, title)
+      .expect("writing to a String cannot fail");
+   accessible.push_str("</title>");
+   accessible.push_str(&svg[end + 1..]);
+   accessible.push('\n');
+   accessible
+}
@@ -3,7 +3,10 @@ use std::{collections::HashMap, fmt::Debug};
 use pulldown_cmark::{CowStr, Event as CmarkEvent, MetadataBlockKind, Tag, TagEnd};
 use thiserror::Error;
 
-use super::FootnoteDefinitions;
+use super::citations::{self, Citation};
+use super::crossrefs::{self, Label, Reference};
+use super::links::{self, Form, InternalLink, Piece};
+use super::math::Expression;
 
 #[derive(Debug)]
 pub(super) struct State<S: ParseState> {
@@ -12,10 +15,22 @@ pub(super) struct State<S: ParseState> {
 
 #[derive(Debug)]
 pub(super) enum Event<'e> {
-   Basic(CmarkEvent<'e>),
+   /// A `pulldown_cmark` event with no special handling, and its offset in the source
+   /// if it came from there rather than from rewriting other events.
+   Basic(CmarkEvent<'e>, Option<usize>),
    FootnoteReference(CowStr<'e>),
+   ExcerptMarker,
+   InternalLink(InternalLink<'e>),
+   Citation(Citation),
+   CrossReference(Reference),
+   Label(Label),
+   Math(Expression<'e>),
 }
 
+/// The footnote definitions from the first pass, by name. Like the rest of the content,
+/// they can have internal links, citations, and so on in them until those are resolved.
+pub(super) type Definitions<'e> = HashMap<CowStr<'e>, Vec<Event<'e>>>;
+
 #[derive(Debug)]
 pub(super) enum FirstPass<'e> {
    Initial(State<Initial>),
@@ -29,17 +44,31 @@ impl<'e> FirstPass<'e> {
       FirstPass::Initial(State::new())
    }
 
+   /// What the state machine is doing, for error messages: the states themselves hold
+   /// far too much to be useful in one.
+   pub(super) fn description(&self) -> &'static str {
+      match self {
+         FirstPass::Initial(_) => "at the start of the document",
+         FirstPass::ExtractingMetadata(_) => "extracting metadata",
+         FirstPass::ExtractedMetadata(_) => "finishing metadata",
+         FirstPass::Content(_) => "processing content",
+      }
+   }
+
    pub(super) fn finalize(
       self,
-   ) -> Result<(Option<CowStr<'e>>, Vec<Event<'e>>, FootnoteDefinitions<'e>), Error> {
+   ) -> Result<(Option<CowStr<'e>>, Vec<Event<'e>>, Definitions<'e>), Error> {
       match self {
-         FirstPass::Content(content) => Ok((
-            content.data.metadata,
-            content.data.events,
-            content.data.footnote_definitions,
-         )),
+         FirstPass::Content(mut content) => {
+            content.flush_text();
+            Ok((
+               content.data.metadata,
+               content.data.events,
+               content.data.footnote_definitions,
+            ))
+         }
          _ => Err(Error::Finalizing {
-            state: format!("{self:?}"),
+            state: self.description(),
          }),
       }
    }
@@ -117,8 +146,12 @@ impl<'e> State<ExtractedMetadata<'e>> {
 pub(super) struct Content<'e> {
    metadata: Option<CowStr<'e>>,
    events: Vec<Event<'e>>,
-   current_footnote: Option<(CowStr<'e>, Vec<CmarkEvent<'e>>)>,
-   footnote_definitions: FootnoteDefinitions<'e>,
+   current_footnote: Option<(CowStr<'e>, Vec<Event<'e>>)>,
+   footnote_definitions: Definitions<'e>,
+   /// Adjacent text nodes (with their source offsets), held until the run ends so that
+   /// citations, cross-references, and wiki-style links split across them can be found.
+   text_run: Vec<(CowStr<'e>, usize)>,
+   in_code_block: bool,
 }
 
 impl<'e> Content<'e> {
@@ -128,6 +161,8 @@ impl<'e> Content<'e> {
          events: vec![],
          current_footnote: None,
          footnote_definitions: HashMap::new(),
+         text_run: vec![],
+         in_code_block: false,
       }
    }
 }
@@ -135,10 +170,70 @@ impl<'e> Content<'e> {
 impl ParseState for Content<'_> {}
 
 impl<'e> State<Content<'e>> {
-   /// "Handling" events consists, at this stage, of just distinguishing between
-   /// footnote references, footnote definitions, and everything else.
-   pub(super) fn handle(&mut self, event: CmarkEvent<'e>) -> Result<(), Error> {
+   /// "Handling" events consists, at this stage, of distinguishing between footnote
+   /// references, footnote definitions, internal links, citations, math, and everything
+   /// else. `offset` is the byte offset of the event in the original source.
+   pub(super) fn handle(
+      &mut self,
+      event: CmarkEvent<'e>,
+      offset: usize,
+   ) -> Result<(), Error> {
+      if let CmarkEvent::Text(text) = event {
+         if self.data.in_code_block {
+            self.push(CmarkEvent::Text(text), offset);
+         } else {
+            self.data.text_run.push((text, offset));
+         }
+         return Ok(());
+      }
+
+      self.flush_text();
+
       match event {
+         CmarkEvent::Start(Tag::CodeBlock(kind)) => {
+            self.data.in_code_block = true;
+            self.push(CmarkEvent::Start(Tag::CodeBlock(kind)), offset);
+            Ok(())
+         }
+         CmarkEvent::End(TagEnd::CodeBlock) => {
+            self.data.in_code_block = false;
+            self.push(CmarkEvent::End(TagEnd::CodeBlock), offset);
+            Ok(())
+         }
+         CmarkEvent::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+         }) if dest_url.starts_with(links::SCHEME) => {
+            let target = dest_url[links::SCHEME.len()..].to_string().into();
+            self.emit(Event::InternalLink(InternalLink {
+               target,
+               offset,
+               form: Form::Scheme {
+                  link_type,
+                  title,
+                  id,
+               },
+            }));
+            Ok(())
+         }
+         CmarkEvent::InlineMath(tex) => {
+            self.emit(Event::Math(Expression {
+               tex,
+               display: false,
+               offset,
+            }));
+            Ok(())
+         }
+         CmarkEvent::DisplayMath(tex) => {
+            self.emit(Event::Math(Expression {
+               tex,
+               display: true,
+               offset,
+            }));
+            Ok(())
+         }
          CmarkEvent::Start(Tag::FootnoteDefinition(name)) => self.start_footnote(name),
          CmarkEvent::End(TagEnd::FootnoteDefinition) => self.end_footnote(),
          CmarkEvent::FootnoteReference(name) => {
@@ -149,15 +244,73 @@ impl<'e> State<Content<'e>> {
             Ok(())
          }
          other => {
-            match self.data.current_footnote {
-               Some((_, ref mut events)) => events.push(other.clone()),
-               None => self.data.events.push(Event::Basic(other.clone())),
-            };
+            self.push(other, offset);
             Ok(())
          }
       }
    }
 
+   fn push(&mut self, event: CmarkEvent<'e>, offset: usize) {
+      self.emit(Event::Basic(event, Some(offset)));
+   }
+
+   /// Add to the footnote definition in progress, if there is one, or else the content.
+   fn emit(&mut self, event: Event<'e>) {
+      match self.data.current_footnote {
+         Some((_, ref mut events)) => events.push(event),
+         None => self.data.events.push(event),
+      }
+   }
+
+   fn flush_text(&mut self) {
+      if self.data.text_run.is_empty() {
+         return;
+      }
+
+      let run = std::mem::take(&mut self.data.text_run);
+      for piece in citations::split_citations(run) {
+         let run = match piece {
+            citations::Piece::Text(run) => run,
+            citations::Piece::Citation(citation) => {
+               self.emit(Event::Citation(citation));
+               continue;
+            }
+         };
+
+         for piece in crossrefs::split_references(run) {
+            let run = match piece {
+               crossrefs::Piece::Text(run) => run,
+               crossrefs::Piece::Reference(reference) => {
+                  self.emit(Event::CrossReference(reference));
+                  continue;
+               }
+               crossrefs::Piece::Label(label) => {
+                  self.emit(Event::Label(label));
+                  continue;
+               }
+            };
+
+            for piece in links::split_wiki_links(run) {
+               self.emit(match piece {
+                  Piece::Text(text, offset) => {
+                     Event::Basic(CmarkEvent::Text(text), Some(offset))
+                  }
+                  Piece::Link(link) => Event::InternalLink(link),
+               });
+            }
+         }
+      }
+   }
+
+   /// Record the location of the excerpt marker. A marker inside a footnote makes no
+   /// sense as the end of an excerpt, so it is simply dropped.
+   pub(super) fn mark_excerpt(&mut self) {
+      self.flush_text();
+      if self.data.current_footnote.is_none() {
+         self.data.events.push(Event::ExcerptMarker);
+      }
+   }
+
    fn start_footnote(&mut self, name: CowStr<'e>) -> Result<(), Error> {
       match self.data.current_footnote {
          Some((ref current, _)) => Err(Error::AlreadyInFootnote {
@@ -190,6 +343,41 @@ impl<'e> State<Content<'e>> {
    }
 }
 
+/// Adjacent text nodes joined into a single string, so that syntax which `pulldown_cmark`
+/// splits across several nodes (it splits text at every bracket, for example) can be
+/// found, while still mapping back to offsets in the original source.
+pub(super) struct TextRun<'e> {
+   parts: Vec<(CowStr<'e>, usize)>,
+   pub(super) joined: String,
+}
+
+impl<'e> TextRun<'e> {
+   pub(super) fn new(parts: Vec<(CowStr<'e>, usize)>) -> TextRun<'e> {
+      let joined = parts.iter().map(|(text, _)| text.as_ref()).collect();
+      TextRun { parts, joined }
+   }
+
+   /// Map an index in the joined string back to the original source offset.
+   pub(super) fn source_offset(&self, index: usize) -> usize {
+      let mut start = 0;
+      let mut found = (0, 0);
+      for (text, offset) in &self.parts {
+         if start > index {
+            break;
+         }
+         found = (start, *offset);
+         start += text.len();
+      }
+
+      let (start, offset) = found;
+      offset + (index - start)
+   }
+
+   pub(super) fn into_parts(self) -> impl Iterator<Item = (CowStr<'e>, usize)> {
+      self.parts.into_iter()
+   }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
    #[error("starting footnote '{new}' but already in footnote {current}")]
@@ -201,8 +389,8 @@ pub enum Error {
    #[error("ending footnote when not in a footnote")]
    EndFootnoteWhenNotInFootnote,
 
-   #[error("finalizing from an invalid state {state}")]
-   Finalizing { state: String },
+   #[error("finalizing while {state}")]
+   Finalizing { state: &'static str },
 }
 
 mod private {
@@ -0,0 +1,214 @@
+//! Footnotes, numbered in the order they are referenced and emitted after the content.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use log::error;
+use pulldown_cmark::{html, CowStr, Event, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+
+use super::transform::{Context, Located, Transform};
+
+/// Replaces each footnote reference with a numbered link to its definition, and moves
+/// the definitions to a list after the content. Definitions which are never referenced
+/// are dropped; a reference to a definition which does not exist is left as written.
+pub(super) struct Footnotes;
+
+impl Transform for Footnotes {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let (events, definitions) = extract_definitions(events);
+
+      let mut content = Vec::with_capacity(events.len());
+      let mut emitted = Vec::new();
+      for located in events {
+         let Event::FootnoteReference(name) = located.event else {
+            content.push(located);
+            continue;
+         };
+
+         match definitions.get(&name) {
+            Some(definition) => {
+               emitted.push(definition.clone());
+               let index = emitted.len();
+               let link = format!(
+                  r##"<sup><a href="#{name}" id="{backref}">{index}</a></sup>"##,
+                  name = footnote_ref_name(index),
+                  backref = footnote_backref_name(index),
+               );
+               content.push(Located {
+                  event: Event::Html(link.into()),
+                  offset: located.offset,
+               });
+            }
+
+            None => {
+               error!("Missing definition for footnote labeled '{name}'");
+               content.push(Located {
+                  event: Event::Text(format!("[^{name}]").into()),
+                  offset: located.offset,
+               });
+            }
+         }
+      }
+
+      if !emitted.is_empty() {
+         let (events, notes) = notes(emitted, context.appendix.len());
+         context.append(events);
+         context.footnotes.extend(notes);
+      }
+
+      Ok(content)
+   }
+}
+
+/// A footnote as emitted after the content, with its rendered HTML (without the link
+/// back to its reference).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Footnote {
+   /// Its number, in the order the footnotes are referenced.
+   pub number: usize,
+   /// The `id` of its list item, which its reference links to.
+   pub id: String,
+   pub html: String,
+}
+
+type Definitions<'e> = HashMap<CowStr<'e>, Vec<Located<'e>>>;
+
+/// Separate the footnote definitions (without their start and end tags) from the rest
+/// of the events.
+pub(super) fn extract_definitions(
+   events: Vec<Located<'_>>,
+) -> (Vec<Located<'_>>, Definitions<'_>) {
+   let mut rest = Vec::with_capacity(events.len());
+   let mut definitions = HashMap::new();
+   let mut current: Option<(CowStr, Vec<Located>)> = None;
+   for located in events {
+      match (located.event, &mut current) {
+         (Event::Start(Tag::FootnoteDefinition(name)), _) => {
+            current = Some((name, vec![]));
+         }
+
+         (Event::End(TagEnd::FootnoteDefinition), _) => {
+            if let Some((name, definition)) = current.take() {
+               definitions.insert(name, definition);
+            }
+         }
+
+         (event, Some((_, definition))) => definition.push(Located {
+            event,
+            offset: located.offset,
+         }),
+
+         (event, None) => rest.push(Located {
+            event,
+            offset: located.offset,
+         }),
+      }
+   }
+
+   (rest, definitions)
+}
+
+/// Where a footnote is in the events appended after the content, so that it can be
+/// rendered on its own once any template syntax in it has been rewritten.
+pub(super) struct Note {
+   number: usize,
+   /// Its content, within the list item.
+   events: Range<usize>,
+   /// The link back to its reference, which is part of its content.
+   backref: usize,
+}
+
+/// The events for the list of notes, to append after the content starting at the index
+/// `start`, along with where each note is in them.
+fn notes(
+   definitions: Vec<Vec<Located<'_>>>,
+   start: usize,
+) -> (Vec<Located<'_>>, Vec<Note>) {
+   let mut events = vec![
+      Located::from(Event::Rule),
+      Located::from(Event::Html(
+         r#"<section class="footnotes"><ol class="footnotes-list">"#.into(),
+      )),
+   ];
+   let mut notes = Vec::with_capacity(definitions.len());
+
+   for (index, mut definition) in definitions
+      .into_iter()
+      .enumerate()
+      .map(|(index, definition)| (index + 1, definition))
+   {
+      events.push(Located::from(Event::Html(note_start(index).into())));
+      let content_start = start + events.len();
+
+      let backref = Located::from(Event::Html(
+         format!(
+            r##"<a href="#{backref}" class="fn-backref">↩</a>"##,
+            backref = footnote_backref_name(index)
+         )
+         .into(),
+      ));
+
+      let backref_index;
+      if let Some(Event::End(TagEnd::Paragraph)) = definition.last().map(|l| &l.event) {
+         let p = definition.pop().unwrap();
+         backref_index = start + events.len() + definition.len();
+         definition.push(backref);
+         definition.push(p);
+         events.append(&mut definition);
+      } else {
+         events.append(&mut definition);
+         backref_index = start + events.len();
+         events.push(backref);
+      }
+
+      notes.push(Note {
+         number: index,
+         events: content_start..start + events.len(),
+         backref: backref_index,
+      });
+      events.push(Located::from(Event::End(TagEnd::Item)));
+   }
+
+   events.push(Located::from(Event::Html("</ol></section>".into())));
+   (events, notes)
+}
+
+/// Render each of the `notes` from the events appended after the content.
+pub(super) fn render(appended: &[Event<'_>], notes: &[Note]) -> Vec<Footnote> {
+   notes
+      .iter()
+      .map(|note| {
+         let events = note
+            .events
+            .clone()
+            .filter(|&index| index != note.backref)
+            .map(|index| appended[index].clone());
+         let mut html = String::new();
+         html::push_html(&mut html, events);
+         Footnote {
+            number: note.number,
+            id: footnote_ref_name(note.number),
+            html,
+         }
+      })
+      .collect()
+}
+
+fn note_start(index: usize) -> String {
+   format!(r#"<li id="{}">"#, footnote_ref_name(index))
+}
+
+#[inline]
+fn footnote_ref_name(index: usize) -> String {
+   format!("fn{index}")
+}
+
+#[inline]
+fn footnote_backref_name(index: usize) -> String {
+   format!("fnref{index}")
+}
@@ -0,0 +1,382 @@
+//! Gemtext, the line-oriented format served over Gemini. It has headings (three levels),
+//! list items, quotes, preformatted blocks, and links on lines of their own, and nothing
+//! else: no inline formatting, no nesting, and no inline links.
+//!
+//! So headings, lists, and quotes map directly; links (and images) are collected and
+//! emitted after the paragraph, list item, or table containing them; code blocks and
+//! tables become preformatted blocks; footnotes and the bibliography become lists after
+//! the content; and any HTML (e.g. from citations or figures) is reduced to its text.
+
+use std::collections::HashMap;
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, Tag, TagEnd};
+
+use super::footnotes::extract_definitions;
+use super::languages;
+use super::second_pass::{self, is_excerpt_marker, is_template, rewrite_one, Rewritten};
+use super::text::strip_tags;
+use super::transform::{Located, Transform};
+use super::FootnoteDefinitions;
+
+/// Render the lowered events as gemtext. Only the given transforms run: the built-in
+/// highlighting, math, and footnote transforms produce HTML, so math is written as its
+/// TeX source, and code and footnotes are handled here.
+pub(super) fn render(
+   source: &str,
+   events: Vec<Located<'_>>,
+   transforms: &[&dyn Transform],
+   bibliography: Option<&str>,
+   rewritten: &Rewritten,
+   rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<String, second_pass::Error> {
+   let second_pass::Transformed {
+      mut events,
+      appendix,
+      ..
+   } = second_pass::run(source, events, transforms)?;
+   events.extend(appendix);
+   let events = languages::without_spans(events);
+   let (events, definitions) = extract_definitions(events);
+
+   let mut writer = Writer::new(source, rewritten, rewrite, &definitions);
+   for located in events {
+      writer.handle(located)?;
+   }
+   writer.end_block();
+
+   // References inside notes are left as written, like references to missing notes.
+   let none = HashMap::new();
+   let mut notes = Vec::new();
+   for name in std::mem::take(&mut writer.notes) {
+      let mut note = Writer::new(source, rewritten, rewrite, &none);
+      for located in definitions[&name].iter().cloned() {
+         note.handle(located)?;
+      }
+      note.end_block();
+      notes.push(note.output);
+   }
+
+   let mut output = writer.output;
+   for (index, note) in notes.iter().enumerate() {
+      let (links, text): (Vec<&str>, Vec<&str>) = note
+         .lines()
+         .filter(|line| !line.trim().is_empty())
+         .partition(|line| line.starts_with("=>"));
+      output.push_str(&format!("* [{}] {}\n", index + 1, text.join(" ")));
+      for link in links {
+         output.push_str(link);
+         output.push('\n');
+      }
+   }
+
+   if let Some(bibliography) = bibliography {
+      if !output.is_empty() && !output.ends_with("\n\n") {
+         output.push('\n');
+      }
+      for entry in bibliography.split("</li>").map(strip_tags) {
+         if !entry.trim().is_empty() {
+            output.push_str(&format!("* {}\n", entry.trim()));
+         }
+      }
+   }
+
+   Ok(output.trim_end().to_string() + "\n")
+}
+
+struct Writer<'s, 'r, 'd, 'e, R> {
+   source: &'s str,
+   /// The results of rewriting the text for HTML, so the templates in the content mean
+   /// the same thing here.
+   rewritten: &'r Rewritten,
+   rewrite: &'r R,
+   definitions: &'d FootnoteDefinitions<'e>,
+   output: String,
+   /// The line being built; written (with any quote marker) once it is finished.
+   line: String,
+   /// Links found in the current block, as `(url, label)`, to write after it.
+   links: Vec<(String, String)>,
+   /// The destinations of the links and images currently open, with where their label
+   /// starts in `line` and whether they are images (whose labels are not inline text).
+   open_links: Vec<(CowStr<'e>, usize, bool)>,
+   /// The next number for each open list, or `None` for unordered lists.
+   lists: Vec<Option<u64>>,
+   quotes: usize,
+   in_code_block: bool,
+   in_table: bool,
+   cells: usize,
+   /// Footnotes, by name, in the order they were first referenced.
+   notes: Vec<CowStr<'e>>,
+   defined: HashMap<CowStr<'e>, usize>,
+}
+
+impl<'s, 'r, 'd, 'e, R> Writer<'s, 'r, 'd, 'e, R>
+where
+   R: Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+{
+   fn new(
+      source: &'s str,
+      rewritten: &'r Rewritten,
+      rewrite: &'r R,
+      definitions: &'d FootnoteDefinitions<'e>,
+   ) -> Self {
+      Writer {
+         source,
+         rewritten,
+         rewrite,
+         definitions,
+         output: String::new(),
+         line: String::new(),
+         links: vec![],
+         open_links: vec![],
+         lists: vec![],
+         quotes: 0,
+         in_code_block: false,
+         in_table: false,
+         cells: 0,
+         notes: vec![],
+         defined: HashMap::new(),
+      }
+   }
+
+   fn handle(&mut self, located: Located<'e>) -> Result<(), second_pass::Error> {
+      if is_excerpt_marker(&located.event) {
+         return Ok(());
+      }
+
+      match located.event {
+         Event::Start(tag) => self.start(tag),
+         Event::End(tag) => self.end(tag),
+
+         Event::Text(text) if self.in_code_block => self.output.push_str(&text),
+         Event::Text(text) => {
+            // Rewritten text is HTML, with whatever was inserted escaped, so it goes
+            // back to plain text the same way the rest of the HTML does.
+            let text = if is_template(&text) {
+               let html = match self.rewritten.get(located.offset, &text) {
+                  Some(rewritten) => rewritten.to_string(),
+                  // Only text which a built-in transform changed for HTML, e.g. in a
+                  // verse block, was not rewritten there.
+                  None => rewrite_one(self.source, &text, located.offset, self.rewrite)?,
+               };
+               strip_tags(&html)
+            } else {
+               text.to_string()
+            };
+            self.push(&text);
+         }
+
+         Event::Code(code) => self.push(&format!("`{code}`")),
+         Event::InlineMath(tex) => self.push(&format!("${tex}$")),
+         Event::DisplayMath(tex) => self.push(&format!("$${tex}$$")),
+         Event::Html(html) | Event::InlineHtml(html) => self.push(&strip_tags(&html)),
+
+         Event::FootnoteReference(name) if !self.definitions.contains_key(&name) => {
+            self.push(&format!("[^{name}]"));
+         }
+
+         Event::FootnoteReference(name) => {
+            let count = self.notes.len();
+            let number = *self.defined.entry(name.clone()).or_insert(count + 1);
+            if number > count {
+               self.notes.push(name);
+            }
+            self.push(&format!("[{number}]"));
+         }
+
+         Event::SoftBreak => self.push(" "),
+         Event::HardBreak => self.flush_line(),
+         Event::Rule => self.end_block(),
+         Event::TaskListMarker(done) => self.push(if done { "[x] " } else { "[ ] " }),
+      }
+
+      Ok(())
+   }
+
+   fn start(&mut self, tag: Tag<'e>) {
+      match tag {
+         Tag::Paragraph if self.lists.is_empty() => self.end_block(),
+
+         Tag::Heading { level, .. } => {
+            self.end_block();
+            let depth = match level {
+               HeadingLevel::H1 => 1,
+               HeadingLevel::H2 => 2,
+               _ => 3,
+            };
+            self.line = "#".repeat(depth) + " ";
+         }
+
+         Tag::BlockQuote(_) => {
+            self.end_block();
+            self.quotes += 1;
+         }
+
+         Tag::CodeBlock(kind) => {
+            self.end_block();
+            self.in_code_block = true;
+            let language = match kind {
+               CodeBlockKind::Fenced(language) => language,
+               CodeBlockKind::Indented => CowStr::from(""),
+            };
+            self.output.push_str(&format!("```{language}\n"));
+         }
+
+         Tag::List(start) => {
+            if self.lists.is_empty() {
+               self.end_block();
+            } else {
+               self.flush_line();
+            }
+            self.lists.push(start);
+         }
+
+         Tag::Item => {
+            self.flush_line();
+            self.line = String::from("* ");
+            if let Some(Some(number)) = self.lists.last_mut() {
+               self.line.push_str(&format!("{number}. "));
+               *number += 1;
+            }
+         }
+
+         Tag::Table(_) => {
+            self.end_block();
+            self.in_table = true;
+            self.output.push_str("```\n");
+         }
+
+         Tag::TableHead | Tag::TableRow => self.cells = 0,
+
+         Tag::TableCell => {
+            if self.cells > 0 {
+               self.line.push_str(" | ");
+            }
+            self.cells += 1;
+         }
+
+         Tag::Link { dest_url, .. } => {
+            self.open_links.push((dest_url, self.line.len(), false));
+         }
+
+         Tag::Image { dest_url, .. } => {
+            self.open_links.push((dest_url, self.line.len(), true));
+         }
+
+         Tag::DefinitionListTitle | Tag::DefinitionListDefinition => self.flush_line(),
+
+         _ => {}
+      }
+   }
+
+   fn end(&mut self, tag: TagEnd) {
+      match tag {
+         TagEnd::Paragraph if self.lists.is_empty() => self.end_block(),
+         TagEnd::Paragraph => self.flush_line(),
+
+         TagEnd::Heading(_) => self.end_block(),
+
+         TagEnd::BlockQuote(_) => {
+            self.flush_line();
+            self.quotes = self.quotes.saturating_sub(1);
+            self.end_block();
+         }
+
+         TagEnd::CodeBlock => {
+            if !self.output.ends_with('\n') {
+               self.output.push('\n');
+            }
+            self.output.push_str("```\n");
+            self.in_code_block = false;
+            self.blank_line();
+         }
+
+         TagEnd::List(_) => {
+            self.lists.pop();
+            if self.lists.is_empty() {
+               self.end_block();
+            }
+         }
+
+         TagEnd::Item => {
+            self.flush_line();
+            self.flush_links();
+         }
+
+         TagEnd::TableHead | TagEnd::TableRow => self.flush_line(),
+
+         TagEnd::Table => {
+            self.flush_line();
+            self.output.push_str("```\n");
+            self.in_table = false;
+            self.end_block();
+         }
+
+         TagEnd::Link | TagEnd::Image => {
+            if let Some((url, start, is_image)) = self.open_links.pop() {
+               let label = self
+                  .line
+                  .get(start..)
+                  .unwrap_or_default()
+                  .trim()
+                  .to_string();
+               if is_image {
+                  self.line.truncate(start);
+               }
+               let label = if label.is_empty() {
+                  url.to_string()
+               } else {
+                  label
+               };
+               self.links.push((url.to_string(), label));
+            }
+         }
+
+         TagEnd::DefinitionListTitle | TagEnd::DefinitionListDefinition => {
+            self.flush_line();
+         }
+
+         TagEnd::DefinitionList => self.end_block(),
+
+         _ => {}
+      }
+   }
+
+   /// Add inline text to the current line. Gemtext lines cannot wrap, so any newlines
+   /// (e.g. from rewriting) become spaces.
+   fn push(&mut self, text: &str) {
+      self.line.push_str(&text.replace('\n', " "));
+   }
+
+   fn flush_line(&mut self) {
+      let line = std::mem::take(&mut self.line);
+      let line = line.trim();
+      if line.is_empty() || line == "*" {
+         return;
+      }
+
+      if self.quotes > 0 && !self.in_table {
+         self.output.push_str("> ");
+      }
+      self.output.push_str(line);
+      self.output.push('\n');
+   }
+
+   fn flush_links(&mut self) {
+      for (url, label) in std::mem::take(&mut self.links) {
+         self.output.push_str(&format!("=> {url} {label}\n"));
+      }
+   }
+
+   /// Finish the current block: its text, then its links, then a blank line.
+   fn end_block(&mut self) {
+      self.flush_line();
+      self.flush_links();
+      self.blank_line();
+   }
+
+   fn blank_line(&mut self) {
+      if !self.output.is_empty() && !self.output.ends_with("\n\n") {
+         self.output.push('\n');
+      }
+   }
+}
@@ -0,0 +1,221 @@
+//! Syntax highlighting for code blocks, with `syntect`.
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html_body_text;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use thiserror::Error;
+
+use super::location::{at, Location};
+use super::transform::{Context, Located, Transform};
+
+/// Replaces every code block with highlighted HTML: fenced code blocks by the syntax
+/// named for the fence, and indented code blocks by their first line (e.g. a shebang).
+pub(super) struct Highlight {
+   pub(super) syntax_set: SyntaxSet,
+}
+
+impl Transform for Highlight {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let mut highlighted = Vec::with_capacity(events.len());
+      let mut code_block = None;
+      for located in events {
+         match (located.event, &mut code_block) {
+            (Event::Start(Tag::CodeBlock(kind)), None) => {
+               code_block = Some(CodeBlock::start(kind, &self.syntax_set));
+            }
+
+            (Event::Text(text), Some(code_block)) => code_block
+               .highlight(&text)
+               .map_err(|source| Error::BadSyntaxLine {
+                  source,
+                  location: located.offset.map(|offset| context.location(offset)),
+               })?,
+
+            (Event::End(TagEnd::CodeBlock), code_block) => match code_block.take() {
+               Some(code_block) => {
+                  highlighted.extend(code_block.end().into_iter().map(Located::from))
+               }
+               None => {
+                  return Err(
+                     Error::FinishedNonStartedCodeBlock {
+                        location: located.offset.map(|offset| context.location(offset)),
+                     }
+                     .into(),
+                  )
+               }
+            },
+
+            (event, _) => highlighted.push(Located {
+               event,
+               offset: located.offset,
+            }),
+         }
+      }
+
+      Ok(highlighted)
+   }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+   #[error("cannot finish a code block we never started{}", at(.location))]
+   FinishedNonStartedCodeBlock { location: Option<Location> },
+
+   #[error("syntax highlighting failure{}", at(.location))]
+   BadSyntaxLine {
+      source: syntect::Error,
+      location: Option<Location>,
+   },
+}
+
+#[derive(Debug)]
+struct CodeBlock<'e, 's> {
+   highlighting: Highlighting<'s>,
+   syntax_set: Option<&'s SyntaxSet>,
+   events: Vec<Event<'e>>,
+}
+
+impl<'c, 's> CodeBlock<'c, 's> {
+   /// Start highlighting a code block.
+   fn start(kind: CodeBlockKind, syntax_set: &'s SyntaxSet) -> Self {
+      match kind {
+         CodeBlockKind::Fenced(name) => {
+            let found = syntax_set.find_syntax_by_token(name.as_ref());
+            let (html, highlighting) = if let Some(syntax) = found {
+               (
+                  Event::Html(format!("<pre><code class='{}'>", syntax.name).into()),
+                  Highlighting::KnownSyntax(ClassedHTMLGenerator::new_with_class_style(
+                     syntax,
+                     syntax_set,
+                     ClassStyle::Spaced,
+                  )),
+               )
+            } else {
+               (
+                  Event::Html("<pre><code>".into()),
+                  Highlighting::UnknownSyntax,
+               )
+            };
+
+            CodeBlock {
+               highlighting,
+               syntax_set: Some(syntax_set),
+               events: vec![html],
+            }
+         }
+         CodeBlockKind::Indented => CodeBlock {
+            highlighting: Highlighting::RequiresFirstLineParse,
+            syntax_set: Some(syntax_set),
+            events: vec![],
+         },
+      }
+   }
+
+   /// Produces events when:
+   ///
+   /// - starting a new code block
+   /// - ending a code block
+   ///
+   /// Note that it does *not* emit events while highlighting a line. Instead, it stores
+   /// internal state which produces a single fully-rendered HTML event when complete.
+   fn highlight(&mut self, text: &CowStr<'c>) -> Result<(), syntect::Error> {
+      let mut handle_unknown = || self.events.push(Event::Html(escape(text).into()));
+
+      let Some(syntax_set) = self.syntax_set else {
+         handle_unknown();
+         return Ok(());
+      };
+
+      match self.highlighting {
+         Highlighting::RequiresFirstLineParse => {
+            match syntax_set.find_syntax_by_first_line(text) {
+               // If Syntect has a definition, emit processed HTML for the wrapper
+               // and for the first line.
+               Some(definition) => {
+                  let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                     definition,
+                     syntax_set,
+                     ClassStyle::Spaced,
+                  );
+                  let event = Event::Html(
+                     format!(
+                        "<pre lang='{name}'><code class='{name}'>",
+                        name = definition.name
+                     )
+                     .into(),
+                  );
+                  generator.parse_html_for_line_which_includes_newline(text)?;
+                  self.highlighting = Highlighting::KnownSyntax(generator);
+                  self.events.push(event);
+                  Ok(())
+               }
+
+               // Otherwise, we treat this as a code block, but with no syntax
+               // highlighting applied.
+               None => {
+                  self.highlighting = Highlighting::UnknownSyntax;
+                  let event =
+                     Event::Html((String::from("<pre><code>") + &escape(text)).into());
+                  self.events.push(event);
+                  Ok(())
+               }
+            }
+         }
+
+         // This is a little quirky: it hands off the text to the highlighter and
+         // relies on correctly calling `highlighter.finalize()` when we reach the
+         // end of the code block.
+         // TODO: consider type-state-ifying that, too!
+         Highlighting::KnownSyntax(ref mut generator) => {
+            generator.parse_html_for_line_which_includes_newline(text.as_ref())?;
+
+            // ...and therefore produces no events!
+            Ok(())
+         }
+
+         Highlighting::UnknownSyntax => {
+            handle_unknown();
+            Ok(())
+         }
+      }
+   }
+
+   /// Finish a code block, consuming the state and producing a single `Event::Html`
+   /// as its result.
+   fn end(mut self) -> Vec<Event<'c>> {
+      let end_html = match self.highlighting {
+         Highlighting::KnownSyntax(generator) => generator.finalize() + "</code></pre>",
+         _ => "</code></pre>".to_string(),
+      };
+      let end_event = Event::Html(end_html.into());
+      self.events.push(end_event);
+      self.events
+   }
+}
+
+enum Highlighting<'s> {
+   RequiresFirstLineParse,
+   UnknownSyntax,
+   KnownSyntax(ClassedHTMLGenerator<'s>),
+}
+
+impl std::fmt::Debug for Highlighting<'_> {
+   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      match self {
+         Self::RequiresFirstLineParse => write!(f, "RequiresFirstLineParse"),
+         Self::UnknownSyntax => write!(f, "UnknownSyntax"),
+         Self::KnownSyntax(_) => write!(f, "KnownSyntax"),
+      }
+   }
+}
+
+fn escape(text: &str) -> String {
+   let mut escaped = String::with_capacity(text.len());
+   escape_html_body_text(&mut escaped, text).expect("writing to a String cannot fail");
+   escaped
+}
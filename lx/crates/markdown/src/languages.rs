@@ -0,0 +1,349 @@
+//! Language tagging: runs of text in non-Latin scripts (e.g. Greek or Hebrew) are wrapped
+//! in `<span lang="…">`, with `dir="rtl"` for right-to-left scripts, so that browsers
+//! pick the right fonts and shaping and screen readers the right pronunciation.
+//!
+//! Which scripts are tagged, and with which language, is configurable; by default Greek
+//! is tagged as Ancient Greek (`grc`) and Hebrew as Hebrew (`he`). A run includes any
+//! spaces and punctuation *between* words in its script, but not around them.
+//!
+//! Text can also be tagged explicitly, with an attribute span:
+//!
+//! ```markdown
+//! The [Deus absconditus]{lang=la} of Luther's theology
+//! ```
+//!
+//! which also accepts `dir=rtl` (or `dir=ltr`). Nothing inside an explicit span is
+//! tagged automatically, nor is anything in code or in any text containing template
+//! syntax (`{{`, `{%`, or `{#`), since splitting that text would break the template.
+
+use std::collections::BTreeMap;
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+use serde::{Deserialize, Serialize};
+
+use super::second_pass::is_template;
+use super::transform::{Context, Located, Transform};
+
+/// The language to tag each script with, e.g. `greek: el` for Modern Greek. Scripts which
+/// are not listed are not tagged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Languages(pub BTreeMap<Script, String>);
+
+impl Default for Languages {
+   fn default() -> Self {
+      Languages(BTreeMap::from([
+         (Script::Greek, String::from("grc")),
+         (Script::Hebrew, String::from("he")),
+      ]))
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Script {
+   Arabic,
+   Armenian,
+   Coptic,
+   Cyrillic,
+   Georgian,
+   Greek,
+   Hebrew,
+   Syriac,
+}
+
+impl Script {
+   fn of(c: char) -> Option<Script> {
+      match c {
+         '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Some(Script::Greek),
+         '\u{2C80}'..='\u{2CFF}' => Some(Script::Coptic),
+         '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+         '\u{0530}'..='\u{058F}' | '\u{FB13}'..='\u{FB17}' => Some(Script::Armenian),
+         '\u{0590}'..='\u{05FF}' | '\u{FB1D}'..='\u{FB4F}' => Some(Script::Hebrew),
+         '\u{0600}'..='\u{06FF}'
+         | '\u{0750}'..='\u{077F}'
+         | '\u{08A0}'..='\u{08FF}'
+         | '\u{FB50}'..='\u{FDFF}'
+         | '\u{FE70}'..='\u{FEFF}' => Some(Script::Arabic),
+         '\u{0700}'..='\u{074F}' => Some(Script::Syriac),
+         '\u{10A0}'..='\u{10FF}' => Some(Script::Georgian),
+         _ => None,
+      }
+   }
+
+   fn is_rtl(self) -> bool {
+      matches!(self, Script::Arabic | Script::Hebrew | Script::Syriac)
+   }
+}
+
+impl Transform for Languages {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let mut tagged = Vec::with_capacity(events.len());
+      let mut in_code = false;
+      let mut explicit = 0usize;
+      for piece in explicit_spans(events) {
+         let located = match piece {
+            Piece::Open(html, offset) => {
+               explicit += 1;
+               tagged.push(Located {
+                  event: Event::InlineHtml(html.into()),
+                  offset,
+               });
+               continue;
+            }
+            Piece::Close(offset) => {
+               explicit = explicit.saturating_sub(1);
+               tagged.push(Located {
+                  event: Event::InlineHtml("</span>".into()),
+                  offset,
+               });
+               continue;
+            }
+            Piece::Event(located) => located,
+         };
+
+         let offset = located.offset;
+         match located.event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            Event::Text(ref text) if !in_code && explicit == 0 && !is_template(text) => {
+               tagged.extend(
+                  self
+                     .tag(text)
+                     .into_iter()
+                     .map(|event| Located { event, offset }),
+               );
+               continue;
+            }
+            _ => {}
+         }
+         tagged.push(located);
+      }
+
+      Ok(tagged)
+   }
+}
+
+impl Languages {
+   /// The events for `text`, with each run in a configured script wrapped in a span.
+   fn tag(&self, text: &str) -> Vec<Event<'static>> {
+      let mut events = Vec::new();
+      let mut rest_start = 0;
+      let mut index = 0;
+      while let Some(c) = text[index..].chars().next() {
+         let configured = Script::of(c)
+            .filter(|_| c.is_alphabetic())
+            .and_then(|script| self.0.get(&script).map(|lang| (script, lang)));
+         let Some((script, lang)) = configured else {
+            index += c.len_utf8();
+            continue;
+         };
+
+         let end = run_end(text, index, script);
+         if index > rest_start {
+            events.push(Event::Text(text[rest_start..index].to_string().into()));
+         }
+         events.push(Event::InlineHtml(
+            open(lang, script.is_rtl().then_some("rtl")).into(),
+         ));
+         events.push(Event::Text(text[index..end].to_string().into()));
+         events.push(Event::InlineHtml("</span>".into()));
+         index = end;
+         rest_start = end;
+      }
+
+      if rest_start < text.len() {
+         events.push(Event::Text(text[rest_start..].to_string().into()));
+      }
+
+      events
+   }
+}
+
+/// The end of a run in `script` which starts at `start`: it continues over its own
+/// script (and combining marks), and over anything else which is neither a letter nor a
+/// digit as long as the script resumes afterward.
+fn run_end(text: &str, start: usize, script: Script) -> usize {
+   let mut end = start;
+   for (index, c) in text[start..].char_indices() {
+      if Script::of(c) == Some(script) || is_combining(c) {
+         end = start + index + c.len_utf8();
+      } else if c.is_alphanumeric() {
+         break;
+      }
+   }
+   end
+}
+
+fn is_combining(c: char) -> bool {
+   matches!(c, '\u{0300}'..='\u{036F}' | '\u{1DC0}'..='\u{1DFF}' | '\u{20D0}'..='\u{20FF}')
+}
+
+fn open(lang: &str, dir: Option<&str>) -> String {
+   let mut html = String::from(r#"<span lang=""#);
+   escape_html(&mut html, lang).expect("writing to a String cannot fail");
+   html.push('"');
+   if let Some(dir) = dir {
+      html.push_str(&format!(r#" dir="{dir}""#));
+   }
+   html.push('>');
+   html
+}
+
+/// The events with every explicit language span reduced to its content, for output
+/// which cannot tag languages.
+pub(super) fn without_spans(events: Vec<Located<'_>>) -> Vec<Located<'_>> {
+   explicit_spans(events)
+      .into_iter()
+      .filter_map(|piece| match piece {
+         Piece::Event(located) => Some(located),
+         Piece::Open(..) | Piece::Close(_) => None,
+      })
+      .collect()
+}
+
+/// An event, or where an explicit language span opens or closes.
+enum Piece<'e> {
+   Event(Located<'e>),
+   Open(String, Option<usize>),
+   Close(Option<usize>),
+}
+
+/// Find every `[text]{lang=…}` span, which may contain other inline events but may not
+/// cross the boundaries of a block.
+fn explicit_spans(events: Vec<Located<'_>>) -> Vec<Piece<'_>> {
+   let mut pieces = Vec::with_capacity(events.len());
+   // The index in `pieces` of each `[` which might open a span.
+   let mut brackets = Vec::<usize>::new();
+   let mut in_code = false;
+   for located in merge_text(events) {
+      let offset = located.offset;
+      let text = match located.event {
+         Event::Text(text) if !in_code && !is_template(&text) => text,
+         event => {
+            match &event {
+               Event::Start(Tag::CodeBlock(_)) => in_code = true,
+               Event::End(TagEnd::CodeBlock) => in_code = false,
+               Event::Start(tag) if !is_inline(tag) => brackets.clear(),
+               Event::End(tag) if !is_inline_end(tag) => brackets.clear(),
+               _ => {}
+            }
+            pieces.push(Piece::Event(Located { event, offset }));
+            continue;
+         }
+      };
+
+      let text_piece = |s: &str| {
+         Piece::Event(Located {
+            event: Event::Text(s.to_string().into()),
+            offset,
+         })
+      };
+
+      let mut rest_start = 0;
+      let mut index = 0;
+      while index < text.len() {
+         let rest = &text[index..];
+         if rest.starts_with('[') {
+            if index > rest_start {
+               pieces.push(text_piece(&text[rest_start..index]));
+            }
+            brackets.push(pieces.len());
+            pieces.push(text_piece("["));
+            index += 1;
+            rest_start = index;
+            continue;
+         }
+
+         if rest.starts_with("]{") && !brackets.is_empty() {
+            if let Some((html, length)) = attributes(&rest[1..]) {
+               if index > rest_start {
+                  pieces.push(text_piece(&text[rest_start..index]));
+               }
+               let bracket = brackets.pop().expect("checked above");
+               pieces[bracket] = Piece::Open(html, offset);
+               pieces.push(Piece::Close(offset));
+               index += 1 + length;
+               rest_start = index;
+               continue;
+            }
+         }
+
+         index += rest.chars().next().map_or(1, char::len_utf8);
+      }
+
+      if rest_start < text.len() {
+         pieces.push(text_piece(&text[rest_start..]));
+      }
+   }
+
+   pieces
+}
+
+/// Join adjacent text events, which `pulldown_cmark` splits at brackets, so that a span
+/// can be found within a single one.
+fn merge_text(events: Vec<Located<'_>>) -> Vec<Located<'_>> {
+   let mut merged: Vec<Located> = Vec::with_capacity(events.len());
+   for located in events {
+      match (merged.last_mut(), located.event) {
+         (
+            Some(Located {
+               event: Event::Text(previous),
+               ..
+            }),
+            Event::Text(text),
+         ) => *previous = format!("{previous}{text}").into(),
+         (_, event) => merged.push(Located {
+            event,
+            offset: located.offset,
+         }),
+      }
+   }
+   merged
+}
+
+/// Parse `{lang=… dir=…}` at the start of `text`, returning the opening tag for the span
+/// and the length of the attributes.
+fn attributes(text: &str) -> Option<(String, usize)> {
+   let length = text.find('}')? + 1;
+   let mut lang = None;
+   let mut dir = None;
+   for attribute in text[1..length - 1].split_whitespace() {
+      let (key, value) = attribute.split_once('=')?;
+      let value = value.trim_matches(|c| c == '"' || c == '\'');
+      match key {
+         "lang" if !value.is_empty() => lang = Some(value),
+         "dir" if value == "rtl" || value == "ltr" => dir = Some(value),
+         _ => return None,
+      }
+   }
+
+   Some((open(lang?, dir), length))
+}
+
+fn is_inline(tag: &Tag) -> bool {
+   matches!(
+      tag,
+      Tag::Emphasis
+         | Tag::Strong
+         | Tag::Strikethrough
+         | Tag::Link { .. }
+         | Tag::Image { .. }
+   )
+}
+
+fn is_inline_end(tag: &TagEnd) -> bool {
+   matches!(
+      tag,
+      TagEnd::Emphasis
+         | TagEnd::Strong
+         | TagEnd::Strikethrough
+         | TagEnd::Link
+         | TagEnd::Image
+   )
+}
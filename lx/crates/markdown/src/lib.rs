@@ -3,48 +3,132 @@
 //! 1. Handle two concerns:
 //!     - metadata extraction (exposed to callers)
 //!     - footnote extraction (managed wholly internally)
+//!     - internal link extraction (resolved by callers; see [`ToRender::resolve_links`]
+//!       and [`ToRender::resolve_relative_links`])
+//!     - citation extraction (formatted by callers; see [`ToRender::cite`])
+//!     - scripture references (linked by callers; see [`ToRender::link_scripture`])
+//!     - numbering figures, tables, equations, and sections, and resolving
+//!       cross-references to them
+//!     - shortcodes wrapping block content (expanded by callers; see
+//!       [`Markdown::emit_with_shortcodes`])
 //! 2. Perform "transform" operations using the result of (1):
+//!     - Run each [`Transform`]: first any supplied by the caller (see
+//!       [`Markdown::with_transform`]), then the built-in ones, which
+//!         - lay out `verse` blocks as poetry,
+//!         - draw `svgbob` blocks of ASCII art as SVG diagrams,
+//!         - apply syntax highlighting,
+//!         - convert math to MathML, after expanding any LaTeX macros,
+//!         - tag text in other scripts with its language (see [`Languages`]),
+//!         - mark glossary terms and abbreviations (see [`Glossary`]),
+//!         - mark links to other sites (see [`ExternalLinks`]), and
+//!         - emit footnotes, which are followed by the bibliography for any citations.
 //!     - Rewrite the text of the document using a supplied templating language,
-//!       if any (notably: applying this *only* to text nodes!).
-//!     - Apply syntax highlighting.
-//!     - Emit footnotes.
+//!       if any (notably: applying this *only* to text nodes, and only those with
+//!       template syntax in them, which are all rewritten at once!).
+//!     - Expand shortcodes, from the innermost out, with their rendered content.
+//!     - Split out an excerpt, using either an explicit marker or the first few
+//!       paragraphs of the content.
+//!     - Optionally, also write the content as gemtext (see [`Markdown::with_gemtext`]),
+//!       running only the caller's transforms.
 
+mod citations;
+mod crossrefs;
+mod diagrams;
 mod first_pass;
+mod footnotes;
+mod gemtext;
+mod highlight;
+mod languages;
+mod links;
+mod location;
+mod math;
+mod outline;
+mod scripture;
 mod second_pass;
+mod shortcodes;
+mod terms;
+mod text;
+mod transform;
+mod urls;
+mod verse;
 
 use std::collections::HashMap;
 use std::fmt::Debug;
 
 use lazy_static::lazy_static;
+use log::warn;
 pub use pulldown_cmark::Options;
 use pulldown_cmark::{html, CowStr, Event, MetadataBlockKind, Parser, Tag, TagEnd};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use syntect::parsing::SyntaxSet;
 use thiserror::Error;
 
+use diagrams::Diagrams;
 use first_pass::FirstPass;
-use second_pass::second_pass;
+use footnotes::Footnotes;
+use highlight::Highlight;
+use math::MathML;
+use second_pass::{second_pass, Output};
+use urls::MarkExternal;
+use verse::Verse;
+
+pub use citations::{
+   Bibliography, BibliographyError, Style, StyleError, UnknownCitation, UnknownCitations,
+};
+pub use crossrefs::{CrossReferenceError, CrossReferenceErrors};
+pub use footnotes::Footnote;
+pub use highlight::Error as HighlightError;
+pub use languages::{Languages, Script};
+pub use links::{has_scheme, Resolved, UnresolvedLink, UnresolvedLinks};
+pub use location::Location;
+pub use math::{BadMath, Macros, Math, MathError};
+pub use outline::Heading;
+pub use scripture::{Passage, Scripture, Syntax as ScriptureSyntax, UnknownBook};
+pub use shortcodes::{ExpandError, Shortcode, ShortcodeError};
+pub use terms::Glossary;
+pub use text::strip_tags;
+pub use transform::{Context, Located, Transform};
+pub use urls::{ExternalLinks, InvalidUrl};
 
 /// A footnote definition can have any arbitrary sequence of `pulldown_cmark::Event`s
 /// in it, excepting other footnotes definitions. However, that scenario *should* be
 /// forbidden by both `pulldown_cmark` itself *and* the event handling.
-type FootnoteDefinitions<'e> = HashMap<CowStr<'e>, Vec<Event<'e>>>;
+type FootnoteDefinitions<'e> = HashMap<CowStr<'e>, Vec<Located<'e>>>;
 
 #[derive(Error, Debug)]
 pub enum PrepareError {
-   #[error("tried to use TOML for metadata")]
-   UsedToml,
+   #[error("tried to use TOML for metadata at {location}")]
+   UsedToml { location: Location },
 
    #[error("failed to extract metadata section")]
    MetadataExtraction,
 
-   #[error("could not prepare Markdown: {state} is invalid in {context}")]
-   State { state: String, context: String },
+   #[error("could not prepare Markdown: unexpected {event} while {state} at {location}")]
+   State {
+      event: String,
+      state: String,
+      location: Location,
+   },
 
-   #[error("could not prepare Markdown content section")]
-   Content {
+   #[error("inline Markdown cannot contain block content ({kind}) at {location}")]
+   BlockContent { kind: String, location: Location },
+
+   #[error(transparent)]
+   CrossReferences {
+      #[from]
+      source: CrossReferenceErrors,
+   },
+
+   #[error(transparent)]
+   Shortcodes {
       #[from]
+      source: ShortcodeError,
+   },
+
+   #[error("could not prepare Markdown content section at {location}")]
+   Content {
       source: first_pass::Error,
+      location: Location,
    },
 }
 
@@ -58,8 +142,95 @@ pub struct Prepared<'e> {
 }
 
 pub struct ToRender<'e> {
+   source: &'e str,
    first_pass_events: Vec<first_pass::Event<'e>>,
-   footnote_definitions: FootnoteDefinitions<'e>,
+   footnote_definitions: first_pass::Definitions<'e>,
+   /// The HTML for the bibliography, once citations have been formatted.
+   bibliography: Option<String>,
+   /// Math macros for this content alone, in addition to the site-wide ones.
+   macros: Macros,
+   shortcodes: Vec<Shortcode>,
+}
+
+impl ToRender<'_> {
+   /// Resolve every internal link (`[[target]]`, `[[target|label]]`, and `lx:target`)
+   /// using the supplied function, which knows about every other page. Links which are
+   /// never resolved are rendered as-is, with a warning.
+   ///
+   /// # Errors
+   ///
+   /// Returns *all* the targets which could not be resolved, with their locations in
+   /// the original source.
+   pub fn resolve_links(
+      &mut self,
+      resolve: impl Fn(&str) -> Option<Resolved>,
+   ) -> Result<(), UnresolvedLinks> {
+      links::resolve(
+         &mut self.first_pass_events,
+         &mut self.footnote_definitions,
+         self.source,
+         resolve,
+      )
+   }
+
+   /// Resolve every relative link to another Markdown source file (e.g.
+   /// `[intro](../intro.md#history)`) using the supplied function, which gets the path as
+   /// written and knows where the file doing the linking is.
+   ///
+   /// # Errors
+   ///
+   /// Returns *all* the paths which could not be resolved, with their locations in the
+   /// original source.
+   pub fn resolve_relative_links(
+      &mut self,
+      resolve: impl Fn(&str) -> Option<Resolved>,
+   ) -> Result<(), UnresolvedLinks> {
+      links::resolve_relative(
+         &mut self.first_pass_events,
+         &mut self.footnote_definitions,
+         self.source,
+         resolve,
+      )
+   }
+
+   /// Format every citation (`[@key]`, `[@key, p. 12]`, etc.) with the given
+   /// bibliography and CSL style, and generate the bibliography which follows the
+   /// footnotes. Citations which are never formatted are rendered as-is, with a warning.
+   ///
+   /// # Errors
+   ///
+   /// Returns *all* the citations of works missing from the bibliography, with their
+   /// locations in the original source.
+   pub fn cite(
+      &mut self,
+      bibliography: &Bibliography,
+      style: &Style,
+   ) -> Result<(), UnknownCitations> {
+      self.bibliography = citations::format(
+         &mut self.first_pass_events,
+         &mut self.footnote_definitions,
+         self.source,
+         bibliography,
+         style,
+      )?;
+      Ok(())
+   }
+
+   /// Link every scripture reference in the content (e.g. `John 1:1`) to read it, as
+   /// configured, returning each distinct passage in the order it first appears.
+   pub fn link_scripture(&mut self, scripture: &Scripture) -> Vec<Passage> {
+      scripture::link(
+         &mut self.first_pass_events,
+         &mut self.footnote_definitions,
+         scripture,
+      )
+   }
+
+   /// Define LaTeX macros for this content, which take precedence over any with the same
+   /// name supplied via [`Markdown::with_math`].
+   pub fn define_macros(&mut self, macros: &Macros) {
+      self.macros = self.macros.extended_by(macros);
+   }
 }
 
 #[derive(Error, Debug)]
@@ -85,23 +256,133 @@ lazy_static! {
    };
 }
 
+/// How to find the excerpt for a document: everything before `marker` if it is present,
+/// or otherwise the first `paragraphs` top-level paragraphs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Excerpt {
+   pub marker: String,
+   pub paragraphs: usize,
+}
+
+impl Excerpt {
+   /// Whitespace inside the marker is ignored, so `<!--more-->` and `<!-- more -->` are
+   /// treated identically.
+   fn is_marker(&self, html: &str) -> bool {
+      let without_whitespace =
+         |s: &str| -> String { s.chars().filter(|c| !c.is_whitespace()).collect() };
+
+      without_whitespace(html) == without_whitespace(&self.marker)
+   }
+}
+
+impl Default for Excerpt {
+   fn default() -> Self {
+      Excerpt {
+         marker: String::from("<!-- more -->"),
+         paragraphs: 1,
+      }
+   }
+}
+
 pub struct Markdown {
-   syntax_set: SyntaxSet,
+   highlight: Highlight,
+   excerpt: Excerpt,
+   math: Math,
+   math_fallback: bool,
+   gemtext: bool,
+   glossary: Option<Glossary>,
+   languages: Languages,
+   site_url: Option<String>,
+   external_links: ExternalLinks,
+   transforms: Vec<Box<dyn Transform>>,
 }
 
 impl Markdown {
    pub fn new(syntax_set: Option<SyntaxSet>) -> Markdown {
       Markdown {
-         syntax_set: syntax_set.unwrap_or_else(load_syntaxes), // TODO: pull from location?
+         highlight: Highlight {
+            syntax_set: syntax_set.unwrap_or_else(load_syntaxes), // TODO: pull from location?
+         },
+         excerpt: Excerpt::default(),
+         math: Math::default(),
+         math_fallback: false,
+         gemtext: false,
+         glossary: None,
+         languages: Languages::default(),
+         site_url: None,
+         external_links: ExternalLinks::default(),
+         transforms: vec![],
+      }
+   }
+
+   pub fn with_excerpt(self, excerpt: Excerpt) -> Markdown {
+      Markdown { excerpt, ..self }
+   }
+
+   pub fn with_math(self, math: Math) -> Markdown {
+      Markdown { math, ..self }
+   }
+
+   /// Render math which fails to convert as highlighted fallback markup, with a warning,
+   /// instead of failing. Useful while developing; not something to publish.
+   pub fn with_math_fallback(self, math_fallback: bool) -> Markdown {
+      Markdown {
+         math_fallback,
+         ..self
+      }
+   }
+
+   /// Also render content as gemtext, for serving over Gemini. See
+   /// [`Rendered::gemtext`].
+   pub fn with_gemtext(self, gemtext: bool) -> Markdown {
+      Markdown { gemtext, ..self }
+   }
+
+   /// Mark the first use of each glossary term in every document, linking it to its
+   /// definition. See [`Glossary`].
+   pub fn with_glossary(self, glossary: Glossary) -> Markdown {
+      Markdown {
+         glossary: Some(glossary),
+         ..self
       }
    }
 
+   /// Which scripts to tag with which language; see [`Languages`]. By default, Greek and
+   /// Hebrew are tagged.
+   pub fn with_languages(self, languages: Languages) -> Markdown {
+      Markdown { languages, ..self }
+   }
+
+   /// The site's own URL, so that links to it are not treated as external.
+   pub fn with_site_url(self, site_url: impl Into<String>) -> Markdown {
+      Markdown {
+         site_url: Some(site_url.into()),
+         ..self
+      }
+   }
+
+   /// Add attributes to every link to another site. See [`ExternalLinks`].
+   pub fn with_external_links(self, external_links: ExternalLinks) -> Markdown {
+      Markdown {
+         external_links,
+         ..self
+      }
+   }
+
+   /// Add a transform to run over every document, after any already added but before
+   /// the built-in syntax highlighting, math, and footnotes. See [`Transform`].
+   pub fn with_transform(mut self, transform: impl Transform + 'static) -> Markdown {
+      self.transforms.push(Box::new(transform));
+      self
+   }
+
    pub fn render(
       &self,
       src: &str,
       rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
    ) -> Result<(Option<String>, Rendered), Error> {
-      let prepared = prepare(src)?;
+      let prepared = self.prepare(src)?;
       let rendered = self.emit(prepared.to_render, rewrite)?;
 
       // TODO: return named types instead of anonymous tuple values. Maybe just attach the
@@ -109,52 +390,172 @@ impl Markdown {
       Ok((prepared.metadata_src, rendered))
    }
 
+   /// Render a single line of Markdown as *inline* content: no wrapping `<p>`, and no
+   /// block-level elements at all. This is the right tool for fields like subtitles,
+   /// which end up inside `<h2>` or `<title>` where block content is invalid HTML.
+   pub fn render_inline(
+      &self,
+      src: &str,
+      rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+   ) -> Result<Rendered, Error> {
+      let to_render = prepare_inline(src)?;
+      self.emit(to_render, rewrite).map_err(Error::from)
+   }
+
+   /// Render prepared content, leaving the content of any shortcodes as it is, with a
+   /// warning. See [`Markdown::emit_with_shortcodes`] to expand them.
    pub fn emit(
       &self,
       to_render: ToRender,
       rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+   ) -> Result<Rendered, RenderError> {
+      self.emit_with_shortcodes(to_render, rewrite, |shortcode, content| {
+         warn!("Unexpanded shortcode '{}'", shortcode.name);
+         Ok(content.to_string())
+      })
+   }
+
+   /// Render prepared content, replacing each shortcode with the result of `expand`,
+   /// which gets the shortcode and the HTML for its content.
+   pub fn emit_with_shortcodes(
+      &self,
+      to_render: ToRender,
+      rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+      expand: impl Fn(
+         &Shortcode,
+         &str,
+      ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
    ) -> Result<Rendered, RenderError> {
       let ToRender {
+         source,
          first_pass_events,
          footnote_definitions,
+         bibliography,
+         macros,
+         shortcodes,
       } = to_render;
 
-      let events = second_pass(
-         footnote_definitions,
-         &self.syntax_set,
-         first_pass_events,
-         rewrite,
-      )
-      .map_err(RenderError::from)?;
+      let math = MathML {
+         macros: self.math.macros.extended_by(&macros),
+         fallback: self.math_fallback,
+      };
+
+      let user_transforms = self
+         .transforms
+         .iter()
+         .map(|transform| transform.as_ref())
+         .collect::<Vec<_>>();
+
+      let events = second_pass::lower(first_pass_events, footnote_definitions);
+      let (events, abbreviations) = terms::extract_abbreviations(events);
+      let glossary = self.glossary.as_ref().map(|g| g as &dyn Transform);
+      let external = MarkExternal {
+         attributes: &self.external_links,
+         site_host: self
+            .site_url
+            .as_deref()
+            .and_then(|url| urls::parse(url).ok())
+            .and_then(|url| url.host_str().map(String::from)),
+      };
+
+      let transforms = user_transforms
+         .iter()
+         .copied()
+         .chain([
+            &Verse as &dyn Transform,
+            &Diagrams,
+            &self.highlight,
+            &math,
+            &self.languages,
+         ])
+         .chain(glossary)
+         .chain([&abbreviations as &dyn Transform, &external, &Footnotes])
+         .collect::<Vec<_>>();
+
+      let gemtext_events = self.gemtext.then(|| events.clone());
+
+      let Output {
+         content,
+         excerpt,
+         toc,
+         footnotes,
+         rewritten,
+      } = second_pass(
+         source,
+         events,
+         &transforms,
+         self.excerpt.paragraphs,
+         &rewrite,
+         &shortcodes,
+         &expand,
+      )?;
+
+      let gemtext = gemtext_events
+         .map(|events| {
+            gemtext::render(
+               source,
+               events,
+               &user_transforms,
+               bibliography.as_deref(),
+               &rewritten,
+               &rewrite,
+            )
+         })
+         .transpose()?;
+
+      let mut html = String::new();
+      html::push_html(&mut html, content);
+      if let Some(bibliography) = bibliography {
+         html.push_str(&bibliography);
+      }
 
-      let mut content = String::new();
-      html::push_html(&mut content, events);
+      let mut excerpt_html = String::new();
+      html::push_html(&mut excerpt_html, excerpt.into_iter());
 
-      Ok(Rendered(content))
+      Ok(Rendered {
+         html,
+         excerpt: excerpt_html,
+         gemtext,
+         toc,
+         footnotes,
+      })
+   }
+
+   pub fn prepare<'src>(&self, src: &'src str) -> Result<Prepared<'src>, Error> {
+      prepare(src, &self.excerpt)
    }
 }
 
-pub fn prepare(src: &str) -> Result<Prepared<'_>, Error> {
+fn prepare<'src>(src: &'src str, excerpt: &Excerpt) -> Result<Prepared<'src>, Error> {
    let parser = Parser::new_ext(src, *OPTIONS);
 
    let mut state = first_pass::FirstPass::new();
 
    // TODO: rewrite all these `bad_prepare_state` calls into actual specific errors from
    // the enum above!
-   for event in parser {
+   for (event, range) in parser.into_offset_iter() {
       match event {
+         Event::Html(ref html) | Event::InlineHtml(ref html)
+            if excerpt.is_marker(html) =>
+         {
+            match state {
+               FirstPass::Content(ref mut content) => content.mark_excerpt(),
+               _ => return bad_prepare_state(&event, &state, src, range.start),
+            }
+         }
+
          Event::Start(Tag::MetadataBlock(kind)) => match state {
             FirstPass::Initial(initial) => {
                state = FirstPass::ExtractingMetadata(initial.parsing_metadata(kind))
             }
-            _ => return bad_prepare_state(&event, &state),
+            _ => return bad_prepare_state(&event, &state, src, range.start),
          },
 
          Event::End(TagEnd::MetadataBlock(_)) => match state {
             FirstPass::ExtractedMetadata(metadata) => {
                state = FirstPass::Content(metadata.start_content())
             }
-            _ => return bad_prepare_state(&event, &state),
+            _ => return bad_prepare_state(&event, &state, src, range.start),
          },
 
          Event::Text(ref text) => match state {
@@ -168,45 +569,123 @@ pub fn prepare(src: &str) -> Result<Prepared<'_>, Error> {
                }
 
                MetadataBlockKind::PlusesStyle => {
-                  return Err(Error::from(PrepareError::UsedToml))
+                  return Err(Error::from(PrepareError::UsedToml {
+                     location: Location::in_source(src, range.start),
+                  }))
                }
             },
 
-            FirstPass::Content(ref mut content) => {
-               content.handle(event).map_err(PrepareError::from)?
-            }
+            FirstPass::Content(ref mut content) => content
+               .handle(event, range.start)
+               .map_err(|source| content_error(source, src, range.start))?,
 
-            _ => return bad_prepare_state(&event, &state),
+            _ => return bad_prepare_state(&event, &state, src, range.start),
          },
 
          other => match state {
             FirstPass::Initial(initial) => {
                let mut content = initial.start_content();
-               content.handle(other).map_err(PrepareError::from)?;
+               content
+                  .handle(other, range.start)
+                  .map_err(|source| content_error(source, src, range.start))?;
                state = FirstPass::Content(content);
             }
 
-            FirstPass::Content(ref mut content) => {
-               content.handle(other).map_err(PrepareError::from)?
-            }
+            FirstPass::Content(ref mut content) => content
+               .handle(other, range.start)
+               .map_err(|source| content_error(source, src, range.start))?,
 
-            _ => return bad_prepare_state(&other, &state),
+            _ => return bad_prepare_state(&other, &state, src, range.start),
          },
       }
    }
 
-   let (metadata, first_pass_events, footnote_definitions) =
-      state.finalize().map_err(PrepareError::from)?;
+   let (metadata, mut first_pass_events, mut footnote_definitions) = state
+      .finalize()
+      .map_err(|source| content_error(source, src, src.len()))?;
+
+   crossrefs::number(&mut first_pass_events, &mut footnote_definitions, src)
+      .map_err(PrepareError::from)?;
+   let shortcodes =
+      shortcodes::find(&mut first_pass_events, src).map_err(PrepareError::from)?;
 
    Ok(Prepared {
       metadata_src: metadata.map(|m| m.to_string()),
       to_render: ToRender {
+         source: src,
          first_pass_events,
          footnote_definitions,
+         bibliography: None,
+         macros: Macros::default(),
+         shortcodes,
       },
    })
 }
 
+/// Prepare Markdown which must be rendered inline. A single top-level paragraph is
+/// unwrapped; any other block-level content is an error rather than being silently
+/// rendered, since the caller has said it cannot be valid where it will be used.
+fn prepare_inline(src: &str) -> Result<ToRender<'_>, Error> {
+   let parser = Parser::new_ext(src, *OPTIONS);
+
+   let mut content = first_pass::State::new().start_content();
+   let mut seen_paragraph = false;
+
+   for (event, range) in parser.into_offset_iter() {
+      match event {
+         Event::Start(Tag::Paragraph) if !seen_paragraph => seen_paragraph = true,
+
+         // There can only ever be one paragraph to end: a second one is an error.
+         Event::End(TagEnd::Paragraph) => {}
+
+         Event::Start(ref tag) if is_block(tag) => {
+            return Err(Error::from(PrepareError::BlockContent {
+               kind: format!("{tag:?}"),
+               location: Location::in_source(src, range.start),
+            }))
+         }
+
+         Event::Rule => {
+            return Err(Error::from(PrepareError::BlockContent {
+               kind: String::from("Rule"),
+               location: Location::in_source(src, range.start),
+            }))
+         }
+
+         other => content
+            .handle(other, range.start)
+            .map_err(|source| content_error(source, src, range.start))?,
+      }
+   }
+
+   let (_, mut first_pass_events, mut footnote_definitions) = FirstPass::Content(content)
+      .finalize()
+      .map_err(|source| content_error(source, src, src.len()))?;
+
+   crossrefs::number(&mut first_pass_events, &mut footnote_definitions, src)
+      .map_err(PrepareError::from)?;
+
+   Ok(ToRender {
+      source: src,
+      first_pass_events,
+      footnote_definitions,
+      bibliography: None,
+      macros: Macros::default(),
+      shortcodes: Vec::new(),
+   })
+}
+
+fn is_block(tag: &Tag) -> bool {
+   !matches!(
+      tag,
+      Tag::Emphasis
+         | Tag::Strong
+         | Tag::Strikethrough
+         | Tag::Link { .. }
+         | Tag::Image { .. }
+   )
+}
+
 #[derive(Error, Debug)]
 #[error("could not render Markdown content")]
 pub struct RenderError {
@@ -214,26 +693,92 @@ pub struct RenderError {
    source: second_pass::Error,
 }
 
-/// The result of successfully rendering content: HTML. It can be extracted via
-/// the `.html()` method.
+/// The result of successfully rendering content: HTML, for both the full content and
+/// its excerpt, and gemtext for the full content if [`Markdown::with_gemtext`] is set.
+/// They can be extracted via the `.html()`, `.excerpt()`, and `.gemtext()` methods. The
+/// content's headings and footnotes are also available separately, via `.toc()` and
+/// `.footnotes()`.
 #[derive(Debug, Deserialize)]
-#[repr(transparent)]
-pub struct Rendered(String);
+pub struct Rendered {
+   html: String,
+   excerpt: String,
+   gemtext: Option<String>,
+   toc: Vec<Heading>,
+   footnotes: Vec<Footnote>,
+}
 
 impl Rendered {
    #[inline(always)]
    pub fn html(&self) -> &str {
-      self.0.as_str()
+      self.html.as_str()
+   }
+
+   #[inline(always)]
+   pub fn excerpt(&self) -> &str {
+      self.excerpt.as_str()
+   }
+
+   #[inline(always)]
+   pub fn gemtext(&self) -> Option<&str> {
+      self.gemtext.as_deref()
+   }
+
+   #[inline(always)]
+   pub fn toc(&self) -> &[Heading] {
+      &self.toc
+   }
+
+   #[inline(always)]
+   pub fn footnotes(&self) -> &[Footnote] {
+      &self.footnotes
+   }
+
+   /// The same content with every relative URL (of links, images, etc.) made absolute
+   /// against `page_url`, the URL of the page the content is on, for reading anywhere
+   /// other than that page: feeds, email, and so on.
+   ///
+   /// # Errors
+   ///
+   /// When `page_url` is not a valid absolute URL.
+   pub fn to_absolute(&self, page_url: &str) -> Result<Rendered, InvalidUrl> {
+      let base = urls::parse(page_url)?;
+      Ok(Rendered {
+         html: urls::absolute(&self.html, &base),
+         excerpt: urls::absolute(&self.excerpt, &base),
+         gemtext: self.gemtext.clone(),
+         toc: self.toc.clone(),
+         footnotes: self
+            .footnotes
+            .iter()
+            .map(|footnote| Footnote {
+               html: urls::absolute(&footnote.html, &base),
+               ..footnote.clone()
+            })
+            .collect(),
+      })
    }
 }
 
-fn bad_prepare_state<T>(state: &impl Debug, context: &impl Debug) -> Result<T, Error> {
+fn bad_prepare_state<T>(
+   event: &Event,
+   state: &FirstPass,
+   src: &str,
+   offset: usize,
+) -> Result<T, Error> {
    Err(Error::from(PrepareError::State {
-      state: format!("{state:?}"),
-      context: format!("{context:?}"),
+      event: format!("{event:?}"),
+      state: state.description().to_string(),
+      location: Location::in_source(src, offset),
    }))
 }
 
+fn content_error(source: first_pass::Error, src: &str, offset: usize) -> Error {
+   Error::from(PrepareError::Content {
+      source,
+      location: Location::in_source(src, offset),
+   })
+}
+
 // TODO: I think what I would *like* to do is have a slow path for dev and a
 // fast path for prod, where the slow path just loads the `.sublime-syntax`
 // from disk and compiles them, and the fast path uses a `build.rs` or similar
@@ -261,3 +806,886 @@ fn load_syntaxes() -> SyntaxSet {
    // syntax_builder.build()
    SyntaxSet::load_defaults_newlines()
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use pulldown_cmark::CodeBlockKind;
+   use std::collections::BTreeMap;
+
+   fn no_rewrite(s: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+      Ok(s.to_string())
+   }
+
+   #[test]
+   fn inline_has_no_paragraph() {
+      let md = Markdown::new(None);
+      let rendered = md.render_inline("Hello, *world*!", no_rewrite).unwrap();
+      assert_eq!(rendered.html(), "Hello, <em>world</em>!");
+   }
+
+   #[test]
+   fn inline_rejects_block_content() {
+      let md = Markdown::new(None);
+      for src in ["# A heading", "- a list", "one\n\ntwo", "> quoted", "---"] {
+         assert!(
+            matches!(
+               md.render_inline(src, no_rewrite),
+               Err(Error::Prepare {
+                  source: PrepareError::BlockContent { .. }
+               })
+            ),
+            "'{src}' should be rejected"
+         );
+      }
+   }
+
+   #[test]
+   fn excerpt_ends_at_marker() {
+      let md = Markdown::new(None);
+      let src = "One.\n\nTwo.\n\n<!--more-->\n\nThree.";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(rendered.excerpt(), "<p>One.</p>\n<p>Two.</p>\n");
+      assert!(rendered.html().contains("<p>Three.</p>"));
+   }
+
+   #[test]
+   fn excerpt_closes_open_tags_at_inline_marker() {
+      let md = Markdown::new(None);
+      let src = "One *and <!-- more --> two*.";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(rendered.excerpt(), "<p>One <em>and </em></p>\n");
+   }
+
+   #[test]
+   fn excerpt_falls_back_to_paragraphs() {
+      let md = Markdown::new(None).with_excerpt(Excerpt {
+         paragraphs: 2,
+         ..Excerpt::default()
+      });
+      let src = "One.\n\n> Quoted.\n\nTwo.\n\nThree.";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.excerpt(),
+         "<p>One.</p>\n<blockquote>\n<p>Quoted.</p>\n</blockquote>\n<p>Two.</p>\n"
+      );
+   }
+
+   #[test]
+   fn headings_and_footnotes_are_outlined() {
+      let md = Markdown::new(None);
+      let src = "# Intro {#intro}\n\nSee.[^a]\n\n## A *second* part\n\n[^a]: A note:\n\n    - with\n    - a list\n";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+
+      assert_eq!(
+         rendered.toc(),
+         [
+            Heading {
+               level: 1,
+               id: Some(String::from("intro")),
+               html: String::from("Intro"),
+            },
+            Heading {
+               level: 2,
+               id: None,
+               html: String::from("A <em>second</em> part"),
+            },
+         ]
+      );
+
+      let [footnote] = rendered.footnotes() else {
+         panic!("expected one footnote, got {:?}", rendered.footnotes());
+      };
+      assert_eq!((footnote.number, footnote.id.as_str()), (1, "fn1"));
+      let html = &footnote.html;
+      assert!(html.starts_with("<p>A note:</p>"), "{html}");
+      assert!(html.contains("<li>a list</li>"), "{html}");
+      assert!(!html.contains("fn-backref"), "{html}");
+   }
+
+   #[test]
+   fn shortcodes_wrap_rendered_content() {
+      let md = Markdown::new(None);
+      let src = "{% callout \"warning\" %}\n\nCareful *now*.\n\n{% aside %}\n\nInner.\n\n{% endaside %}\n\n{% endcallout %}\n\n{% if x %}\n";
+      let prepared = md.prepare(src).unwrap();
+      let rendered = md
+         .emit_with_shortcodes(prepared.to_render, no_rewrite, |shortcode, content| {
+            Ok(format!(
+               "<div class=\"{}\" data-args='{}'>{content}</div>",
+               shortcode.name, shortcode.arguments
+            ))
+         })
+         .unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<div class=\"callout\" data-args='\"warning\"'><p>Careful <em>now</em>.</p>\n\
+          <div class=\"aside\" data-args=''><p>Inner.</p>\n</div>\n</div>\n\
+          <p>{% if x %}</p>\n"
+      );
+
+      let unclosed = "- {% note %}\n\n  Text.\n\n- {% endnote %}\n";
+      let error = md.prepare(unclosed).map(|_| ()).unwrap_err();
+      let Error::Prepare {
+         source: PrepareError::Shortcodes { source },
+      } = error
+      else {
+         panic!("a shortcode closed in another list item should be an error");
+      };
+      let ShortcodeError::Unclosed { name, location } = source else {
+         panic!("expected an unclosed shortcode, got {source}");
+      };
+      assert_eq!(name, "note");
+      assert_eq!((location.line, location.column), (1, 3));
+   }
+
+   fn resolve_known(target: &str) -> Option<Resolved> {
+      (target == "known").then(|| Resolved {
+         url: String::from("/known/"),
+         title: String::from("Known"),
+      })
+   }
+
+   #[test]
+   fn internal_links_resolve_with_default_labels() {
+      let md = Markdown::new(None);
+      let src = "[[known]], [[known|label]], [text](lx:known), and [](lx:known).";
+      let mut prepared = md.prepare(src).unwrap();
+      prepared.to_render.resolve_links(resolve_known).unwrap();
+      let rendered = md.emit(prepared.to_render, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p><a href=\"/known/\">Known</a>, <a href=\"/known/\">label</a>, \
+         <a href=\"/known/\">text</a>, and <a href=\"/known/\">Known</a>.</p>\n"
+      );
+   }
+
+   #[test]
+   fn unresolved_internal_links_report_positions() {
+      let md = Markdown::new(None);
+      let src =
+         "---\ntitle: Hi\n---\n\nFine: [[known]].\n\nNot: [[unknown]] or [x](lx:nope).";
+      let mut prepared = md.prepare(src).unwrap();
+      let UnresolvedLinks(unresolved) =
+         prepared.to_render.resolve_links(resolve_known).unwrap_err();
+      let found = unresolved
+         .iter()
+         .map(|link| (link.target.as_str(), link.line, link.column))
+         .collect::<Vec<_>>();
+      assert_eq!(found, vec![("unknown", 7, 6), ("nope", 7, 21)]);
+   }
+
+   #[test]
+   fn internal_links_in_footnotes_resolve() {
+      let md = Markdown::new(None);
+      let src = "A note.[^n]\n\n[^n]: See [[known]] and [the other](lx:known).";
+      let mut prepared = md.prepare(src).unwrap();
+      prepared.to_render.resolve_links(resolve_known).unwrap();
+      let rendered = md.emit(prepared.to_render, no_rewrite).unwrap();
+      assert!(
+         rendered.html().contains(
+            "See <a href=\"/known/\">Known</a> and <a href=\"/known/\">the other</a>."
+         ),
+         "{}",
+         rendered.html()
+      );
+
+      let src = "A note.[^n]\n\n[^n]: See [[unknown]] and [the other](lx:known).";
+      let mut prepared = md.prepare(src).unwrap();
+      let UnresolvedLinks(unresolved) =
+         prepared.to_render.resolve_links(resolve_known).unwrap_err();
+      let found = unresolved
+         .iter()
+         .map(|link| (link.target.as_str(), link.line, link.column))
+         .collect::<Vec<_>>();
+      assert_eq!(found, vec![("unknown", 3, 11)]);
+   }
+
+   const BIBTEX: &str = r#"
+@book{augustine,
+   author = {Augustine},
+   title = {Confessions},
+   publisher = {Oxford University Press},
+   year = {1991},
+}
+
+@book{att,
+   author = {Smith, Jane},
+   title = {AT&T: A History},
+   publisher = {Example Press},
+   year = {2020},
+}
+"#;
+
+   fn cite(src: &str, style: &str) -> Rendered {
+      let md = Markdown::new(None);
+      let bibliography = Bibliography::from_biblatex(BIBTEX).unwrap();
+      let style = Style::named(style).unwrap();
+      let mut prepared = md.prepare(src).unwrap();
+      prepared.to_render.cite(&bibliography, &style).unwrap();
+      md.emit(prepared.to_render, no_rewrite).unwrap()
+   }
+
+   #[test]
+   fn citations_in_text_precede_bibliography() {
+      let rendered = cite(
+         "As argued [@augustine, p. 12] and [@att], but [see @att] is not a citation.[^1]\n\n\
+         [^1]: A note.",
+         "chicago-author-date",
+      );
+      let html = rendered.html();
+      assert!(html.starts_with(
+         "<p>As argued <span class=\"citation\" data-cites=\"augustine\">\
+         (Augustine 1991, 12)</span> and <span class=\"citation\" data-cites=\"att\">\
+         (Smith 2020)</span>, but [see @att] is not a citation."
+      ));
+      assert!(html.ends_with(
+         "</ol></section><section class=\"bibliography\"><ul class=\"bibliography-list\">\
+         <li id=\"ref-augustine\">Augustine. 1991. <i>Confessions</i>. Oxford University Press.</li>\
+         <li id=\"ref-att\">Smith, Jane. 2020. <i>AT&amp;T: A History</i>. Example Press.</li>\
+         </ul></section>"
+      ));
+   }
+
+   #[test]
+   fn citations_become_notes_in_note_styles() {
+      let rendered = cite(
+         "First.[^1] Then [@augustine, chap. 3; @att].\n\n[^1]: A note.",
+         "chicago-notes",
+      );
+      let html = rendered.html();
+      assert!(html.starts_with(
+         "<p>First.<sup><a href=\"#fn1\" id=\"fnref1\">1</a></sup> \
+         Then <sup><a href=\"#fn2\" id=\"fnref2\">2</a></sup>.</p>"
+      ));
+      assert!(html.contains(
+         "<li id=\"fn2\">\n<p><span class=\"citation\" data-cites=\"augustine att\">\
+         Augustine, <i>Confessions</i>, chap. 3; Smith, <i>AT&amp;T: A History</i>.</span>"
+      ));
+   }
+
+   #[test]
+   fn citations_in_footnotes_are_part_of_the_note() {
+      let src = "First.[^1] Then [@att].\n\n[^1]: As in [@augustine, p. 3].";
+      let html = cite(src, "chicago-notes").html().to_string();
+      assert!(
+         html.contains(
+            "<li id=\"fn1\">\n<p>As in <span class=\"citation\" data-cites=\"augustine\">\
+             Augustine, <i>Confessions</i>, 3.</span>."
+         ),
+         "{html}"
+      );
+      assert!(html.contains("Then <sup><a href=\"#fn2\" id=\"fnref2\">2</a></sup>."));
+
+      let html = cite(src, "chicago-author-date").html().to_string();
+      assert!(
+         html.contains(
+            "<p>As in <span class=\"citation\" data-cites=\"augustine\">\
+             (Augustine 1991, 3)</span>."
+         ),
+         "{html}"
+      );
+
+      let md = Markdown::new(None);
+      let bibliography = Bibliography::from_biblatex(BIBTEX).unwrap();
+      let style = Style::named("chicago-notes").unwrap();
+      let mut prepared = md.prepare("A note.[^1]\n\n[^1]: See [@nope].").unwrap();
+      let UnknownCitations(unknown) =
+         prepared.to_render.cite(&bibliography, &style).unwrap_err();
+      assert_eq!(
+         unknown
+            .iter()
+            .map(|citation| (citation.key.as_str(), citation.line, citation.column))
+            .collect::<Vec<_>>(),
+         vec![("nope", 3, 11)]
+      );
+   }
+
+   #[test]
+   fn unknown_citations_report_positions() {
+      let md = Markdown::new(None);
+      let bibliography = Bibliography::from_biblatex(BIBTEX).unwrap();
+      let style = Style::named("chicago-author-date").unwrap();
+      let src = "Fine [@augustine].\n\nNot [@nope, 3; @att] or [@missing].";
+      let mut prepared = md.prepare(src).unwrap();
+      let UnknownCitations(unknown) =
+         prepared.to_render.cite(&bibliography, &style).unwrap_err();
+      let found = unknown
+         .iter()
+         .map(|citation| (citation.key.as_str(), citation.line, citation.column))
+         .collect::<Vec<_>>();
+      assert_eq!(found, vec![("nope", 3, 5), ("missing", 3, 25)]);
+   }
+
+   #[test]
+   fn cross_references_are_numbered_and_linked() {
+      let md = Markdown::new(None);
+      let src = "\
+## Intro {#sec:intro}
+
+See @fig:map, [@tbl:results], and @eq:energy, in @sec:method.
+
+![A map](map.png \"The map\") {#fig:map}
+
+![Unlabelled](other.png \"Another\")
+
+| a | b |
+|---|---|
+| 1 | 2 |
+
+Table: Results {#tbl:results}
+
+$$E = mc^2$$ {#eq:energy}
+
+## Method
+
+### Details {#sec:method}
+";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      let html = rendered.html();
+
+      for expected in [
+         r#"<h2 id="sec:intro" data-number="1">Intro</h2>"#,
+         r##"<p>See <a href="#fig:map" class="cross-ref">Figure 1</a>, [<a href="#tbl:results" class="cross-ref">Table 1</a>], and <a href="#eq:energy" class="cross-ref">Equation 1</a>, in <a href="#sec:method" class="cross-ref">Section 2.1</a>.</p>"##,
+         r#"<figure class="figure" id="fig:map"><img src="map.png" alt="A map" title="The map" /><figcaption>Figure 1: The map</figcaption></figure>"#,
+         r#"<figure class="figure"><img src="other.png" alt="Unlabelled" title="Another" /><figcaption>Figure 2: Another</figcaption></figure>"#,
+         r#"<figure class="table" id="tbl:results"><table>"#,
+         "</table>\n<figcaption>Table 1: Results</figcaption></figure>",
+         r#"<span class="equation" id="eq:energy"><math"#,
+         r#"<span class="equation-number">(1)</span></span>"#,
+      ] {
+         assert!(html.contains(expected), "missing {expected} in:\n{html}");
+      }
+   }
+
+   #[test]
+   fn unresolved_cross_references_are_errors() {
+      let md = Markdown::new(None);
+      let src =
+         "Dup.\n\n$$x$$ {#eq:x}\n\n$$y$$ {#eq:x}\n\nSee @fig:nope.\n\nStray {#fig:x}";
+      let Err(Error::Prepare {
+         source:
+            PrepareError::CrossReferences {
+               source: CrossReferenceErrors(errors),
+            },
+      }) = md.prepare(src)
+      else {
+         panic!("expected cross-reference errors");
+      };
+
+      let [duplicate, unresolved, unattached] = errors.as_slice() else {
+         panic!("unexpected errors: {errors:?}");
+      };
+      let CrossReferenceError::Duplicate {
+         label,
+         location: Some(duplicate),
+      } = duplicate
+      else {
+         panic!("expected a duplicate label, got {duplicate:?}");
+      };
+      assert_eq!((label.as_str(), duplicate.line), ("eq:x", 5));
+
+      let CrossReferenceError::Unresolved { label, location } = unresolved else {
+         panic!("expected an unresolved reference, got {unresolved:?}");
+      };
+      assert_eq!(label, "fig:nope");
+      assert_eq!((location.line, location.column), (7, 5));
+      let message = location.to_string();
+      assert!(
+         message.ends_with("7 | See @fig:nope.\n  |     ^"),
+         "{message}"
+      );
+
+      let CrossReferenceError::Unattached { label, location } = unattached else {
+         panic!("expected a label attached to nothing, got {unattached:?}");
+      };
+      assert_eq!(label, "fig:x");
+      assert_eq!((location.line, location.column), (9, 7));
+   }
+
+   #[test]
+   fn cross_references_work_in_footnotes() {
+      let md = Markdown::new(None);
+      let src = "\
+See @fig:map and @fig:noted.[^note]
+
+![A map](map.png \"The map\") {#fig:map}
+
+[^note]: Compare @fig:map with:
+
+    ![Noted](noted.png \"Noted\") {#fig:noted}
+";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      let html = rendered.html();
+
+      for expected in [
+         r##"See <a href="#fig:map" class="cross-ref">Figure 1</a> and <a href="#fig:noted" class="cross-ref">Figure 2</a>."##,
+         r##"Compare <a href="#fig:map" class="cross-ref">Figure 1</a> with:"##,
+         r#"<figure class="figure" id="fig:noted"><img src="noted.png" alt="Noted" title="Noted" /><figcaption>Figure 2: Noted</figcaption></figure>"#,
+      ] {
+         assert!(html.contains(expected), "missing {expected} in:\n{html}");
+      }
+
+      let src = "Fine.[^note]\n\n[^note]: See @tbl:nope.";
+      let Err(Error::Prepare {
+         source:
+            PrepareError::CrossReferences {
+               source: CrossReferenceErrors(errors),
+            },
+      }) = md.prepare(src)
+      else {
+         panic!("expected cross-reference errors");
+      };
+      let [CrossReferenceError::Unresolved { label, location }] = errors.as_slice()
+      else {
+         panic!("unexpected errors: {errors:?}");
+      };
+      assert_eq!(label, "tbl:nope");
+      assert_eq!((location.line, location.column), (3, 14));
+   }
+
+   #[test]
+   fn math_uses_macros_and_reports_failures() {
+      let macros: Macros = serde_json::from_str(r#"{"R": "\\mathbb{R}"}"#).unwrap();
+      let md = Markdown::new(None).with_math(Math { macros });
+      let src = "Fine: $x \\in \\R$.\n\nBad: $\\frac{1}$.";
+
+      let prepared = md.prepare(src).unwrap();
+      let error = md.emit(prepared.to_render, no_rewrite).unwrap_err();
+      let second_pass::Error::Transform { source } = error.source else {
+         panic!("expected a transform error, got {:?}", error.source);
+      };
+      let bad_math = source.downcast_ref::<BadMath>().unwrap();
+      assert_eq!(bad_math.expression, "\\frac{1}");
+      let location = bad_math.location.as_ref().unwrap();
+      assert_eq!((location.line, location.column), (3, 6));
+
+      let md = md.with_math_fallback(true);
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      let html = rendered.html();
+      assert!(
+         html.contains("<mi mathvariant=\"double-struck\">R</mi>"),
+         "{html}"
+      );
+      assert!(html.contains("$\\frac{1}$</span>"), "{html}");
+   }
+
+   #[test]
+   fn rewrite_errors_point_at_the_source() {
+      let md = Markdown::new(None);
+      let src = "# Title\n\nFine.\n\nA *very* {{ broken }} line.";
+      let prepared = md.prepare(src).unwrap();
+      let error = md
+         .emit(prepared.to_render, |text| {
+            if text.contains("{{") {
+               Err("unclosed".into())
+            } else {
+               Ok(text.to_string())
+            }
+         })
+         .unwrap_err();
+
+      let second_pass::Error::Rewrite {
+         location: Some(location),
+         ..
+      } = error.source
+      else {
+         panic!("expected a located rewrite error, got {:?}", error.source);
+      };
+      assert_eq!((location.line, location.column), (5, 9));
+      assert_eq!(location.snippet, "A *very* {{ broken }} line.");
+   }
+
+   #[test]
+   fn prose_is_escaped_and_rewritten_text_is_html() {
+      let md = Markdown::new(None).with_gemtext(true);
+      let src = "A `Vec<T>` is a Vec<T, A>, says AT&T.\n\n\
+                 Fish & chips for {{ who }} if {{ a < b }}, and {% raw %}<b>{% endraw %}.";
+      let (_, rendered) = md
+         .render(src, |text| {
+            // Like a template engine: the literal text passes through, and whatever it
+            // inserts is its responsibility to escape.
+            Ok(text
+               .replace("{{ who }}", "Tom &amp; Jerry")
+               .replace("{{ a < b }}", "<em>ever</em>"))
+         })
+         .unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p>A <code>Vec&lt;T&gt;</code> is a Vec&lt;T, A&gt;, says AT&amp;T.</p>\n\
+          <p>Fish &amp; chips for Tom &amp; Jerry if <em>ever</em>, \
+          and {% raw %}<b>{% endraw %}.</p>\n"
+      );
+      // Gemtext is plain text, so what was inserted is not escaped there.
+      assert_eq!(
+         rendered.gemtext(),
+         Some(
+            "A `Vec<T>` is a Vec<T, A>, says AT&T.\n\n\
+             Fish & chips for Tom & Jerry if ever, and {% raw %}{% endraw %}.\n"
+         )
+      );
+   }
+
+   #[test]
+   fn text_is_rewritten_all_at_once() {
+      let md = Markdown::new(None);
+      let calls = std::cell::RefCell::new(Vec::new());
+      let src = "# {{ title }}\n\nPlain *text*.\n\nBy {{ author }}.[^n]\n\n[^n]: {{ x }}";
+      let (_, rendered) = md
+         .render(src, |text| {
+            calls.borrow_mut().push(text.to_string());
+            Ok(text.replace("{{ ", "[").replace(" }}", "]"))
+         })
+         .unwrap();
+      assert_eq!(calls.borrow().len(), 1, "{:?}", calls.borrow());
+      let html = rendered.html();
+      assert!(html.starts_with("<h1>[title]</h1>\n<p>Plain <em>text</em>.</p>"));
+      assert!(html.contains("<p>By [author]."), "{html}");
+      assert!(html.contains("<p>[x]"), "{html}");
+
+      // Gemtext uses the same results rather than rewriting anything again.
+      calls.borrow_mut().clear();
+      let (_, rendered) = Markdown::new(None)
+         .with_gemtext(true)
+         .render(src, |text| {
+            calls.borrow_mut().push(text.to_string());
+            Ok(text.replace("{{ ", "[").replace(" }}", "]"))
+         })
+         .unwrap();
+      assert_eq!(calls.borrow().len(), 1, "{:?}", calls.borrow());
+      assert!(rendered.gemtext().unwrap().starts_with("# [title]"));
+
+      // A block which spans text nodes does not survive splitting, so it is an error,
+      // which points at the text which closes it.
+      let src = "{% if x %}one *two* three{% endif %}";
+      let prepared = md.prepare(src).unwrap();
+      let error = md
+         .emit(prepared.to_render, |text| {
+            Ok(if text.contains('\u{1F}') {
+               String::from("lost")
+            } else {
+               text.to_string()
+            })
+         })
+         .unwrap_err();
+      let second_pass::Error::Rewrite {
+         original,
+         location: Some(location),
+         ..
+      } = error.source
+      else {
+         panic!("expected a located rewrite error, got {:?}", error.source);
+      };
+      assert_eq!(original, " three{% endif %}");
+      assert_eq!((location.line, location.column), (1, 20));
+   }
+
+   #[test]
+   fn templates_share_one_scope_in_html_and_gemtext() {
+      let md = Markdown::new(None).with_gemtext(true);
+      let src = "{% set n = 1 %}A\n\n{{ n }}";
+      let (_, rendered) = md
+         .render(src, |text| {
+            // Like a template engine: `n` is only defined after it is set.
+            if !text.contains("{% set n = 1 %}") {
+               return Err("n is undefined".into());
+            }
+            Ok(text.replace("{% set n = 1 %}", "").replace("{{ n }}", "1"))
+         })
+         .unwrap();
+      assert_eq!(rendered.html(), "<p>A</p>\n<p>1</p>\n");
+      assert_eq!(rendered.gemtext(), Some("A\n\n1\n"));
+   }
+
+   #[test]
+   fn gemtext_maps_blocks_and_collects_links() {
+      let md = Markdown::new(None).with_gemtext(true);
+      let src = r#"# Title
+
+Some *text* with [a link](https://example.com) and a note.[^n]
+
+> A quote, with $x^2$.
+
+- one
+- [two](/two/)
+
+```rust
+let x = 1;
+```
+
+[^n]: The note, linking [elsewhere](https://example.org).
+"#;
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.gemtext(),
+         Some(
+            "# Title
+
+Some text with a link and a note.[1]
+=> https://example.com a link
+
+> A quote, with $x^2$.
+
+* one
+* two
+=> /two/ two
+
+```rust
+let x = 1;
+```
+
+* [1] The note, linking elsewhere.
+=> https://example.org elsewhere
+"
+         )
+      );
+
+      let (_, rendered) = Markdown::new(None).render(src, no_rewrite).unwrap();
+      assert_eq!(rendered.gemtext(), None);
+   }
+
+   #[test]
+   fn definition_lists_abbreviations_and_glossary_terms() {
+      let glossary = Glossary {
+         url: String::from("/glossary/"),
+         terms: BTreeMap::from([(
+            String::from("Means of Grace"),
+            String::from("How God gives grace"),
+         )]),
+      };
+      let md = Markdown::new(None).with_glossary(glossary);
+      let src = "\
+HTML, the means of grace; the means of grace again.
+
+Term
+: Its definition, in HTML.
+
+[HTML](https://html.spec.whatwg.org) and `HTML` and {{ HTML }}.
+
+*[HTML]: HyperText Markup Language
+";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      let html = rendered.html();
+
+      for expected in [
+         r#"<p><abbr title="HyperText Markup Language">HTML</abbr>, the <a href="/glossary/#term-means-of-grace" class="glossary-term"><abbr title="How God gives grace">means of grace</abbr></a>; the means of grace again.</p>"#,
+         "<dl>\n<dt>Term</dt>\n<dd>Its definition, in <abbr title=\"HyperText Markup Language\">HTML</abbr>.</dd>\n</dl>",
+         r#"<p><a href="https://html.spec.whatwg.org">HTML</a> and <code>HTML</code> and {{ HTML }}.</p>"#,
+      ] {
+         assert!(html.contains(expected), "missing {expected} in:\n{html}");
+      }
+      assert!(!html.contains("*[HTML]"), "{html}");
+   }
+
+   #[test]
+   fn verse_keeps_lines_indentation_and_stanzas() {
+      let md = Markdown::new(None);
+      let src = "```verse\nWhose woods these are I think I know.\n   His house is in the *village* though;\n\n- not a list\n```\n";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         concat!(
+            r#"<div class="verse"><p class="stanza"><span class="line">Whose woods these are I think I know.</span><br />"#,
+            "\n",
+            r#"<span class="line" style="--indent: 3">His house is in the <em>village</em> though;</span></p>"#,
+            "\n",
+            r#"<p class="stanza"><span class="line">- not a list</span></p>"#,
+            "\n</div>\n",
+         )
+      );
+   }
+
+   #[test]
+   fn scripture_references_are_normalized_and_linked() {
+      let md = Markdown::new(None);
+      let scripture = Scripture {
+         reader: String::from("https://read.example/{book}/{passage}?v={translation}"),
+         ..Scripture::default()
+      };
+      let mut prepared = md
+         .prepare("See 1 Cor. 13:4–7 and Jn 3:16, not `John 1:1` or Johnny 5.")
+         .unwrap();
+      let passages = prepared.to_render.link_scripture(&scripture);
+      assert_eq!(
+         passages.iter().map(|p| p.book.as_str()).collect::<Vec<_>>(),
+         ["1 Corinthians", "John"]
+      );
+
+      let rendered = md.emit(prepared.to_render, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p>See <a href=\"https://read.example/1%20Corinthians/13:4-7?v=ESV\" \
+         title=\"1 Corinthians 13:4–7 (ESV)\">1 Cor. 13:4–7</a> and \
+         <a href=\"https://read.example/John/3:16?v=ESV\" title=\"John 3:16 (ESV)\">\
+         Jn 3:16</a>, not <code>John 1:1</code> or Johnny 5.</p>\n"
+      );
+
+      let delimited = Scripture {
+         syntax: ScriptureSyntax::Delimited,
+         ..Scripture::default()
+      };
+      let mut prepared = md.prepare("Compare ((ps 23)) with Psalm 24.").unwrap();
+      let passages = prepared.to_render.link_scripture(&delimited);
+      assert_eq!(passages.len(), 1);
+      assert_eq!(passages[0].book, "Psalms");
+      assert!(scripture.passage("Song of Solomon", "2:1", None).is_ok());
+      assert!(scripture.passage("Hezekiah", "1", None).is_err());
+   }
+
+   #[test]
+   fn links_are_resolved_marked_and_made_absolute() {
+      let md = Markdown::new(None)
+         .with_site_url("https://example.com/")
+         .with_external_links(ExternalLinks {
+            rel: Some(String::from("noopener")),
+            class: Some(String::from("external")),
+         });
+      let mut prepared = md
+         .prepare(
+            "[Intro](../intro.md#why), [elsewhere](https://rust-lang.org \"Rust\"), \
+            [home](https://example.com/about/), and ![a cat](cat.png).",
+         )
+         .unwrap();
+      prepared
+         .to_render
+         .resolve_relative_links(|path| {
+            (path == "../intro.md").then(|| Resolved {
+               url: String::from("/intro/"),
+               title: String::from("Intro"),
+            })
+         })
+         .unwrap();
+
+      let rendered = md.emit(prepared.to_render, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p><a href=\"/intro/#why\">Intro</a>, <a href=\"https://rust-lang.org\" \
+         title=\"Rust\" rel=\"noopener\" class=\"external\">elsewhere</a>, \
+         <a href=\"https://example.com/about/\">home</a>, and \
+         <img src=\"cat.png\" alt=\"a cat\" />.</p>\n"
+      );
+
+      let page_url = "https://example.com/essays/first/";
+      let absolute = rendered.to_absolute(page_url).unwrap();
+      assert_eq!(
+         absolute.html(),
+         "<p><a href=\"https://example.com/intro/#why\">Intro</a>, \
+         <a href=\"https://rust-lang.org\" title=\"Rust\" rel=\"noopener\" \
+         class=\"external\">elsewhere</a>, <a href=\"https://example.com/about/\">home</a>, \
+         and <img src=\"https://example.com/essays/first/cat.png\" alt=\"a cat\" />.</p>\n"
+      );
+
+      let mut prepared = md.prepare("A [broken](missing.md) link.").unwrap();
+      let Err(UnresolvedLinks(unresolved)) =
+         prepared.to_render.resolve_relative_links(|_| None)
+      else {
+         panic!("expected an unresolved link");
+      };
+      assert_eq!(unresolved[0].target, "missing.md");
+      assert_eq!((unresolved[0].line, unresolved[0].column), (1, 3));
+
+      let mut prepared = md.prepare("[Draft](na%C3%AFve%20%28draft%29.md)").unwrap();
+      prepared
+         .to_render
+         .resolve_relative_links(|path| {
+            (path == "naïve (draft).md").then(|| Resolved {
+               url: String::from("/naive-draft/"),
+               title: String::from("Draft"),
+            })
+         })
+         .unwrap();
+   }
+
+   #[test]
+   fn svgbob_blocks_become_accessible_svg() {
+      let md = Markdown::new(None);
+      let src = "```svgbob Client & server\n+---+    +---+\n| a |--->| b |\n+---+    +---+\n```\n";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      let html = rendered.html();
+      assert!(html.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+      assert!(html.contains(r#" role="img"><title>Client &amp; server</title>"#));
+      assert!(html.contains("stroke: currentColor;"));
+      assert!(!html.contains("<pre"));
+   }
+
+   #[test]
+   fn plain_text_leaves_out_diagrams() {
+      let md = Markdown::new(None);
+      let src = "Before & after:\n\n```svgbob A box\n+---+\n| a |\n+---+\n```\n\nDone.";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         strip_tags(rendered.html()).trim(),
+         "Before & after:\n\nDone."
+      );
+      assert_eq!(strip_tags("<p>1 &lt; 2<br/></p>"), "1 < 2");
+   }
+
+   #[test]
+   fn other_scripts_are_tagged_with_their_language() {
+      let md = Markdown::new(None);
+      let src = "The λόγος, ἐν ἀρχῇ, and בְּרֵאשִׁית בָּרָא. [Deus absconditus]{lang=la}, \
+         [*not* λόγος]{lang=el}, and `λόγος`.";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p>The <span lang=\"grc\">λόγος, ἐν ἀρχῇ</span>, and \
+         <span lang=\"he\" dir=\"rtl\">בְּרֵאשִׁית בָּרָא</span>. \
+         <span lang=\"la\">Deus absconditus</span>, \
+         <span lang=\"el\"><em>not</em> λόγος</span>, and <code>λόγος</code>.</p>\n"
+      );
+
+      let md = Markdown::new(None).with_languages(Languages(BTreeMap::new()));
+      let (_, rendered) = md.render("The λόγος.", no_rewrite).unwrap();
+      assert_eq!(rendered.html(), "<p>The λόγος.</p>\n");
+   }
+
+   /// Turns `shout` code blocks into upper-cased paragraphs, and notes how many there
+   /// were after the content.
+   struct Shout;
+
+   impl Transform for Shout {
+      fn transform<'e>(
+         &self,
+         events: Vec<Located<'e>>,
+         context: &mut Context<'_, 'e>,
+      ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+         let mut shouting = false;
+         let mut count = 0;
+         let mut transformed = Vec::with_capacity(events.len());
+         for located in events {
+            let event = match located.event {
+               Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang)))
+                  if lang.as_ref() == "shout" =>
+               {
+                  shouting = true;
+                  count += 1;
+                  Event::Start(Tag::Paragraph)
+               }
+               Event::Text(text) if shouting => Event::Text(text.to_uppercase().into()),
+               Event::End(TagEnd::CodeBlock) if shouting => {
+                  shouting = false;
+                  Event::End(TagEnd::Paragraph)
+               }
+               event => event,
+            };
+            transformed.push(Located { event, ..located });
+         }
+
+         context.append([Located::from(Event::Html(
+            format!("<p>{count} shouts</p>").into(),
+         ))]);
+         Ok(transformed)
+      }
+   }
+
+   #[test]
+   fn transforms_run_before_built_in_transforms() {
+      let md = Markdown::new(None).with_transform(Shout);
+      let src = "```shout\nhello\n```\n\n```\nquiet\n```\n";
+      let (_, rendered) = md.render(src, no_rewrite).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p>HELLO\n</p>\n<pre><code>quiet\n</code></pre><p>1 shouts</p>"
+      );
+      assert_eq!(rendered.excerpt(), "<p>HELLO\n</p>\n");
+   }
+}
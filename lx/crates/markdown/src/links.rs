@@ -0,0 +1,312 @@
+//! Links between items in the same site, using either wiki-style syntax (`[[target]]`
+//! and `[[target|label]]`) or standard Markdown links with the `lx:` scheme (e.g.
+//! `[label](lx:target)`). These are found during the first pass, but only the caller
+//! knows about the other pages in the site, so it resolves them before the second pass.
+//!
+//! Wiki-style labels are plain text, not Markdown. Both forms work in footnote
+//! definitions as well as the rest of the content.
+//!
+//! Standard Markdown links to other source files by their relative path, e.g.
+//! `[the intro](../intro.md#history)`, are resolved the same way (by the caller, relative
+//! to the linking file), keeping any fragment. These work in footnotes, too.
+
+use std::fmt;
+
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{CowStr, Event as CmarkEvent, LinkType, Tag, TagEnd};
+use thiserror::Error;
+
+use super::first_pass::{Definitions, Event, TextRun};
+use super::location::line_and_column;
+
+pub(super) const SCHEME: &str = "lx:";
+
+#[derive(Debug)]
+pub(super) struct InternalLink<'e> {
+   pub(super) target: CowStr<'e>,
+   /// Byte offset of the start of the link in the original source.
+   pub(super) offset: usize,
+   pub(super) form: Form<'e>,
+}
+
+#[derive(Debug)]
+pub(super) enum Form<'e> {
+   /// `[[target]]` or `[[target|label]]`: a complete link, with an optional label.
+   Wiki { label: Option<CowStr<'e>> },
+
+   /// `[label](lx:target "title")`: only the *start* of the link. Its label and the end
+   /// of the link are the events which follow it.
+   Scheme {
+      link_type: LinkType,
+      title: CowStr<'e>,
+      id: CowStr<'e>,
+   },
+}
+
+impl fmt::Display for InternalLink<'_> {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match &self.form {
+         Form::Wiki { label: Some(label) } => write!(f, "[[{}|{label}]]", self.target),
+         Form::Wiki { label: None } => write!(f, "[[{}]]", self.target),
+         Form::Scheme { .. } => write!(f, "{SCHEME}{}", self.target),
+      }
+   }
+}
+
+/// A link target resolved by the caller: where it goes and what to call it when the
+/// link does not supply its own label.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+   pub url: String,
+   pub title: String,
+}
+
+#[derive(Error, Debug)]
+#[error("no page found for link target '{target}' at line {line}, column {column}")]
+pub struct UnresolvedLink {
+   pub target: String,
+   pub line: usize,
+   pub column: usize,
+}
+
+#[derive(Error, Debug)]
+pub struct UnresolvedLinks(pub Vec<UnresolvedLink>);
+
+impl fmt::Display for UnresolvedLinks {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      writeln!(f, "could not resolve {} internal links", self.0.len())?;
+      for link in &self.0 {
+         writeln!(f, "\t{link}")?;
+      }
+      Ok(())
+   }
+}
+
+pub(super) enum Piece<'e> {
+   /// Text, with its offset in the original source.
+   Text(CowStr<'e>, usize),
+   Link(InternalLink<'e>),
+}
+
+/// Given a run of adjacent text nodes and their offsets in the original source, find
+/// any wiki-style links in them. `pulldown_cmark` splits text at brackets, so a single
+/// `[[target]]` is usually several text nodes by the time it gets here.
+pub(super) fn split_wiki_links(run: Vec<(CowStr<'_>, usize)>) -> Vec<Piece<'_>> {
+   let run = TextRun::new(run);
+   if !run.joined.contains("[[") {
+      return run
+         .into_parts()
+         .map(|(text, offset)| Piece::Text(text, offset))
+         .collect();
+   }
+   let joined = run.joined.as_str();
+
+   let mut pieces = Vec::new();
+   let mut rest_start = 0;
+   let mut search_from = 0;
+   while let Some(open) = joined[search_from..].find("[[").map(|i| i + search_from) {
+      let Some(close) = joined[open + 2..].find("]]").map(|i| i + open + 2) else {
+         break;
+      };
+
+      let inner = &joined[open + 2..close];
+      if inner.is_empty() || inner.contains(['[', ']', '\n']) {
+         search_from = open + 2;
+         continue;
+      }
+
+      let (target, label) = match inner.split_once('|') {
+         Some((target, label)) => (target.trim(), Some(label.trim())),
+         None => (inner.trim(), None),
+      };
+
+      if open > rest_start {
+         pieces.push(Piece::Text(
+            joined[rest_start..open].to_string().into(),
+            run.source_offset(rest_start),
+         ));
+      }
+
+      pieces.push(Piece::Link(InternalLink {
+         target: target.to_string().into(),
+         offset: run.source_offset(open),
+         form: Form::Wiki {
+            label: label
+               .filter(|label| !label.is_empty())
+               .map(|label| label.to_string().into()),
+         },
+      }));
+
+      rest_start = close + 2;
+      search_from = rest_start;
+   }
+
+   if rest_start < joined.len() {
+      pieces.push(Piece::Text(
+         joined[rest_start..].to_string().into(),
+         run.source_offset(rest_start),
+      ));
+   }
+
+   pieces
+}
+
+/// Replace every internal link in `events` and the footnote definitions with a normal
+/// link to its resolved target, collecting every link which cannot be resolved instead of
+/// stopping at the first.
+pub(super) fn resolve(
+   events: &mut Vec<Event<'_>>,
+   footnote_definitions: &mut Definitions<'_>,
+   source: &str,
+   resolve: impl Fn(&str) -> Option<Resolved>,
+) -> Result<(), UnresolvedLinks> {
+   let mut unresolved = Vec::new();
+   resolve_in(events, source, &resolve, &mut unresolved);
+   for definition in footnote_definitions.values_mut() {
+      resolve_in(definition, source, &resolve, &mut unresolved);
+   }
+
+   if unresolved.is_empty() {
+      Ok(())
+   } else {
+      unresolved.sort_by_key(|link| (link.line, link.column));
+      Err(UnresolvedLinks(unresolved))
+   }
+}
+
+fn resolve_in(
+   events: &mut Vec<Event<'_>>,
+   source: &str,
+   resolve: &impl Fn(&str) -> Option<Resolved>,
+   unresolved: &mut Vec<UnresolvedLink>,
+) {
+   let mut resolved = Vec::with_capacity(events.len());
+   let mut events_iter = std::mem::take(events).into_iter().peekable();
+   while let Some(event) = events_iter.next() {
+      let Event::InternalLink(link) = event else {
+         resolved.push(event);
+         continue;
+      };
+
+      let Some(Resolved { url, title }) = resolve(link.target.as_ref()) else {
+         let (line, column) = line_and_column(source, link.offset);
+         unresolved.push(UnresolvedLink {
+            target: link.target.to_string(),
+            line,
+            column,
+         });
+         continue;
+      };
+
+      match link.form {
+         Form::Wiki { label } => {
+            let offset = Some(link.offset);
+            resolved.push(Event::Basic(
+               CmarkEvent::Start(Tag::Link {
+                  link_type: LinkType::Inline,
+                  dest_url: url.into(),
+                  title: CowStr::Borrowed(""),
+                  id: CowStr::Borrowed(""),
+               }),
+               offset,
+            ));
+            resolved.push(Event::Basic(
+               CmarkEvent::Text(label.unwrap_or_else(|| title.into())),
+               offset,
+            ));
+            resolved.push(Event::Basic(CmarkEvent::End(TagEnd::Link), offset));
+         }
+
+         Form::Scheme {
+            link_type,
+            title: link_title,
+            id,
+         } => {
+            let offset = Some(link.offset);
+            resolved.push(Event::Basic(
+               CmarkEvent::Start(Tag::Link {
+                  link_type,
+                  dest_url: url.into(),
+                  title: link_title,
+                  id,
+               }),
+               offset,
+            ));
+
+            // `[](lx:target)`: use the title of the target as the label.
+            if let Some(Event::Basic(CmarkEvent::End(TagEnd::Link), _)) =
+               events_iter.peek()
+            {
+               resolved.push(Event::Basic(CmarkEvent::Text(title.into()), offset));
+            }
+         }
+      }
+   }
+
+   *events = resolved;
+}
+
+/// Replace the destination of every relative link to another Markdown source file with
+/// its resolved URL, collecting every link which cannot be resolved.
+pub(super) fn resolve_relative<'e>(
+   events: &mut [Event<'e>],
+   footnote_definitions: &mut Definitions<'e>,
+   source: &str,
+   resolve: impl Fn(&str) -> Option<Resolved>,
+) -> Result<(), UnresolvedLinks> {
+   let mut unresolved = Vec::new();
+   let mut rewrite = |dest_url: &mut CowStr<'_>, offset: Option<usize>| {
+      let Some((path, rest)) = relative_source(dest_url) else {
+         return;
+      };
+
+      // Source paths are written URL-encoded, e.g. `jj%20init.md` for `jj init.md`.
+      let decoded = percent_decode_str(path).decode_utf8_lossy();
+      match resolve(&decoded) {
+         Some(Resolved { url, .. }) => *dest_url = format!("{url}{rest}").into(),
+         None => {
+            let (line, column) = line_and_column(source, offset.unwrap_or_default());
+            unresolved.push(UnresolvedLink {
+               target: dest_url.to_string(),
+               line,
+               column,
+            });
+         }
+      }
+   };
+
+   let definitions = footnote_definitions.values_mut().flatten();
+   for event in events.iter_mut().chain(definitions) {
+      if let Event::Basic(CmarkEvent::Start(Tag::Link { dest_url, .. }), offset) = event {
+         rewrite(dest_url, *offset);
+      }
+   }
+
+   if unresolved.is_empty() {
+      Ok(())
+   } else {
+      unresolved.sort_by_key(|link| (link.line, link.column));
+      Err(UnresolvedLinks(unresolved))
+   }
+}
+
+/// If `href` is a relative link to a Markdown source file, e.g. `../intro.md#history`,
+/// its path and whatever follows the path (the query and fragment).
+fn relative_source(href: &str) -> Option<(&str, &str)> {
+   if href.starts_with(['/', '#']) || has_scheme(href) {
+      return None;
+   }
+
+   let (path, rest) = href.split_at(href.find(['?', '#']).unwrap_or(href.len()));
+   path.ends_with(".md").then_some((path, rest))
+}
+
+/// Whether `href` starts with a URL scheme, e.g. `https:` or `mailto:`.
+pub fn has_scheme(href: &str) -> bool {
+   href.split_once(':').is_some_and(|(scheme, _)| {
+      scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+         && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+   })
+}
@@ -0,0 +1,72 @@
+//! Positions in the original source, for errors which need to point at the content
+//! which caused them.
+
+use std::fmt;
+
+/// A line and column in the source, along with that line of the source, so that an
+/// error can show exactly what it is complaining about even in a very long document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+   pub line: usize,
+   pub column: usize,
+   pub snippet: String,
+}
+
+impl Location {
+   pub(super) fn in_source(source: &str, offset: usize) -> Location {
+      let (line, column) = line_and_column(source, offset);
+      let snippet = source.lines().nth(line - 1).unwrap_or_default().to_string();
+      Location {
+         line,
+         column,
+         snippet,
+      }
+   }
+}
+
+/// Formats as `line 3, column 6`, followed by the source line with a marker under the
+/// column:
+///
+/// ```text
+/// line 3, column 6
+///   |
+/// 3 | Bad: $\frac{1}$.
+///   |      ^
+/// ```
+impl fmt::Display for Location {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      let Location {
+         line,
+         column,
+         snippet,
+      } = self;
+
+      let gutter = " ".repeat(line.to_string().len());
+      let indent = " ".repeat(column - 1);
+      writeln!(f, "line {line}, column {column}")?;
+      writeln!(f, "{gutter} |")?;
+      writeln!(f, "{line} | {snippet}")?;
+      write!(f, "{gutter} | {indent}^")
+   }
+}
+
+/// ` at <location>` when there is a location, and nothing otherwise: for errors about
+/// content which may have come from somewhere other than the source.
+pub(super) fn at(location: &Option<Location>) -> String {
+   location
+      .as_ref()
+      .map(|location| format!(" at {location}"))
+      .unwrap_or_default()
+}
+
+/// One-indexed line and column (in characters, not bytes) for a byte offset.
+pub(super) fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+   let before = &source[..offset.min(source.len())];
+   let line = before.matches('\n').count() + 1;
+   let column = before
+      .rsplit('\n')
+      .next()
+      .map_or(0, |line| line.chars().count())
+      + 1;
+   (line, column)
+}
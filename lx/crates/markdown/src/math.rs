@@ -0,0 +1,367 @@
+//! LaTeX math, converted to MathML with `latex2mathml` after expanding any user-defined
+//! macros, since `latex2mathml` does not support `\newcommand` itself.
+//!
+//! Macros are defined as a map from their name (with or without the leading `\`) to
+//! their body, which refers to its arguments as `#1`, `#2`, etc., just as with
+//! `\newcommand`:
+//!
+//! ```yaml
+//! math:
+//!   macros:
+//!     R: \mathbb{R}
+//!     norm: \left\lVert #1 \right\rVert
+//! ```
+
+use std::collections::BTreeMap;
+
+use log::error;
+use pulldown_cmark::{CowStr, Event};
+use pulldown_cmark_escape::escape_html;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::location::{at, Location};
+use super::transform::{Context, Located, Transform};
+
+/// Macros which expand to other macros are expanded in turn, up to this depth, so that a
+/// recursive definition is an error instead of a hang.
+const MAX_DEPTH: usize = 32;
+
+const PARSE_ERROR: &str = "[PARSE ERROR: ";
+
+/// Site- or item-level configuration for math.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Math {
+   pub macros: Macros,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Macros(BTreeMap<String, String>);
+
+impl Macros {
+   /// These macros, with any in `other` added, replacing any with the same name.
+   pub fn extended_by(&self, other: &Macros) -> Macros {
+      let mut macros = self.0.clone();
+      macros.extend(
+         other.0.iter().map(|(name, body)| {
+            (name.trim_start_matches('\\').to_string(), body.clone())
+         }),
+      );
+      Macros(macros)
+   }
+
+   fn get(&self, name: &str) -> Option<&str> {
+      self
+         .0
+         .get(name)
+         .or_else(|| self.0.get(&format!("\\{name}")))
+         .map(String::as_str)
+   }
+
+   fn expand(&self, tex: &str) -> Result<String, MathError> {
+      if self.0.is_empty() {
+         return Ok(tex.to_string());
+      }
+
+      self.expand_at(tex, 0)
+   }
+
+   fn expand_at(&self, tex: &str, depth: usize) -> Result<String, MathError> {
+      let mut expanded = String::with_capacity(tex.len());
+      let mut rest = tex;
+      while let Some(slash) = rest.find('\\') {
+         expanded.push_str(&rest[..slash]);
+         let after = &rest[slash + 1..];
+
+         // A control *symbol* like `\\` or `\{` can never be a macro, and its second
+         // character must not be mistaken for the start of another command.
+         let name_len = after
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(after.len());
+         if name_len == 0 {
+            let symbol_len = after.chars().next().map_or(0, char::len_utf8);
+            expanded.push('\\');
+            expanded.push_str(&after[..symbol_len]);
+            rest = &after[symbol_len..];
+            continue;
+         }
+
+         let name = &after[..name_len];
+         rest = &after[name_len..];
+
+         let Some(body) = self.get(name) else {
+            expanded.push('\\');
+            expanded.push_str(name);
+            continue;
+         };
+
+         if depth >= MAX_DEPTH {
+            return Err(MathError::TooDeep {
+               name: name.to_string(),
+            });
+         }
+
+         let arity = arity(body);
+         let mut arguments = Vec::with_capacity(arity);
+         for _ in 0..arity {
+            let (argument, remaining) =
+               argument(rest).ok_or_else(|| MathError::MissingArgument {
+                  name: name.to_string(),
+                  expected: arity,
+               })?;
+            arguments.push(argument);
+            rest = remaining;
+         }
+
+         let substituted = substitute(body, &arguments);
+         expanded.push_str(&self.expand_at(&substituted, depth + 1)?);
+      }
+
+      expanded.push_str(rest);
+      Ok(expanded)
+   }
+}
+
+/// The number of arguments a macro takes: the highest `#n` in its body.
+fn arity(body: &str) -> usize {
+   body
+      .split('#')
+      .skip(1)
+      .filter_map(|after| after.chars().next()?.to_digit(10))
+      .max()
+      .unwrap_or(0) as usize
+}
+
+/// The next argument in `tex` and everything after it: either a `{…}` group (without the
+/// braces) or a single token.
+fn argument(tex: &str) -> Option<(&str, &str)> {
+   let tex = tex.trim_start();
+   let mut chars = tex.char_indices();
+   match chars.next()? {
+      (_, '{') => {
+         let mut depth = 0;
+         let mut escaped = false;
+         for (index, c) in tex.char_indices() {
+            match c {
+               _ if escaped => escaped = false,
+               '\\' => escaped = true,
+               '{' => depth += 1,
+               '}' => {
+                  depth -= 1;
+                  if depth == 0 {
+                     return Some((&tex[1..index], &tex[index + 1..]));
+                  }
+               }
+               _ => {}
+            }
+         }
+         None
+      }
+
+      (_, '\\') => {
+         let after = &tex[1..];
+         let len = match after.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => after.chars().next().map_or(0, char::len_utf8),
+            Some(len) => len,
+            None => after.len(),
+         };
+         Some((&tex[..1 + len], &tex[1 + len..]))
+      }
+
+      (_, c) => Some((&tex[..c.len_utf8()], &tex[c.len_utf8()..])),
+   }
+}
+
+fn substitute(body: &str, arguments: &[&str]) -> String {
+   let mut substituted = body.to_string();
+   for (index, argument) in arguments.iter().enumerate().rev() {
+      substituted = substituted.replace(&format!("#{}", index + 1), argument);
+   }
+   substituted
+}
+
+/// A math expression from the source, with the byte offset at which it starts, so that
+/// failures can point to it.
+#[derive(Debug)]
+pub(super) struct Expression<'e> {
+   pub(super) tex: CowStr<'e>,
+   pub(super) display: bool,
+   pub(super) offset: usize,
+}
+
+impl<'e> From<Expression<'e>> for Located<'e> {
+   fn from(expression: Expression<'e>) -> Self {
+      let event = if expression.display {
+         Event::DisplayMath(expression.tex)
+      } else {
+         Event::InlineMath(expression.tex)
+      };
+      Located::new(event, expression.offset)
+   }
+}
+
+/// Converts math to MathML, expanding `macros` first. With `fallback`, expressions which
+/// fail to convert are rendered as highlighted fallback markup (with a warning) instead
+/// of failing.
+pub(super) struct MathML {
+   pub(super) macros: Macros,
+   pub(super) fallback: bool,
+}
+
+impl Transform for MathML {
+   fn transform<'e>(
+      &self,
+      mut events: Vec<Located<'e>>,
+      context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      for located in &mut events {
+         let (tex, display) = match &located.event {
+            Event::InlineMath(tex) => (tex, false),
+            Event::DisplayMath(tex) => (tex, true),
+            _ => continue,
+         };
+
+         let html = match render(tex, display, &self.macros) {
+            Ok(mathml) => mathml,
+
+            Err(reason) => {
+               let html = self.fallback.then(|| fallback(tex, display, &reason));
+               let error = BadMath {
+                  expression: tex.to_string(),
+                  location: located.offset.map(|offset| context.location(offset)),
+                  reason,
+               };
+
+               match html {
+                  Some(html) => {
+                     error!("{error}: {}", error.reason);
+                     html
+                  }
+                  None => return Err(error.into()),
+               }
+            }
+         };
+
+         located.event = Event::InlineHtml(html.into());
+      }
+
+      Ok(events)
+   }
+}
+
+fn render(tex: &str, display: bool, macros: &Macros) -> Result<String, MathError> {
+   let style = if display {
+      latex2mathml::DisplayStyle::Block
+   } else {
+      latex2mathml::DisplayStyle::Inline
+   };
+
+   let expanded = macros.expand(tex)?;
+   let mathml = latex2mathml::latex_to_mathml(&expanded, style)?;
+
+   // Some malformed input is not an error as far as `latex2mathml` is concerned: it
+   // renders a message into the output instead, which should not be published.
+   match mathml.split_once(PARSE_ERROR) {
+      Some((_, rest)) => Err(MathError::Parse {
+         message: rest.split("]</mtext>").next().unwrap_or(rest).to_string(),
+      }),
+      None => Ok(mathml),
+   }
+}
+
+/// The expression as written, highlighted and with the error as its tooltip, so it is
+/// easy to spot and fix while developing.
+fn fallback(tex: &str, display: bool, error: &MathError) -> String {
+   let (class, delimiter) = if display {
+      ("math-error math-display", "$$")
+   } else {
+      ("math-error math-inline", "$")
+   };
+
+   let mut html = format!(r#"<span class="{class}" title=""#);
+   escape(&mut html, &error.to_string());
+   html.push_str(r#"" style="color: #a00; background: #fee; font-family: monospace">"#);
+   html.push_str(delimiter);
+   escape(&mut html, tex);
+   html.push_str(delimiter);
+   html.push_str("</span>");
+   html
+}
+
+fn escape(html: &mut String, text: &str) {
+   escape_html(&mut *html, text).expect("writing to a String cannot fail");
+}
+
+/// A math expression which could not be rendered, and where it is in the source (if it
+/// is known: it is not for math which a transform added).
+#[derive(Error, Debug)]
+#[error("could not render math '{expression}'{}", at(.location))]
+pub struct BadMath {
+   pub expression: String,
+   pub location: Option<Location>,
+   #[source]
+   pub reason: MathError,
+}
+
+#[derive(Error, Debug)]
+pub enum MathError {
+   #[error("macro '\\{name}' expects {expected} arguments")]
+   MissingArgument { name: String, expected: usize },
+
+   #[error("macro '\\{name}' expands too deeply (is it recursive?)")]
+   TooDeep { name: String },
+
+   #[error("could not parse LaTeX: {message}")]
+   Parse { message: String },
+
+   #[error(transparent)]
+   Latex {
+      #[from]
+      source: latex2mathml::LatexError,
+   },
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn macros(definitions: &[(&str, &str)]) -> Macros {
+      Macros(
+         definitions
+            .iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect(),
+      )
+   }
+
+   #[test]
+   fn expands_macros_with_arguments() {
+      let macros = macros(&[
+         ("R", r"\mathbb{R}"),
+         (r"\norm", r"\left\lVert #1 \right\rVert"),
+         ("pair", r"(#1, #2)"),
+      ]);
+
+      assert_eq!(
+         macros
+            .expand(r"\norm{x + \R} = \pair a{\R} \\ \Rx")
+            .unwrap(),
+         r"\left\lVert x + \mathbb{R} \right\rVert = (a, \mathbb{R}) \\ \Rx"
+      );
+   }
+
+   #[test]
+   fn reports_bad_macros() {
+      let macros = macros(&[("loop", r"\loop"), ("pair", "(#1, #2)")]);
+      assert!(matches!(
+         macros.expand(r"\loop"),
+         Err(MathError::TooDeep { .. })
+      ));
+      assert!(matches!(
+         macros.expand(r"\pair{a}"),
+         Err(MathError::MissingArgument { expected: 2, .. })
+      ));
+   }
+}
@@ -0,0 +1,45 @@
+//! The outline of rendered content: its headings, in order, for a table of contents.
+
+use pulldown_cmark::{html, Event, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+
+/// A heading in the content, with its rendered (inline) HTML.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heading {
+   /// 1 for `<h1>` through 6 for `<h6>`.
+   pub level: u8,
+   /// The heading's `id`, if it has one (e.g. from `## Method {#method}`).
+   pub id: Option<String>,
+   pub html: String,
+}
+
+/// Every heading in `events`, in document order.
+pub(super) fn headings(events: &[Event<'_>]) -> Vec<Heading> {
+   let mut headings = Vec::new();
+   let mut current: Option<(u8, Option<String>, Vec<Event>)> = None;
+   for event in events {
+      match (event, &mut current) {
+         (Event::Start(Tag::Heading { level, id, .. }), None) => {
+            current = Some((*level as u8, id.as_deref().map(String::from), vec![]));
+         }
+
+         (Event::End(TagEnd::Heading(_)), Some(_)) => {
+            if let Some((level, id, inner)) = current.take() {
+               let mut heading = String::new();
+               html::push_html(&mut heading, inner.into_iter());
+               headings.push(Heading {
+                  level,
+                  id,
+                  html: heading,
+               });
+            }
+         }
+
+         (event, Some((_, _, inner))) => inner.push(event.clone()),
+
+         (_, None) => {}
+      }
+   }
+
+   headings
+}
@@ -0,0 +1,512 @@
+//! Scripture references, e.g. *John 1:1*, *1 Cor. 13:4–7*, or *Ps 23*: recognized in
+//! content and linked to a reader, with the book names normalized so that every
+//! reference to a book can be found no matter how it was written.
+//!
+//! References are recognized either anywhere in prose ([`Syntax::Bare`]), where book
+//! names must be capitalized as they are normally written, or only between delimiters
+//! ([`Syntax::Delimited`], e.g. `((jn 3:16))`), where the delimiters are removed and
+//! case does not matter. A reference needs a chapter; verses and ranges (including
+//! across chapters, e.g. `John 1:35–2:12`) are optional. References are not recognized
+//! inside links, images, or code.
+
+use std::collections::BTreeMap;
+
+use log::warn;
+use pulldown_cmark::{CowStr, Event as CmarkEvent, LinkType, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::first_pass::{Definitions, Event};
+
+/// How to recognize and link scripture references.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scripture {
+   /// The URL to read a passage at, with `{book}`, `{passage}`, and `{translation}`
+   /// replaced by those of the reference.
+   pub reader: String,
+   /// The translation to read passages in, unless a reference names its own.
+   pub translation: String,
+   pub syntax: Syntax,
+   /// The opening and closing delimiters for [`Syntax::Delimited`].
+   pub delimiters: (String, String),
+   /// Further names for books, e.g. `Qoh: Ecclesiastes`, in addition to the usual names
+   /// and abbreviations.
+   pub books: BTreeMap<String, String>,
+}
+
+impl Default for Scripture {
+   fn default() -> Self {
+      Scripture {
+         reader: String::from(
+            "https://www.biblegateway.com/passage/?search={book}+{passage}&version={translation}",
+         ),
+         translation: String::from("ESV"),
+         syntax: Syntax::Bare,
+         delimiters: (String::from("(("), String::from("))")),
+         books: BTreeMap::new(),
+      }
+   }
+}
+
+/// How references are written in content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Syntax {
+   /// Anywhere in prose, e.g. `as John 1:1 says`.
+   Bare,
+   /// Only between the configured delimiters, e.g. `as ((John 1:1)) says`.
+   Delimited,
+}
+
+/// A reference to a passage, with its book normalized and the URL to read it at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passage {
+   pub book: String,
+   pub passage: String,
+   pub translation: String,
+   pub url: String,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown book of the Bible '{name}'")]
+pub struct UnknownBook {
+   pub name: String,
+}
+
+impl Scripture {
+   /// Normalize a reference given as its parts, e.g. from an item's metadata.
+   ///
+   /// # Errors
+   ///
+   /// When `book` is not a name for any book of the Bible.
+   pub fn passage(
+      &self,
+      book: &str,
+      passage: &str,
+      translation: Option<&str>,
+   ) -> Result<Passage, UnknownBook> {
+      let names = Names::new(self, true);
+      let trimmed = book.trim().trim_end_matches('.');
+      let book = names
+         .names
+         .iter()
+         .find(|(name, _)| name.eq_ignore_ascii_case(trimmed))
+         .map(|(_, book)| book.clone())
+         .ok_or_else(|| UnknownBook {
+            name: book.to_string(),
+         })?;
+
+      Ok(self.link(book, passage.trim(), translation))
+   }
+
+   /// The canonical name of every book, in order.
+   pub fn books() -> impl Iterator<Item = String> {
+      BOOKS.iter().flat_map(|(name, _)| match numbered(name) {
+         Some((count, name)) => (1..=count).map(|n| format!("{n} {name}")).collect(),
+         None => vec![name.to_string()],
+      })
+   }
+
+   fn link(&self, book: String, passage: &str, translation: Option<&str>) -> Passage {
+      let translation = translation.unwrap_or(&self.translation).to_string();
+      let url = self
+         .reader
+         .replace("{book}", &book.replace(' ', "%20"))
+         .replace(
+            "{passage}",
+            &passage.replace(['–', '—'], "-").replace(' ', ""),
+         )
+         .replace("{translation}", &translation);
+
+      Passage {
+         book,
+         passage: passage.to_string(),
+         translation,
+         url,
+      }
+   }
+
+   /// Find every reference in `text`.
+   fn find(&self, text: &str, names: &Names) -> Vec<Found> {
+      match self.syntax {
+         Syntax::Bare => {
+            let mut found = Vec::new();
+            let mut from = 0;
+            while let Some(reference) = names.next(text, from) {
+               from = reference.end;
+               found.push(reference);
+            }
+            found
+         }
+
+         Syntax::Delimited => {
+            let (open, close) = &self.delimiters;
+            let mut found = Vec::new();
+            let mut from = 0;
+            while let Some(start) = text[from..].find(open.as_str()).map(|i| i + from) {
+               let inner_start = start + open.len();
+               let Some(end) = text[inner_start..].find(close.as_str()) else {
+                  break;
+               };
+               let inner = &text[inner_start..inner_start + end];
+               let end = inner_start + end + close.len();
+
+               match names.at(inner.trim(), 0) {
+                  Some(reference) if reference.end == inner.trim().len() => {
+                     found.push(Found {
+                        start,
+                        end,
+                        ..reference
+                     });
+                  }
+                  _ => warn!("Could not read '{inner}' as a scripture reference"),
+               }
+               from = end;
+            }
+            found
+         }
+      }
+   }
+}
+
+/// A reference found in some text, by its byte range there.
+#[derive(Debug)]
+struct Found {
+   start: usize,
+   end: usize,
+   book: String,
+   passage: String,
+   /// The reference as written, without any delimiters.
+   written: String,
+}
+
+/// Every name for every book, longest first so e.g. `1 John` wins over `John`.
+struct Names {
+   names: Vec<(String, String)>,
+   ignore_case: bool,
+}
+
+impl Names {
+   fn new(scripture: &Scripture, ignore_case: bool) -> Names {
+      let mut names = Vec::new();
+      for (name, abbreviations) in BOOKS {
+         match numbered(name) {
+            Some((count, name)) => {
+               let forms = std::iter::once(name).chain(abbreviations.iter().copied());
+               for n in 1..=count {
+                  let book = format!("{n} {name}");
+                  let prefixes = [
+                     format!("{n} "),
+                     format!("{n}"),
+                     format!("{} ", ["I", "II", "III"][n - 1]),
+                     format!("{} ", ["First", "Second", "Third"][n - 1]),
+                  ];
+                  for form in forms.clone() {
+                     for prefix in &prefixes {
+                        names.push((format!("{prefix}{form}"), book.clone()));
+                     }
+                  }
+               }
+            }
+            None => names.extend(
+               std::iter::once(*name)
+                  .chain(abbreviations.iter().copied())
+                  .map(|form| (form.to_string(), name.to_string())),
+            ),
+         }
+      }
+
+      let canonical = Scripture::books().collect::<Vec<_>>();
+      for (name, book) in &scripture.books {
+         match canonical
+            .iter()
+            .find(|canonical| canonical.eq_ignore_ascii_case(book))
+         {
+            Some(book) => names.push((name.clone(), book.clone())),
+            None => {
+               warn!("Ignoring scripture book name '{name}' for unknown book '{book}'")
+            }
+         }
+      }
+
+      names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+      Names { names, ignore_case }
+   }
+
+   /// The first reference at or after `from` which starts a word.
+   fn next(&self, text: &str, from: usize) -> Option<Found> {
+      text[from..]
+         .char_indices()
+         .map(|(index, _)| index + from)
+         .filter(|&index| index == 0 || !is_word(text[..index].chars().last()))
+         .filter(|&index| {
+            text[index..]
+               .starts_with(|c: char| c.is_ascii_uppercase() || c.is_ascii_digit())
+         })
+         .find_map(|index| self.at(text, index))
+   }
+
+   /// A reference starting exactly at `start`, if there is one.
+   fn at(&self, text: &str, start: usize) -> Option<Found> {
+      let rest = &text[start..];
+      self.names.iter().find_map(|(name, book)| {
+         let candidate = rest.get(..name.len())?;
+         let matches = if self.ignore_case {
+            candidate.eq_ignore_ascii_case(name)
+         } else {
+            candidate == name
+         };
+         if !matches {
+            return None;
+         }
+
+         let after_name = rest[name.len()..]
+            .strip_prefix('.')
+            .unwrap_or(&rest[name.len()..]);
+         let after_space = after_name.trim_start_matches([' ', '\u{a0}']);
+         if after_space.len() == after_name.len() {
+            return None;
+         }
+
+         let length = passage_length(after_space)?;
+         let passage = &after_space[..length];
+         let end = start + (rest.len() - after_space.len()) + length;
+         Some(Found {
+            start,
+            end,
+            book: book.clone(),
+            passage: passage.to_string(),
+            written: text[start..end].to_string(),
+         })
+      })
+   }
+}
+
+/// The length of the passage at the start of `text`: `chapter[:verse][–chapter[:verse]]`.
+fn passage_length(text: &str) -> Option<usize> {
+   let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+
+   let mut length = digits(text);
+   if length == 0 {
+      return None;
+   }
+
+   if let Some(rest) = text[length..].strip_prefix(':') {
+      match digits(rest) {
+         0 => {}
+         verse => length += 1 + verse,
+      }
+   }
+
+   let rest = &text[length..];
+   if let Some(dash) = rest.chars().next().filter(|c| ['-', '–', '—'].contains(c)) {
+      let after_dash = &rest[dash.len_utf8()..];
+      let end = digits(after_dash);
+      if end > 0 {
+         let mut range = dash.len_utf8() + end;
+         if let Some(verse) = after_dash[end..].strip_prefix(':') {
+            match digits(verse) {
+               0 => {}
+               verse => range += 1 + verse,
+            }
+         }
+         length += range;
+      }
+   }
+
+   (!is_word(text[length..].chars().next())).then_some(length)
+}
+
+fn is_word(c: Option<char>) -> bool {
+   c.is_some_and(char::is_alphanumeric)
+}
+
+/// Replace every reference in the text of `events` and the footnote definitions with a
+/// link to read it, returning each distinct passage in the order it first appears.
+pub(super) fn link(
+   events: &mut Vec<Event<'_>>,
+   footnote_definitions: &mut Definitions<'_>,
+   scripture: &Scripture,
+) -> Vec<Passage> {
+   let names = Names::new(scripture, scripture.syntax == Syntax::Delimited);
+   let mut passages = Vec::new();
+   link_in(events, scripture, &names, &mut passages);
+
+   let mut names_in_order = footnote_definitions.keys().cloned().collect::<Vec<_>>();
+   names_in_order.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+   for name in names_in_order {
+      if let Some(definition) = footnote_definitions.get_mut(&name) {
+         link_in(definition, scripture, &names, &mut passages);
+      }
+   }
+
+   passages
+}
+
+fn link_in(
+   events: &mut Vec<Event<'_>>,
+   scripture: &Scripture,
+   names: &Names,
+   passages: &mut Vec<Passage>,
+) {
+   let mut linked = Vec::with_capacity(events.len());
+   let mut excluded = 0usize;
+   for event in std::mem::take(events) {
+      match event {
+         Event::Basic(CmarkEvent::Text(text), offset) if excluded == 0 => {
+            let pieces = pieces(&text, scripture, names, passages);
+            linked.extend(pieces.into_iter().map(|event| Event::Basic(event, offset)));
+         }
+
+         Event::Basic(ref cmark, _) => {
+            excluded = track_exclusion(cmark, excluded);
+            linked.push(event);
+         }
+
+         event => linked.push(event),
+      }
+   }
+   *events = linked;
+}
+
+fn track_exclusion(event: &CmarkEvent, excluded: usize) -> usize {
+   match event {
+      CmarkEvent::Start(Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_)) => {
+         excluded + 1
+      }
+      CmarkEvent::End(TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock) => {
+         excluded.saturating_sub(1)
+      }
+      _ => excluded,
+   }
+}
+
+/// The events for some text with every reference in it linked.
+fn pieces(
+   text: &CowStr<'_>,
+   scripture: &Scripture,
+   names: &Names,
+   passages: &mut Vec<Passage>,
+) -> Vec<CmarkEvent<'static>> {
+   let found = scripture.find(text, names);
+   if found.is_empty() {
+      return vec![CmarkEvent::Text(text.to_string().into())];
+   }
+
+   let mut pieces = Vec::new();
+   let mut rest_start = 0;
+   for Found {
+      start,
+      end,
+      book,
+      passage,
+      written,
+   } in found
+   {
+      if start > rest_start {
+         pieces.push(CmarkEvent::Text(text[rest_start..start].to_string().into()));
+      }
+
+      let passage = scripture.link(book, &passage, None);
+      pieces.push(CmarkEvent::Start(Tag::Link {
+         link_type: LinkType::Inline,
+         dest_url: passage.url.clone().into(),
+         title: format!(
+            "{} {} ({})",
+            passage.book, passage.passage, passage.translation
+         )
+         .into(),
+         id: CowStr::Borrowed(""),
+      }));
+      pieces.push(CmarkEvent::Text(written.into()));
+      pieces.push(CmarkEvent::End(TagEnd::Link));
+
+      if !passages.contains(&passage) {
+         passages.push(passage);
+      }
+      rest_start = end;
+   }
+
+   if rest_start < text.len() {
+      pieces.push(CmarkEvent::Text(text[rest_start..].to_string().into()));
+   }
+
+   pieces
+}
+
+/// For numbered books (e.g. `Samuel`, for `1 Samuel` and `2 Samuel`), how many there are
+/// and the name without the number.
+fn numbered(name: &str) -> Option<(usize, &str)> {
+   name.split_once(' ').and_then(|(count, name)| match count {
+      "2" => Some((2, name)),
+      "3" => Some((3, name)),
+      _ => None,
+   })
+}
+
+/// Every book, in order, with its usual abbreviations. Numbered books are listed once,
+/// with how many there are (e.g. `2 Samuel` stands for both `1 Samuel` and `2 Samuel`).
+/// Abbreviations which are also common English words (e.g. `Is` or `Am`) are left out.
+const BOOKS: &[(&str, &[&str])] = &[
+   ("Genesis", &["Gen", "Gn"]),
+   ("Exodus", &["Exod", "Exo"]),
+   ("Leviticus", &["Lev", "Lv"]),
+   ("Numbers", &["Num", "Nm", "Nb"]),
+   ("Deuteronomy", &["Deut", "Dt"]),
+   ("Joshua", &["Josh", "Jsh"]),
+   ("Judges", &["Judg", "Jdg", "Jdgs"]),
+   ("Ruth", &["Rth"]),
+   ("2 Samuel", &["Sam", "Sm"]),
+   ("2 Kings", &["Kgs", "Kin"]),
+   ("2 Chronicles", &["Chron", "Chr"]),
+   ("Ezra", &["Ezr"]),
+   ("Nehemiah", &["Neh"]),
+   ("Esther", &["Esth", "Est"]),
+   ("Job", &["Jb"]),
+   ("Psalms", &["Psalm", "Ps", "Pss", "Psa"]),
+   ("Proverbs", &["Prov", "Prv", "Pr"]),
+   ("Ecclesiastes", &["Eccl", "Eccles", "Ecc", "Qoheleth"]),
+   (
+      "Song of Songs",
+      &["Song of Solomon", "Song", "Sg", "Canticles"],
+   ),
+   ("Isaiah", &["Isa"]),
+   ("Jeremiah", &["Jer", "Jr"]),
+   ("Lamentations", &["Lam"]),
+   ("Ezekiel", &["Ezek", "Ezk"]),
+   ("Daniel", &["Dan", "Dn"]),
+   ("Hosea", &["Hos"]),
+   ("Joel", &["Jl"]),
+   ("Amos", &[]),
+   ("Obadiah", &["Obad"]),
+   ("Jonah", &["Jon", "Jnh"]),
+   ("Micah", &["Mic", "Mc"]),
+   ("Nahum", &["Nah"]),
+   ("Habakkuk", &["Hab", "Hb"]),
+   ("Zephaniah", &["Zeph", "Zep"]),
+   ("Haggai", &["Hag", "Hg"]),
+   ("Zechariah", &["Zech", "Zec"]),
+   ("Malachi", &["Mal", "Ml"]),
+   ("Matthew", &["Matt", "Mt"]),
+   ("Mark", &["Mk", "Mrk"]),
+   ("Luke", &["Lk", "Luk"]),
+   ("John", &["Jn", "Jhn"]),
+   ("Acts", &[]),
+   ("Romans", &["Rom", "Rm"]),
+   ("2 Corinthians", &["Cor"]),
+   ("Galatians", &["Gal"]),
+   ("Ephesians", &["Eph", "Ephes"]),
+   ("Philippians", &["Phil", "Php"]),
+   ("Colossians", &["Col"]),
+   ("2 Thessalonians", &["Thess", "Thes"]),
+   ("2 Timothy", &["Tim", "Tm"]),
+   ("Titus", &["Tit"]),
+   ("Philemon", &["Phlm", "Philem", "Phm"]),
+   ("Hebrews", &["Heb"]),
+   ("James", &["Jas", "Jm"]),
+   ("2 Peter", &["Pet", "Pt"]),
+   ("3 John", &["Jn", "Jhn"]),
+   ("Jude", &["Jud", "Jd"]),
+   ("Revelation", &["Rev", "Apocalypse"]),
+];
@@ -1,382 +1,456 @@
+use std::collections::HashMap;
+
 use log::error;
-use pulldown_cmark::{CodeBlockKind, CowStr, Tag, TagEnd};
-use syntect::html::{ClassStyle, ClassedHTMLGenerator};
-use syntect::parsing::SyntaxSet;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html_body_text;
 use thiserror::Error;
 
 use super::first_pass;
-use super::FootnoteDefinitions;
-
-/// The second pass through the events is responsible for three tasks:
-///
-/// 1. Applying syntax highlighting.
-/// 2. Properly emitting footnotes.
-/// 3. Performing any template-language-type rewriting of text nodes.
-struct State<'e, 's> {
-   footnote_definitions: FootnoteDefinitions<'e>,
-   syntax_set: &'s SyntaxSet,
-   code_block: Option<CodeBlock<'e, 's>>,
-   events: Vec<pulldown_cmark::Event<'e>>,
-   emitted_definitions: Vec<(CowStr<'e>, Vec<pulldown_cmark::Event<'e>>)>,
+use super::footnotes::{self, Footnote, Note};
+use super::links::{self, Form};
+use super::location::{at, Location};
+use super::outline::{self, Heading};
+use super::shortcodes::{self, ExpandError, Shortcode};
+use super::transform::{Context, Located, Transform};
+
+/// Stands in for the excerpt marker while transforms run, so that it stays in the
+/// right place relative to the events around it.
+const EXCERPT_MARKER: &str = "<!-- lx:excerpt -->";
+
+/// Separates the text nodes when they are rewritten together. No template should ever
+/// produce (or consume) it, since it uses ASCII's own "unit separator".
+const BOUNDARY: &str = "\u{1F}lx:text\u{1F}";
+
+/// The events for the full content, the (balanced) events for its excerpt, and the
+/// outline of the content: its headings and footnotes.
+pub(super) struct Output<'e> {
+   pub(super) content: std::vec::IntoIter<Event<'e>>,
+   pub(super) excerpt: Vec<Event<'e>>,
+   pub(super) toc: Vec<Heading>,
+   pub(super) footnotes: Vec<Footnote>,
+   /// What the text with template syntax in it was rewritten to.
+   pub(super) rewritten: Rewritten,
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
-   #[error("cannot finish a code block we never started")]
-   FinishedNonStartedCodeBlock,
-
-   #[error("all footnote references are handled in the first pass but {0} is provided to the second pass")]
-   UnhandledFootnoteReference(String),
-
-   #[error("syntax highlighting failure")]
-   BadSyntaxLine { source: syntect::Error },
-
-   #[error("bad LaTeX input")]
-   BadLatex {
-      #[from]
-      source: latex2mathml::LatexError,
+   #[error(transparent)]
+   Transform {
+      source: Box<dyn std::error::Error + Send + Sync>,
    },
 
-   #[error("Could not rewrite text")]
+   #[error("Could not rewrite text{}", at(.location))]
    Rewrite {
       source: Box<dyn std::error::Error + Send + Sync>,
       original: String,
+      location: Option<Location>,
+   },
+
+   #[error(transparent)]
+   Shortcode {
+      #[from]
+      source: ExpandError,
    },
 }
 
+/// The second pass through the (already [lowered](lower)) events is responsible for
+/// four tasks:
+///
+/// 1. Running each transform over them in turn.
+/// 2. Performing any template-language-type rewriting of text nodes.
+/// 3. Expanding shortcodes.
+/// 4. Splitting out the excerpt, using the explicit marker if there is one.
 pub(super) fn second_pass<'e>(
-   footnote_definitions: FootnoteDefinitions<'e>,
-   syntax_set: &SyntaxSet,
-   events: Vec<first_pass::Event<'e>>,
-   rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
-) -> Result<impl Iterator<Item = pulldown_cmark::Event<'e>>, Error> {
-   let mut state = State {
-      footnote_definitions,
-      syntax_set,
-      code_block: None,
-      events: vec![],
-      emitted_definitions: vec![],
-   };
+   source: &str,
+   events: Vec<Located<'e>>,
+   transforms: &[&dyn Transform],
+   excerpt_paragraphs: usize,
+   rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+   shortcodes: &[Shortcode],
+   expand: &impl Fn(
+      &Shortcode,
+      &str,
+   ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<Output<'e>, Error> {
+   let Transformed {
+      mut events,
+      appendix,
+      footnotes: notes,
+   } = run(source, events, transforms)?;
+   let content_len = events.len();
+   events.extend(appendix);
+   let (mut content, rewritten) = rewrite_text(source, events, rewrite)?;
+   let appendix = content.split_off(content_len);
+   let mut content = shortcodes::expand(source, content, shortcodes, expand)?;
+
+   let excerpt_end = content.iter().position(is_excerpt_marker);
+   content.retain(|event| !is_excerpt_marker(event));
+
+   let excerpt_end =
+      excerpt_end.unwrap_or_else(|| end_of_paragraph(&content, excerpt_paragraphs));
+   let excerpt = excerpt(&content[..excerpt_end]);
+   let toc = outline::headings(&content);
+   let footnotes = footnotes::render(&appendix, &notes);
+
+   content.extend(appendix);
+   Ok(Output {
+      content: content.into_iter(),
+      excerpt,
+      toc,
+      footnotes,
+      rewritten,
+   })
+}
 
-   for event in events {
-      // If I ever extract/generalize this, I will want to use some kind of log level
-      // handling instead of just always emitting the error.
-      if let Some(warning) = state.handle(event, &rewrite)? {
-         error!("{warning}");
-      }
+/// The events for a document after every transform has run.
+pub(super) struct Transformed<'e> {
+   pub(super) events: Vec<Located<'e>>,
+   /// Anything the transforms appended after the content.
+   pub(super) appendix: Vec<Located<'e>>,
+   /// Where the footnotes are in the appendix.
+   pub(super) footnotes: Vec<Note>,
+}
+
+/// Run each transform in turn.
+pub(super) fn run<'e>(
+   source: &str,
+   mut events: Vec<Located<'e>>,
+   transforms: &[&dyn Transform],
+) -> Result<Transformed<'e>, Error> {
+   let mut context = Context::new(source);
+   for transform in transforms {
+      events = transform
+         .transform(events, &mut context)
+         .map_err(|source| Error::Transform { source })?;
    }
 
-   Ok(state.into_iter())
+   Ok(Transformed {
+      events,
+      appendix: context.appendix,
+      footnotes: context.footnotes,
+   })
 }
 
-impl<'e> State<'e, '_> {
-   /// Returns `Some(String)` when it could successfully emit an event but there was
-   /// something unexpected about it, e.g. a footnote with a missing definition.
-   fn handle(
-      &mut self,
-      event: first_pass::Event<'e>,
-      rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
-   ) -> Result<Option<String>, Error> {
-      use pulldown_cmark::Event::*;
+pub(super) fn is_excerpt_marker(event: &Event) -> bool {
+   matches!(event, Event::Html(html) if html.as_ref() == EXCERPT_MARKER)
+}
 
-      match event {
-         first_pass::Event::Basic(basic) => match basic {
-            Text(text) => {
-               // We do *not* want to rewrite text in code blocks!
-               match self.code_block {
-                  Some(ref mut code_block) => {
-                     code_block.highlight(&text)?;
-                     Ok(None)
-                  }
-                  None => {
-                     let rewritten =
-                        rewrite(text.as_ref()).map_err(|source| Error::Rewrite {
-                           source,
-                           original: text.to_string(),
-                        })?;
-                     self.events.push(Html(rewritten.into()));
-                     Ok(None)
-                  }
-               }
-            }
+/// Convert the events from the first pass to plain `pulldown_cmark` events, with the
+/// footnote definitions after the content.
+///
+/// Anything still here which is not a plain `pulldown_cmark` event was never resolved
+/// (e.g. when rendering a single file outside of a site), so emit it as it was written,
+/// with a warning.
+pub(super) fn lower<'e>(
+   events: Vec<first_pass::Event<'e>>,
+   footnote_definitions: first_pass::Definitions<'e>,
+) -> Vec<Located<'e>> {
+   let mut lowered = events.into_iter().map(lower_one).collect::<Vec<_>>();
+   for (name, definition) in footnote_definitions {
+      lowered.push(Located::from(Event::Start(Tag::FootnoteDefinition(name))));
+      lowered.extend(definition.into_iter().map(lower_one));
+      lowered.push(Located::from(Event::End(TagEnd::FootnoteDefinition)));
+   }
 
-            Start(Tag::CodeBlock(kind)) => {
-               self.code_block = Some(CodeBlock::start(kind, self.syntax_set));
-               Ok(None)
-            }
+   lowered
+}
 
-            End(TagEnd::CodeBlock) => match self.code_block.take() {
-               Some(code_block) => {
-                  self.events.append(&mut code_block.end());
-                  Ok(None)
-               }
-               None => Err(Error::FinishedNonStartedCodeBlock),
-            },
-
-            DisplayMath(content) => {
-               let math = latex2mathml::latex_to_mathml(
-                  content.as_ref(),
-                  latex2mathml::DisplayStyle::Block,
-               )?;
-               self.events.push(Html(math.into()));
-               Ok(None)
-            }
+fn lower_one(event: first_pass::Event<'_>) -> Located<'_> {
+   // If I ever extract/generalize this, I will want to use some kind of log level
+   // handling instead of just always emitting the error.
+   match event {
+      first_pass::Event::Basic(event, offset) => Located { event, offset },
 
-            InlineMath(content) => {
-               let math = latex2mathml::latex_to_mathml(
-                  content.as_ref(),
-                  latex2mathml::DisplayStyle::Inline,
-               )?;
-               self.events.push(Html(math.into()));
-               Ok(None)
-            }
+      first_pass::Event::FootnoteReference(name) => {
+         Located::from(Event::FootnoteReference(name))
+      }
 
-            // If we find a footnote reference here, something has gone wrong: we should
-            // have handled them all during `first_pass`.
-            FootnoteReference(name) => {
-               Err(Error::UnhandledFootnoteReference(name.to_string()))
-            }
+      first_pass::Event::Math(expression) => Located::from(expression),
 
-            // Everything else can just be emitted exactly as is.
-            other => {
-               self.events.push(other.clone());
-               Ok(None)
-            }
-         },
-
-         first_pass::Event::FootnoteReference(name) => {
-            if let Some(definition) = self.footnote_definitions.get(&name) {
-               self.emitted_definitions.push((name, definition.clone()));
-               let index = self.emitted_definitions.len();
-               let link = format!(
-                  r##"<sup><a href="#{name}" id="{backref}">{index}</a></sup>"##,
-                  name = footnote_ref_name(index),
-                  backref = footnote_backref_name(index),
-               );
-
-               self.events.push(Html(link.into()));
-               Ok(None)
-            } else {
-               let event = Text(format!("[^{name}]").into());
-               self.events.push(event);
-               Ok(Some(format!(
-                  "Missing definition for footnote labeled '{name}'"
-               )))
-            }
-         }
+      first_pass::Event::ExcerptMarker => {
+         Located::from(Event::Html(EXCERPT_MARKER.into()))
       }
-   }
-}
 
-#[inline]
-fn footnote_ref_name(index: usize) -> String {
-   format!("fn{index}")
-}
+      // Links are only left here when there was no site to resolve them against.
+      first_pass::Event::InternalLink(link) => {
+         error!("Unresolved internal link '{link}'");
+         let event = match link.form {
+            Form::Wiki { .. } => Event::Text(link.to_string().into()),
+            Form::Scheme {
+               link_type,
+               title,
+               id,
+            } => Event::Start(Tag::Link {
+               link_type,
+               dest_url: format!("{}{}", links::SCHEME, link.target).into(),
+               title,
+               id,
+            }),
+         };
+         Located::new(event, link.offset)
+      }
+
+      // Likewise for citations when there is no bibliography to format them with.
+      first_pass::Event::Citation(citation) => {
+         error!("Unformatted citation '{citation}'");
+         Located::new(Event::Text(citation.to_string().into()), citation.offset)
+      }
 
-#[inline]
-fn footnote_backref_name(index: usize) -> String {
-   format!("fnref{index}")
+      // Numbering replaces every reference, and fails on every label it cannot attach.
+      first_pass::Event::CrossReference(_) | first_pass::Event::Label(_) => {
+         unreachable!("cross-references and labels are all handled when numbering")
+      }
+   }
 }
 
-impl<'e> std::iter::IntoIterator for State<'e, '_> {
-   type Item = pulldown_cmark::Event<'e>;
-   type IntoIter = std::vec::IntoIter<pulldown_cmark::Event<'e>>;
-
-   fn into_iter(self) -> Self::IntoIter {
-      use pulldown_cmark::Event::*;
-
-      let mut events = self.events;
-
-      if !self.emitted_definitions.is_empty() {
-         events.push(Rule);
-         events.push(Html(
-            r#"<section class="footnotes"><ol class="footnotes-list">"#.into(),
-         ));
-
-         for (index, _, mut definition_events) in self
-            .emitted_definitions
-            .into_iter()
-            .enumerate()
-            .map(|(index, (name, evts))| (index + 1, name, evts))
-         {
-            events.push(Html(format!(r#"<li id="fn{index}">"#).into()));
-
-            let backref = Html(
-               format!(
-                  r##"<a href="#{backref}" class="fn-backref">↩</a>"##,
-                  backref = footnote_backref_name(index)
-               )
-               .into(),
-            );
-
-            if let Some(End(TagEnd::Paragraph)) = definition_events.last() {
-               let p = definition_events.pop().unwrap();
-               definition_events.push(backref);
-               definition_events.push(p);
-               events.append(&mut definition_events);
-            } else {
-               events.append(&mut definition_events);
-               events.push(backref);
-            }
+/// Rewrite every text node with template syntax in it, producing exactly one event for
+/// each event in `events`, along with what each node was rewritten to. Text in code
+/// blocks is never rewritten: highlighting has already turned it into HTML by now.
+///
+/// The escaping contract: text without template syntax stays text, which is escaped when
+/// it is written as HTML. Text with template syntax has everything *outside* its
+/// template tags escaped before it is rewritten, and the result of rewriting it is
+/// written as-is, i.e. as HTML. It is up to the rewriter to escape whatever it inserts
+/// (e.g. with auto-escaping) unless that is meant to be HTML.
+///
+/// The text nodes are rewritten all at once, joined by a [`BOUNDARY`], as one template:
+/// a template engine only has to parse one template for the whole document, and e.g. a
+/// variable set in one node is available in the nodes after it. If that fails, or the
+/// rewritten text does not split back into the same number of nodes (because a tag in
+/// one node closes a block opened in another), the error points at the first node where
+/// it does.
+fn rewrite_text<'e>(
+   source: &str,
+   events: Vec<Located<'e>>,
+   rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<(Vec<Event<'e>>, Rewritten), Error> {
+   let templates = events
+      .iter()
+      .filter_map(|located| match &located.event {
+         Event::Text(text) if is_template(text) => Some(Template {
+            original: text.to_string(),
+            escaped: escape_outside_tags(text),
+            offset: located.offset,
+         }),
+         _ => None,
+      })
+      .collect::<Vec<_>>();
+
+   let rewritten = if templates.is_empty() {
+      vec![]
+   } else if templates.iter().any(|t| t.escaped.contains(BOUNDARY)) {
+      // Only possible if the source itself has a `BOUNDARY` in it, which no real
+      // document does, so it is not worth a dedicated error.
+      templates
+         .iter()
+         .map(|t| rewrite_one(source, &t.original, t.offset, rewrite))
+         .collect::<Result<Vec<_>, _>>()?
+   } else {
+      let joined = join(&templates);
+      let rewritten = rewrite(&joined).map_err(|reason| {
+         locate_failure(source, &templates, rewrite).unwrap_or(Error::Rewrite {
+            source: reason,
+            original: joined,
+            location: None,
+         })
+      })?;
+
+      let rewritten = rewritten
+         .split(BOUNDARY)
+         .map(String::from)
+         .collect::<Vec<_>>();
+      if rewritten.len() != templates.len() {
+         return Err(
+            locate_failure(source, &templates, rewrite).unwrap_or_else(|| {
+               Error::Rewrite {
+                  source: SPANNING.into(),
+                  original: templates[0].original.clone(),
+                  location: templates[0]
+                     .offset
+                     .map(|offset| Location::in_source(source, offset)),
+               }
+            }),
+         );
+      }
+      rewritten
+   };
 
-            events.push(End(TagEnd::Item));
+   let rewritten = Rewritten(
+      templates
+         .into_iter()
+         .zip(rewritten)
+         .map(|(template, rewritten)| ((template.offset, template.original), rewritten))
+         .collect(),
+   );
+
+   let events = events
+      .into_iter()
+      .map(|located| match located.event {
+         // `InlineHtml` rather than `Html` because they are written the same way,
+         // except in image `alt` text, where `Html` is dropped.
+         Event::Text(text) if is_template(&text) => {
+            let html = rewritten
+               .get(located.offset, &text)
+               .expect("one for each template")
+               .to_string();
+            Event::InlineHtml(CowStr::from(html))
          }
+         event => event,
+      })
+      .collect();
 
-         events.push(Html("</ol></section>".into()));
-      }
+   Ok((events, rewritten))
+}
 
-      events.into_iter()
-   }
+const SPANNING: &str = "a template tag in this text closes a block opened in other text";
+
+/// A text node with template syntax in it.
+struct Template {
+   original: String,
+   /// Escaped outside its template tags, ready to rewrite.
+   escaped: String,
+   offset: Option<usize>,
 }
 
-#[derive(Debug)]
-struct CodeBlock<'e, 's> {
-   highlighting: Highlighting<'s>,
-   syntax_set: Option<&'s SyntaxSet>,
-   events: Vec<pulldown_cmark::Event<'e>>,
+fn join(templates: &[Template]) -> String {
+   templates
+      .iter()
+      .map(|t| t.escaped.as_str())
+      .collect::<Vec<_>>()
+      .join(BOUNDARY)
 }
 
-impl<'c, 's> CodeBlock<'c, 's> {
-   /// Start highlighting a code block.
-   fn start(kind: CodeBlockKind, syntax_set: &'s SyntaxSet) -> Self {
-      match kind {
-         CodeBlockKind::Fenced(name) => {
-            let found = syntax_set.find_syntax_by_token(name.as_ref());
-            let (html, highlighting) = if let Some(syntax) = found {
-               (
-                  pulldown_cmark::Event::Html(
-                     format!("<pre><code class='{}'>", syntax.name).into(),
-                  ),
-                  Highlighting::KnownSyntax(ClassedHTMLGenerator::new_with_class_style(
-                     syntax,
-                     syntax_set,
-                     ClassStyle::Spaced,
-                  )),
-               )
-            } else {
-               (
-                  pulldown_cmark::Event::Html("<pre><code>".into()),
-                  Highlighting::UnknownSyntax,
-               )
-            };
-
-            CodeBlock {
-               highlighting,
-               syntax_set: Some(syntax_set),
-               events: vec![html],
-            }
-         }
-         CodeBlockKind::Indented => CodeBlock {
-            highlighting: Highlighting::RequiresFirstLineParse,
-            syntax_set: Some(syntax_set),
-            events: vec![],
-         },
-      }
+/// Find the first text node where rewriting fails, by rewriting ever longer runs of the
+/// nodes (so each node still sees everything before it, as it does in the whole).
+fn locate_failure(
+   source: &str,
+   templates: &[Template],
+   rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Option<Error> {
+   (1..=templates.len()).find_map(|end| {
+      let reason = match rewrite(&join(&templates[..end])) {
+         Ok(rewritten) if rewritten.split(BOUNDARY).count() == end => return None,
+         Ok(_) => SPANNING.into(),
+         Err(reason) => reason,
+      };
+
+      let failed = &templates[end - 1];
+      Some(Error::Rewrite {
+         source: reason,
+         original: failed.original.clone(),
+         location: failed
+            .offset
+            .map(|offset| Location::in_source(source, offset)),
+      })
+   })
+}
+
+/// What each text node with template syntax in it was rewritten to (as HTML), by where
+/// it is in the source and what it was, so that gemtext can use the same results.
+pub(super) struct Rewritten(HashMap<(Option<usize>, String), String>);
+
+impl Rewritten {
+   pub(super) fn get(&self, offset: Option<usize>, text: &str) -> Option<&str> {
+      self.0.get(&(offset, text.to_string())).map(String::as_str)
    }
+}
 
-   /// Produces events when:
-   ///
-   /// - starting a new code block
-   /// - ending a code block
-   ///
-   /// Note that it does *not* emit events while highlighting a line. Instead, it stores
-   /// internal state which produces a single fully-rendered HTML event when complete.
-   fn highlight(&mut self, text: &CowStr<'c>) -> Result<(), Error> {
-      let mut handle_unknown = || {
-         self
-            .events
-            .push(pulldown_cmark::Event::Text(text.to_owned()))
+/// HTML-escape `text`, except inside template tags (`{{ … }}`, `{% … %}`, and
+/// `{# … #}`), where escaping would change the meaning of the template. An unclosed tag
+/// is left as it is, for the template engine to report.
+fn escape_outside_tags(text: &str) -> String {
+   let mut escaped = String::with_capacity(text.len());
+   let mut rest = text;
+   while let Some(start) = rest.find('{') {
+      let close = match rest[start..].get(..2) {
+         Some("{{") => "}}",
+         Some("{%") => "%}",
+         Some("{#") => "#}",
+         _ => {
+            escape_html_body_text(&mut escaped, &rest[..=start])
+               .expect("writing to a String cannot fail");
+            rest = &rest[start + 1..];
+            continue;
+         }
       };
 
-      let Some(syntax_set) = self.syntax_set else {
-         handle_unknown();
-         return Ok(());
-      };
+      escape_html_body_text(&mut escaped, &rest[..start])
+         .expect("writing to a String cannot fail");
+      let end = rest[start + 2..]
+         .find(close)
+         .map_or(rest.len(), |end| start + 2 + end + close.len());
+      escaped.push_str(&rest[start..end]);
+      rest = &rest[end..];
+   }
 
-      match self.highlighting {
-         Highlighting::RequiresFirstLineParse => {
-            match syntax_set.find_syntax_by_first_line(text) {
-               // If Syntect has a definition, emit processed HTML for the wrapper
-               // and for the first line.
-               Some(definition) => {
-                  let mut generator = ClassedHTMLGenerator::new_with_class_style(
-                     definition,
-                     syntax_set,
-                     ClassStyle::Spaced,
-                  );
-                  let event = pulldown_cmark::Event::Html(
-                     format!(
-                        "<pre lang='{name}'><code class='{name}'>",
-                        name = definition.name
-                     )
-                     .into(),
-                  );
-                  generator
-                     .parse_html_for_line_which_includes_newline(text)
-                     .map_err(|e| Error::BadSyntaxLine { source: e })?;
-                  self.highlighting = Highlighting::KnownSyntax(generator);
-                  self.events.push(event);
-                  Ok(())
-               }
+   escape_html_body_text(&mut escaped, rest).expect("writing to a String cannot fail");
+   escaped
+}
 
-               // Otherwise, we treat this as a code block, but with no syntax
-               // highlighting applied.
-               None => {
-                  self.highlighting = Highlighting::UnknownSyntax;
-                  let event = pulldown_cmark::Event::Html(
-                     (String::from("<pre><code>") + text).into(),
-                  );
-                  self.events.push(event);
-                  Ok(())
-               }
-            }
-         }
+/// Whether text has any template syntax in it, and so needs rewriting. Transforms which
+/// change text leave such text alone, so the template still sees what was written.
+pub(super) fn is_template(text: &str) -> bool {
+   ["{{", "{%", "{#"]
+      .iter()
+      .any(|delimiter| text.contains(delimiter))
+}
 
-         // This is a little quirky: it hands off the text to the highlighter and
-         // relies on correctly calling `highlighter.finalize()` when we reach the
-         // end of the code block.
-         // TODO: consider type-state-ifying that, too!
-         Highlighting::KnownSyntax(ref mut generator) => {
-            generator
-               .parse_html_for_line_which_includes_newline(text.as_ref())
-               .map_err(|e| Error::BadSyntaxLine { source: e })?;
-
-            // ...and therefore produces no events!
-            Ok(())
-         }
+/// Rewrite a single text node which starts at `offset` in the source on its own, with
+/// the same escaping as when the nodes are rewritten together.
+pub(super) fn rewrite_one(
+   source: &str,
+   text: &str,
+   offset: Option<usize>,
+   rewrite: &impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<String, Error> {
+   rewrite(&escape_outside_tags(text)).map_err(|reason| Error::Rewrite {
+      source: reason,
+      original: text.to_string(),
+      location: offset.map(|offset| Location::in_source(source, offset)),
+   })
+}
 
-         Highlighting::UnknownSyntax => {
-            handle_unknown();
-            Ok(())
+/// The events up to the end of the excerpt. Any tags still open at the end are closed,
+/// so a marker in the middle of e.g. a list is still valid.
+fn excerpt<'e>(events: &[Event<'e>]) -> Vec<Event<'e>> {
+   let mut open = Vec::new();
+   let mut excerpt = Vec::with_capacity(events.len());
+   for event in events {
+      match event {
+         Event::Start(tag) => open.push(tag.to_end()),
+         Event::End(_) => {
+            open.pop();
          }
+         _ => {}
       }
+      excerpt.push(event.clone());
    }
 
-   /// Finish a code block, consuming the state and producing a single `Event::Html`
-   /// as its result.
-   fn end(mut self) -> Vec<pulldown_cmark::Event<'c>> {
-      let end_html = match self.highlighting {
-         Highlighting::KnownSyntax(generator) => generator.finalize() + "</code></pre>",
-         _ => "</code></pre>".to_string(),
-      };
-      let end_event = pulldown_cmark::Event::Html(end_html.into());
-      self.events.push(end_event);
-      self.events
-   }
+   excerpt.extend(open.into_iter().rev().map(Event::End));
+   excerpt
 }
 
-enum Highlighting<'s> {
-   RequiresFirstLineParse,
-   UnknownSyntax,
-   KnownSyntax(ClassedHTMLGenerator<'s>),
-}
+/// The index just past the end of the `nth` top-level paragraph, or the end of the
+/// events if there are not that many paragraphs.
+fn end_of_paragraph(events: &[Event<'_>], nth: usize) -> usize {
+   let mut depth = 0usize;
+   let mut seen = 0;
+   for (index, event) in events.iter().enumerate() {
+      if seen == nth {
+         return index;
+      }
 
-impl std::fmt::Debug for Highlighting<'_> {
-   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-      match self {
-         Self::RequiresFirstLineParse => write!(f, "RequiresFirstLineParse"),
-         Self::UnknownSyntax => write!(f, "UnknownSyntax"),
-         Self::KnownSyntax(_) => write!(f, "KnownSyntax"),
+      match event {
+         Event::Start(_) => depth += 1,
+         Event::End(end) => {
+            depth -= 1;
+            if depth == 0 && *end == TagEnd::Paragraph {
+               seen += 1;
+            }
+         }
+         _ => {}
       }
    }
+
+   events.len()
 }
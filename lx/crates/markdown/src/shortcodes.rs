@@ -0,0 +1,322 @@
+//! Shortcodes: block-level components which wrap Markdown content.
+//!
+//! ```markdown
+//! {% callout "warning" %}
+//! This is *still* Markdown, and can span paragraphs.
+//!
+//! Like this one.
+//! {% endcallout %}
+//! ```
+//!
+//! The opening and closing tags must each be a paragraph of their own, in the same
+//! block (e.g. the same list item), and shortcodes may nest. The content between them is
+//! rendered as usual, and then passed along with the shortcode's name and arguments to
+//! the caller to expand (see
+//! [`Markdown::emit_with_shortcodes`](crate::Markdown::emit_with_shortcodes)). Template
+//! tags like `{% if … %}` are never shortcodes.
+
+use pulldown_cmark::{html, Event as CmarkEvent, TagEnd};
+use thiserror::Error;
+
+use super::first_pass::Event;
+use super::location::Location;
+
+/// Stand in for the start and end of each shortcode while transforms run.
+const START: &str = "<!-- lx:shortcode ";
+const END: &str = "<!-- lx:endshortcode ";
+
+/// Template tags which can never be shortcode names.
+const TEMPLATE_TAGS: [&str; 19] = [
+   "autoescape",
+   "block",
+   "break",
+   "call",
+   "continue",
+   "do",
+   "elif",
+   "else",
+   "extends",
+   "filter",
+   "for",
+   "from",
+   "if",
+   "import",
+   "include",
+   "macro",
+   "raw",
+   "set",
+   "with",
+];
+
+/// A shortcode in the content, e.g. `{% callout "warning" %}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortcode {
+   pub name: String,
+   /// The arguments as written, e.g. `"warning"`, to be interpreted by the caller.
+   pub arguments: String,
+   offset: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum ShortcodeError {
+   #[error("shortcode '{name}' is never closed with '{{% end{name} %}}' (opened at {location})")]
+   Unclosed { name: String, location: Location },
+
+   #[error("'{{% end{name} %}}' does not close any open shortcode at {location}")]
+   Unopened { name: String, location: Location },
+}
+
+#[derive(Error, Debug)]
+#[error("could not expand shortcode '{name}' at {location}")]
+pub struct ExpandError {
+   pub name: String,
+   pub location: Location,
+   source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// Find every shortcode, replacing the paragraphs which open and close it with markers
+/// for [`expand`] to find after transforms run.
+pub(super) fn find(
+   events: &mut Vec<Event<'_>>,
+   source: &str,
+) -> Result<Vec<Shortcode>, ShortcodeError> {
+   let mut shortcodes = Vec::<Shortcode>::new();
+   // Where each tag is in `events`: its index, how many events it spans, and the marker
+   // to replace them with.
+   let mut tags = Vec::new();
+   // The index of each open shortcode, and the block depth it opened at.
+   let mut open = Vec::<(usize, usize)>::new();
+   let mut depth = 0usize;
+   let mut index = 0;
+   while index < events.len() {
+      if let Some((tag, length)) = tag_at(events, index, source) {
+         match tag {
+            Tag::Open {
+               name,
+               arguments,
+               offset,
+            } => {
+               open.push((shortcodes.len(), depth));
+               tags.push((index, length, marker(START, shortcodes.len())));
+               shortcodes.push(Shortcode {
+                  name,
+                  arguments,
+                  offset,
+               });
+            }
+
+            Tag::Close { name, offset } => match open.last() {
+               Some(&(opened, at)) if at == depth && shortcodes[opened].name == name => {
+                  open.pop();
+                  tags.push((index, length, marker(END, opened)));
+               }
+               _ => {
+                  return Err(ShortcodeError::Unopened {
+                     name,
+                     location: Location::in_source(source, offset),
+                  })
+               }
+            },
+         }
+
+         index += length;
+         continue;
+      }
+
+      match &events[index] {
+         Event::Basic(CmarkEvent::Start(tag), _) if !is_inline(tag) => depth += 1,
+         Event::Basic(CmarkEvent::End(tag), _) if !is_inline_end(tag) => {
+            depth = depth.saturating_sub(1);
+            // The block a shortcode opened in has ended without closing it.
+            if let Some(&(opened, at)) = open.last() {
+               if at > depth {
+                  return Err(unclosed(&shortcodes[opened], source));
+               }
+            }
+         }
+         _ => {}
+      }
+      index += 1;
+   }
+
+   if let Some(&(opened, _)) = open.last() {
+      return Err(unclosed(&shortcodes[opened], source));
+   }
+
+   if !tags.is_empty() {
+      let mut tags = tags.into_iter().peekable();
+      let mut skip = 0;
+      let mut replaced = Vec::with_capacity(events.len());
+      for (index, event) in std::mem::take(events).into_iter().enumerate() {
+         if let Some((_, length, marker)) = tags.next_if(|&(at, ..)| at == index) {
+            replaced.push(marker);
+            skip = length;
+         }
+
+         if skip > 0 {
+            skip -= 1;
+         } else {
+            replaced.push(event);
+         }
+      }
+      *events = replaced;
+   }
+
+   Ok(shortcodes)
+}
+
+/// Replace each shortcode, from the innermost out, with the result of `expand`, which
+/// gets the shortcode and the HTML for its content.
+pub(super) fn expand<'e>(
+   source: &str,
+   events: Vec<CmarkEvent<'e>>,
+   shortcodes: &[Shortcode],
+   expand: &impl Fn(
+      &Shortcode,
+      &str,
+   ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<Vec<CmarkEvent<'e>>, ExpandError> {
+   if shortcodes.is_empty() {
+      return Ok(events);
+   }
+
+   let mut expanded = Vec::with_capacity(events.len());
+   // The content of each shortcode being expanded, innermost last.
+   let mut open = Vec::<(usize, Vec<CmarkEvent>)>::new();
+   for event in events {
+      if let Some(index) = marker_index(&event, START) {
+         open.push((index, Vec::new()));
+         continue;
+      }
+
+      let closed = marker_index(&event, END).and_then(|_| open.pop());
+      let event = match closed {
+         Some((index, content)) => {
+            let shortcode = &shortcodes[index];
+            let mut inner = String::new();
+            html::push_html(&mut inner, content.into_iter());
+            let html = expand(shortcode, &inner).map_err(|reason| ExpandError {
+               name: shortcode.name.clone(),
+               location: Location::in_source(source, shortcode.offset),
+               source: reason,
+            })?;
+            CmarkEvent::Html(format!("{}\n", html.trim_end()).into())
+         }
+         None => event,
+      };
+
+      match open.last_mut() {
+         Some((_, content)) => content.push(event),
+         None => expanded.push(event),
+      }
+   }
+
+   Ok(expanded)
+}
+
+fn unclosed(shortcode: &Shortcode, source: &str) -> ShortcodeError {
+   ShortcodeError::Unclosed {
+      name: shortcode.name.clone(),
+      location: Location::in_source(source, shortcode.offset),
+   }
+}
+
+fn marker(kind: &str, index: usize) -> Event<'static> {
+   Event::Basic(CmarkEvent::Html(format!("{kind}{index} -->").into()), None)
+}
+
+fn marker_index(event: &CmarkEvent, kind: &str) -> Option<usize> {
+   match event {
+      CmarkEvent::Html(html) => {
+         html.strip_prefix(kind)?.strip_suffix(" -->")?.parse().ok()
+      }
+      _ => None,
+   }
+}
+
+enum Tag {
+   Open {
+      name: String,
+      arguments: String,
+      offset: usize,
+   },
+   Close {
+      name: String,
+      offset: usize,
+   },
+}
+
+/// The shortcode tag at `index`, if there is one, and how many events it spans: it must
+/// be a paragraph of plain text which is exactly `{% name arguments %}` or
+/// `{% endname %}` in the source.
+fn tag_at(events: &[Event<'_>], index: usize, source: &str) -> Option<(Tag, usize)> {
+   let Event::Basic(CmarkEvent::Start(pulldown_cmark::Tag::Paragraph), Some(offset)) =
+      events[index]
+   else {
+      return None;
+   };
+
+   let mut length = 1;
+   loop {
+      match events.get(index + length)? {
+         Event::Basic(CmarkEvent::Text(_), _) => length += 1,
+         Event::Basic(CmarkEvent::End(TagEnd::Paragraph), _) => break,
+         _ => return None,
+      }
+   }
+
+   let line = source[offset..].lines().next()?.trim();
+   let inner = line.strip_prefix("{%")?.strip_suffix("%}")?.trim();
+   if inner.contains("%}") {
+      return None;
+   }
+
+   let name_end = inner
+      .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+      .unwrap_or(inner.len());
+   let (name, arguments) = inner.split_at(name_end);
+   let arguments = arguments.trim();
+   let tag = match name.strip_prefix("end") {
+      Some(name) if !name.is_empty() && arguments.is_empty() => Tag::Close {
+         name: name.to_string(),
+         offset,
+      },
+      _ => Tag::Open {
+         name: name.to_string(),
+         arguments: arguments.to_string(),
+         offset,
+      },
+   };
+
+   let name = match &tag {
+      Tag::Open { name, .. } | Tag::Close { name, .. } => name,
+   };
+   if name.is_empty() || TEMPLATE_TAGS.contains(&name.as_str()) {
+      return None;
+   }
+
+   Some((tag, length + 1))
+}
+
+fn is_inline(tag: &pulldown_cmark::Tag) -> bool {
+   use pulldown_cmark::Tag;
+   matches!(
+      tag,
+      Tag::Emphasis
+         | Tag::Strong
+         | Tag::Strikethrough
+         | Tag::Link { .. }
+         | Tag::Image { .. }
+   )
+}
+
+fn is_inline_end(tag: &TagEnd) -> bool {
+   matches!(
+      tag,
+      TagEnd::Emphasis
+         | TagEnd::Strong
+         | TagEnd::Strikethrough
+         | TagEnd::Link
+         | TagEnd::Image
+   )
+}
@@ -0,0 +1,284 @@
+//! Terms of art: abbreviations defined in a document, and a glossary for a whole site.
+//!
+//! Abbreviations use the PHP Markdown Extra syntax, in a paragraph of their own:
+//!
+//! ```markdown
+//! *[HTML]: HyperText Markup Language
+//! *[CSS]: Cascading Style Sheets
+//! ```
+//!
+//! The definitions are removed, and every use of an abbreviation in the document becomes
+//! `<abbr title="…">`. Glossary terms are matched regardless of (ASCII) case, but only
+//! their first use in each document is marked, and it also links to its definition.
+//!
+//! Neither is marked inside links, images, or code, nor in any text containing template
+//! syntax (`{{`, `{%`, or `{#`), since splitting that text would break the template.
+//! Glossary terms are not linked from headings.
+
+use std::collections::{BTreeMap, HashSet};
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_html, escape_html_body_text};
+use serde::{Deserialize, Serialize};
+
+use super::second_pass::is_template;
+use super::transform::{Context, Located, Transform};
+
+/// Terms, each with its definition, to mark on their first use in every document, with a
+/// link to where they are defined. See [`Markdown::with_glossary`](crate::Markdown).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Glossary {
+   /// The URL of the page listing every term: links go to the term's [anchor] there.
+   ///
+   /// [anchor]: Glossary::anchor
+   pub url: String,
+   pub terms: BTreeMap<String, String>,
+}
+
+impl Glossary {
+   /// The `id` for a term on the glossary page, e.g. `term-means-of-grace`.
+   pub fn anchor(term: &str) -> String {
+      let mut anchor = String::from("term");
+      for word in term
+         .split(|c: char| !c.is_alphanumeric())
+         .filter(|word| !word.is_empty())
+      {
+         anchor.push('-');
+         anchor.push_str(&word.to_lowercase());
+      }
+      anchor
+   }
+}
+
+impl Transform for Glossary {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let matcher = Matcher::new(&self.terms, true);
+      let mut used = HashSet::new();
+      Ok(mark(events, true, |text| {
+         let found = matcher.find(text, |term| !used.contains(term))?;
+         used.insert(found.term);
+         let definition = &self.terms[found.term];
+         let mut html = format!(
+            r#"<a href="{}#{}" class="glossary-term"><abbr title=""#,
+            self.url,
+            Glossary::anchor(found.term)
+         );
+         escape_html(&mut html, definition).expect("writing to a String cannot fail");
+         html.push_str(r#"">"#);
+         escape_html_body_text(&mut html, &text[found.start..found.end])
+            .expect("writing to a String cannot fail");
+         html.push_str("</abbr></a>");
+         Some((found.start, found.end, html))
+      }))
+   }
+}
+
+/// The abbreviations defined in one document.
+pub(super) struct Abbreviations(BTreeMap<String, String>);
+
+impl Transform for Abbreviations {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      if self.0.is_empty() {
+         return Ok(events);
+      }
+
+      let matcher = Matcher::new(&self.0, false);
+      Ok(mark(events, false, |text| {
+         let found = matcher.find(text, |_| true)?;
+         let mut html = String::from(r#"<abbr title=""#);
+         escape_html(&mut html, &self.0[found.term])
+            .expect("writing to a String cannot fail");
+         html.push_str(r#"">"#);
+         escape_html_body_text(&mut html, &text[found.start..found.end])
+            .expect("writing to a String cannot fail");
+         html.push_str("</abbr>");
+         Some((found.start, found.end, html))
+      }))
+   }
+}
+
+/// Remove every paragraph which consists only of abbreviation definitions, returning
+/// the remaining events and the abbreviations they defined.
+pub(super) fn extract_abbreviations(
+   events: Vec<Located<'_>>,
+) -> (Vec<Located<'_>>, Abbreviations) {
+   let mut abbreviations = BTreeMap::new();
+   let mut rest = Vec::with_capacity(events.len());
+   let mut paragraph: Option<Vec<Located>> = None;
+   for located in events {
+      match (&located.event, paragraph.as_mut()) {
+         (Event::Start(Tag::Paragraph), None) => paragraph = Some(vec![located]),
+
+         (Event::End(TagEnd::Paragraph), Some(_)) => {
+            let mut events = paragraph.take().unwrap_or_default();
+            match definitions(&events) {
+               Some(defined) => abbreviations.extend(defined),
+               None => {
+                  events.push(located);
+                  rest.append(&mut events);
+               }
+            }
+         }
+
+         (_, Some(events)) => events.push(located),
+         (_, None) => rest.push(located),
+      }
+   }
+
+   if let Some(mut unfinished) = paragraph {
+      rest.append(&mut unfinished);
+   }
+
+   (rest, Abbreviations(abbreviations))
+}
+
+/// The definitions in a paragraph (starting with its `Start` event), if every line of it
+/// is one.
+fn definitions(paragraph: &[Located<'_>]) -> Option<Vec<(String, String)>> {
+   let mut text = String::new();
+   for located in &paragraph[1..] {
+      match &located.event {
+         Event::Text(content) => text.push_str(content),
+         Event::SoftBreak | Event::HardBreak => text.push('\n'),
+         _ => return None,
+      }
+   }
+
+   text
+      .lines()
+      .map(|line| {
+         let (abbreviation, title) = line.trim().strip_prefix("*[")?.split_once("]:")?;
+         let (abbreviation, title) = (abbreviation.trim(), title.trim());
+         (!abbreviation.is_empty()).then(|| (abbreviation.into(), title.into()))
+      })
+      .collect()
+}
+
+/// Replace the first match `find` reports in each text node (and then in the rest of it,
+/// and so on) with the HTML it supplies.
+fn mark(
+   events: Vec<Located<'_>>,
+   skip_headings: bool,
+   mut find: impl FnMut(&str) -> Option<(usize, usize, String)>,
+) -> Vec<Located<'_>> {
+   let mut marked = Vec::with_capacity(events.len());
+   let mut excluded = 0usize;
+   for located in events {
+      let offset = located.offset;
+      match located.event {
+         Event::Start(ref tag) if excludes(tag, skip_headings) => {
+            excluded += 1;
+            marked.push(located);
+         }
+
+         Event::End(ref tag) if ends_exclusion(tag, skip_headings) => {
+            excluded = excluded.saturating_sub(1);
+            marked.push(located);
+         }
+
+         Event::Text(text) if excluded == 0 && !is_template(&text) => {
+            let mut rest: &str = &text;
+            while let Some((start, end, html)) = find(rest) {
+               if start > 0 {
+                  marked.push(Located {
+                     event: Event::Text(CowStr::from(rest[..start].to_string())),
+                     offset,
+                  });
+               }
+               marked.push(Located {
+                  event: Event::InlineHtml(html.into()),
+                  offset,
+               });
+               rest = &rest[end..];
+            }
+
+            if !rest.is_empty() {
+               marked.push(Located {
+                  event: Event::Text(CowStr::from(rest.to_string())),
+                  offset,
+               });
+            }
+         }
+
+         event => marked.push(Located { event, offset }),
+      }
+   }
+
+   marked
+}
+
+fn excludes(tag: &Tag, skip_headings: bool) -> bool {
+   match tag {
+      Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_) => true,
+      Tag::Heading { .. } => skip_headings,
+      _ => false,
+   }
+}
+
+fn ends_exclusion(tag: &TagEnd, skip_headings: bool) -> bool {
+   match tag {
+      TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock => true,
+      TagEnd::Heading(_) => skip_headings,
+      _ => false,
+   }
+}
+
+struct Matcher<'t> {
+   /// Longest first, so that e.g. "means of grace" wins over "grace".
+   terms: Vec<&'t str>,
+   ignore_case: bool,
+}
+
+struct Found<'t> {
+   term: &'t str,
+   start: usize,
+   end: usize,
+}
+
+impl<'t> Matcher<'t> {
+   fn new(terms: &'t BTreeMap<String, String>, ignore_case: bool) -> Matcher<'t> {
+      let mut terms = terms.keys().map(String::as_str).collect::<Vec<_>>();
+      terms.sort_by_key(|term| std::cmp::Reverse(term.len()));
+      Matcher { terms, ignore_case }
+   }
+
+   /// The first whole-word use of any term for which `wanted` is true.
+   fn find(&self, text: &str, mut wanted: impl FnMut(&str) -> bool) -> Option<Found<'t>> {
+      let starts = text
+         .char_indices()
+         .filter(|&(index, _)| index == 0 || !is_word(text[..index].chars().last()));
+
+      for (start, _) in starts {
+         for &term in &self.terms {
+            let end = start + term.len();
+            let Some(candidate) = text.get(start..end) else {
+               continue;
+            };
+
+            let matches = if self.ignore_case {
+               candidate.eq_ignore_ascii_case(term)
+            } else {
+               candidate == term
+            };
+
+            if matches && !is_word(text[end..].chars().next()) && wanted(term) {
+               return Some(Found { term, start, end });
+            }
+         }
+      }
+
+      None
+   }
+}
+
+fn is_word(c: Option<char>) -> bool {
+   c.is_some_and(char::is_alphanumeric)
+}
@@ -0,0 +1,49 @@
+//! Plain text from rendered HTML, for places which do not allow markup: feed summaries,
+//! gemtext, the `text` of `lx md --format json`, and so on.
+
+/// Elements whose contents are not prose, e.g. the CSS and drawing of an `svgbob`
+/// diagram, and so are dropped along with their tags.
+const SKIPPED: [&str; 2] = ["style", "svg"];
+
+/// A deliberately simple conversion from HTML to plain text: tags removed, along with the
+/// contents of `<style>` and `<svg>` elements, and the entities `pulldown_cmark` and `lx`
+/// produce decoded. It does not try to be a real HTML parser, but the HTML in question is
+/// always HTML we generated ourselves. Whitespace is left as is, so callers converting a
+/// whole document will usually want to trim the result.
+pub fn strip_tags(html: &str) -> String {
+   let mut text = String::with_capacity(html.len());
+   let mut rest = html;
+   while let Some(start) = rest.find('<') {
+      text.push_str(&rest[..start]);
+      let tag = &rest[start..];
+      let Some(end) = tag.find('>') else {
+         rest = "";
+         break;
+      };
+
+      let name = tag[1..end]
+         .split(|c: char| c.is_whitespace() || c == '/')
+         .next()
+         .unwrap_or_default();
+      let self_closing = tag[..end].ends_with('/');
+      rest = match SKIPPED
+         .iter()
+         .find(|skipped| name.eq_ignore_ascii_case(skipped))
+      {
+         Some(skipped) if !self_closing => {
+            let close = format!("</{skipped}>");
+            tag.find(&close).map_or("", |at| &tag[at + close.len()..])
+         }
+         _ => &tag[end + 1..],
+      };
+   }
+   text.push_str(rest);
+
+   text
+      .replace("&lt;", "<")
+      .replace("&gt;", ">")
+      .replace("&quot;", "\"")
+      .replace("&#39;", "'")
+      .replace("&#x27;", "'")
+      .replace("&amp;", "&")
+}
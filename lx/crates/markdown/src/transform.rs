@@ -0,0 +1,91 @@
+//! The public extension point for rendering: a [`Transform`] observes and rewrites the
+//! stream of `pulldown_cmark` events for a document, after lx's own Markdown extensions
+//! (internal links, citations, cross-references, etc.) have been resolved and before
+//! the result is written as HTML.
+//!
+//! Syntax highlighting, math, and footnotes are all implemented as transforms. Any
+//! registered with [`Markdown::with_transform`](crate::Markdown::with_transform) run
+//! first, in the order they were registered, so they see code blocks, math, and
+//! footnote references and definitions exactly as they were written. Template
+//! rewriting happens after every transform, so text is still plain `Text` here.
+
+use pulldown_cmark::Event;
+
+use super::footnotes::Note;
+use super::location::Location;
+
+/// Observes and rewrites the events for a whole document.
+pub trait Transform: Send + Sync {
+   /// Transform the document's `events`, returning the events to pass on to the next
+   /// transform. Use `context` to locate events in the source and to add events after
+   /// the main content.
+   ///
+   /// # Errors
+   ///
+   /// Any error fails rendering the document.
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// An event, with the byte offset in the source where it starts, if it came from the
+/// source at all rather than from an earlier transform.
+#[derive(Debug, Clone)]
+pub struct Located<'e> {
+   pub event: Event<'e>,
+   pub offset: Option<usize>,
+}
+
+impl<'e> Located<'e> {
+   pub fn new(event: Event<'e>, offset: usize) -> Located<'e> {
+      Located {
+         event,
+         offset: Some(offset),
+      }
+   }
+}
+
+impl<'e> From<Event<'e>> for Located<'e> {
+   fn from(event: Event<'e>) -> Self {
+      Located {
+         event,
+         offset: None,
+      }
+   }
+}
+
+/// Everything about the document being transformed other than its events.
+pub struct Context<'c, 'e> {
+   source: &'c str,
+   pub(super) appendix: Vec<Located<'e>>,
+   /// Where the built-in footnotes transform put each note in the appendix.
+   pub(super) footnotes: Vec<Note>,
+}
+
+impl<'c, 'e> Context<'c, 'e> {
+   pub(super) fn new(source: &'c str) -> Context<'c, 'e> {
+      Context {
+         source,
+         appendix: vec![],
+         footnotes: vec![],
+      }
+   }
+
+   /// The original Markdown source of the document.
+   pub fn source(&self) -> &str {
+      self.source
+   }
+
+   /// Where a byte offset is in the source, for reporting errors.
+   pub fn location(&self, offset: usize) -> Location {
+      Location::in_source(self.source, offset)
+   }
+
+   /// Add events after the main content of the document, e.g. notes. These are never
+   /// part of its excerpt, and later transforms do not see them.
+   pub fn append(&mut self, events: impl IntoIterator<Item = Located<'e>>) {
+      self.appendix.extend(events);
+   }
+}
@@ -0,0 +1,171 @@
+//! URLs in the rendered output: marking links to other sites (e.g. with
+//! `rel="noopener"` or a class for styling), and making every relative URL absolute for
+//! content which will be read somewhere other than its page, like a feed or an email.
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use super::links::has_scheme;
+use super::transform::{Context, Located, Transform};
+
+/// Attributes to add to every link to another site. A link is to another site if it is
+/// an `http` or `https` URL with a different host than the site's own (see
+/// [`Markdown::with_site_url`](crate::Markdown::with_site_url)).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExternalLinks {
+   /// E.g. `noopener noreferrer`.
+   pub rel: Option<String>,
+   /// E.g. `external`.
+   pub class: Option<String>,
+}
+
+pub(super) struct MarkExternal<'a> {
+   pub(super) attributes: &'a ExternalLinks,
+   /// The host of the site itself, if known.
+   pub(super) site_host: Option<String>,
+}
+
+impl Transform for MarkExternal<'_> {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let ExternalLinks { rel, class } = self.attributes;
+      if rel.is_none() && class.is_none() {
+         return Ok(events);
+      }
+
+      let mut marked = Vec::with_capacity(events.len());
+      let mut in_external = false;
+      for located in events {
+         let event = match located.event {
+            Event::Start(Tag::Link {
+               ref dest_url,
+               ref title,
+               ..
+            }) if self.is_external(dest_url) => {
+               in_external = true;
+               let mut html = String::from(r#"<a href=""#);
+               escape_href(&mut html, dest_url).expect("writing to a String cannot fail");
+               html.push('"');
+               let attributes = [
+                  ("title", Some(title.as_ref())),
+                  ("rel", rel.as_deref()),
+                  ("class", class.as_deref()),
+               ];
+               for (name, value) in attributes {
+                  if let Some(value) = value.filter(|value| !value.is_empty()) {
+                     html.push_str(&format!(r#" {name}=""#));
+                     escape_html(&mut html, value)
+                        .expect("writing to a String cannot fail");
+                     html.push('"');
+                  }
+               }
+               html.push('>');
+               Event::InlineHtml(html.into())
+            }
+
+            Event::End(TagEnd::Link) if in_external => {
+               in_external = false;
+               Event::InlineHtml("</a>".into())
+            }
+
+            event => event,
+         };
+
+         marked.push(Located {
+            event,
+            offset: located.offset,
+         });
+      }
+
+      Ok(marked)
+   }
+}
+
+impl MarkExternal<'_> {
+   fn is_external(&self, dest_url: &str) -> bool {
+      let Ok(url) = Url::parse(dest_url) else {
+         return false;
+      };
+
+      matches!(url.scheme(), "http" | "https")
+         && match (url.host_str(), &self.site_host) {
+            (Some(host), Some(site)) => !host.eq_ignore_ascii_case(site),
+            (host, None) => host.is_some(),
+            (None, Some(_)) => false,
+         }
+   }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid page URL '{url}'")]
+pub struct InvalidUrl {
+   pub url: String,
+   source: url::ParseError,
+}
+
+pub(super) fn parse(url: &str) -> Result<Url, InvalidUrl> {
+   Url::parse(url).map_err(|source| InvalidUrl {
+      url: url.to_string(),
+      source,
+   })
+}
+
+/// Make every relative URL in an `href` or `src` attribute in `html` absolute, resolving
+/// it against `base`. Only attributes inside tags are touched: any `<` in text has been
+/// escaped, so every `<` in the HTML starts a tag.
+pub(super) fn absolute(html: &str, base: &Url) -> String {
+   let mut absolute = String::with_capacity(html.len());
+   let mut rest = html;
+   while let Some(start) = rest.find('<') {
+      let end = rest[start..]
+         .find('>')
+         .map_or(rest.len(), |end| start + end + 1);
+      absolute.push_str(&rest[..start]);
+      absolute.push_str(&absolute_in_tag(&rest[start..end], base));
+      rest = &rest[end..];
+   }
+   absolute.push_str(rest);
+   absolute
+}
+
+fn absolute_in_tag(tag: &str, base: &Url) -> String {
+   let mut rewritten = String::with_capacity(tag.len());
+   let mut rest = tag;
+   while let Some((start, quote)) = next_url_attribute(rest) {
+      let Some(length) = rest[start..].find(quote) else {
+         break;
+      };
+
+      let value = &rest[start..start + length];
+      rewritten.push_str(&rest[..start]);
+      match base.join(value) {
+         Ok(url) if !has_scheme(value) => rewritten.push_str(url.as_str()),
+         _ => rewritten.push_str(value),
+      }
+      rest = &rest[start + length..];
+   }
+   rewritten.push_str(rest);
+   rewritten
+}
+
+/// Where the (quoted) value of the next `href` or `src` attribute starts, and its quote.
+fn next_url_attribute(tag: &str) -> Option<(usize, char)> {
+   [" href=", " src="]
+      .into_iter()
+      .filter_map(|attribute| {
+         let start = tag.find(attribute)? + attribute.len();
+         let quote = tag[start..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))?;
+         Some((start + 1, quote))
+      })
+      .min_by_key(|(start, _)| *start)
+}
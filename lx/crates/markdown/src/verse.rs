@@ -0,0 +1,142 @@
+//! Poetry: fenced `verse` (or `poem`) blocks keep their line breaks, indentation, and
+//! stanza breaks, which Markdown would otherwise collapse.
+//!
+//! ````markdown
+//! ```verse
+//! Whose woods these are I think I know.
+//!    His house is in the *village* though;
+//!
+//! He will not see me stopping here
+//! ```
+//! ````
+//!
+//! becomes a `<div class="verse">` of `<p class="stanza">`s, each line a
+//! `<span class="line">`, with `style="--indent: N"` for a line indented by `N` spaces
+//! (tabs count as four) so stylesheets can set hanging indents. Each line is still
+//! inline Markdown, so emphasis, links, code, and math all work; a line which would be a
+//! block on its own (e.g. `- item` or `# heading`) is kept as written.
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+
+use super::transform::{Context, Located, Transform};
+use super::OPTIONS;
+
+/// The fence names which mark a block as verse.
+const FENCES: [&str; 2] = ["verse", "poem"];
+
+pub(super) struct Verse;
+
+impl Transform for Verse {
+   fn transform<'e>(
+      &self,
+      events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let mut transformed = Vec::with_capacity(events.len());
+      let mut verse: Option<(String, Option<usize>)> = None;
+      for located in events {
+         match (located.event, &mut verse) {
+            (Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(fence))), None)
+               if FENCES.contains(&fence.as_ref()) =>
+            {
+               verse = Some((String::new(), located.offset));
+            }
+
+            (Event::Text(text), Some((source, _))) => source.push_str(&text),
+
+            (Event::End(TagEnd::CodeBlock), Some(_)) => {
+               if let Some((source, offset)) = verse.take() {
+                  transformed.extend(
+                     render(&source)
+                        .into_iter()
+                        .map(|event| Located { event, offset }),
+                  );
+               }
+            }
+
+            (event, _) => transformed.push(Located {
+               event,
+               offset: located.offset,
+            }),
+         }
+      }
+
+      Ok(transformed)
+   }
+}
+
+fn render(source: &str) -> Vec<Event<'static>> {
+   let mut events = vec![Event::Html(r#"<div class="verse">"#.into())];
+   for stanza in stanzas(source) {
+      events.push(Event::Html(r#"<p class="stanza">"#.into()));
+      for (index, line) in stanza.iter().enumerate() {
+         if index > 0 {
+            events.push(Event::Html("<br />\n".into()));
+         }
+
+         let content = line.trim_start();
+         let indent = line[..line.len() - content.len()]
+            .chars()
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum::<usize>();
+
+         events.push(Event::Html(
+            match indent {
+               0 => String::from(r#"<span class="line">"#),
+               _ => format!(r#"<span class="line" style="--indent: {indent}">"#),
+            }
+            .into(),
+         ));
+         events.extend(inline(content));
+         events.push(Event::Html("</span>".into()));
+      }
+      events.push(Event::Html("</p>\n".into()));
+   }
+   events.push(Event::Html("</div>\n".into()));
+   events
+}
+
+/// Runs of non-blank lines.
+fn stanzas(source: &str) -> Vec<Vec<&str>> {
+   let mut stanzas = vec![];
+   let mut stanza = vec![];
+   for line in source.lines() {
+      if line.trim().is_empty() {
+         if !stanza.is_empty() {
+            stanzas.push(std::mem::take(&mut stanza));
+         }
+      } else {
+         stanza.push(line.trim_end());
+      }
+   }
+
+   if !stanza.is_empty() {
+      stanzas.push(stanza);
+   }
+
+   stanzas
+}
+
+/// The inline events for a line, or the line as plain text if Markdown would not treat
+/// it as a paragraph.
+fn inline(line: &str) -> Vec<Event<'static>> {
+   let mut events = Parser::new_ext(line, *OPTIONS)
+      .map(Event::into_static)
+      .collect::<Vec<_>>();
+
+   let is_paragraph = matches!(
+      (events.first(), events.last()),
+      (
+         Some(Event::Start(Tag::Paragraph)),
+         Some(Event::End(TagEnd::Paragraph))
+      )
+   ) && events.len() > 2;
+
+   if is_paragraph {
+      events.pop();
+      events.remove(0);
+      events
+   } else {
+      vec![Event::Text(line.to_string().into())]
+   }
+}
@@ -1,49 +1,63 @@
-use std::collections::HashMap;
+use chrono::{DateTime, Datelike, FixedOffset, Month};
 
-use chrono::{Datelike, Month};
-use thiserror::Error;
-
-use crate::page::Page;
+/// Dated items grouped by year and then by month, in date order. Items with the same
+/// date stay in the order they were given in.
+pub struct Archive<T> {
+   pub years: Vec<Year<T>>,
+}
 
-#[allow(dead_code)]
-pub struct Archive<'p>(HashMap<Year, MonthMap<'p>>);
+pub struct Year<T> {
+   pub year: u32,
+   pub months: Vec<MonthItems<T>>,
+}
 
-impl<'e> Archive<'e> {
-   pub fn new(pages: &'e [Page<'e>], order: Order) -> Result<Archive<'e>, Error> {
-      let mut pages = pages
-         .iter()
-         .filter(|page| page.data.date.is_some())
-         .collect::<Vec<&Page>>();
+pub struct MonthItems<T> {
+   pub month: Month,
+   pub items: Vec<T>,
+}
 
-      pages.sort_by(|a, b| {
-         // I just filtered to items which have dates.
-         let a_date = a.data.date.unwrap();
-         let b_date = b.data.date.unwrap();
-         match order {
-            Order::OldFirst => a_date.partial_cmp(&b_date).unwrap(),
-            Order::NewFirst => b_date.partial_cmp(&a_date).unwrap(),
-         }
+impl<T> Archive<T> {
+   pub fn new<I>(items: I, order: Order) -> Archive<T>
+   where
+      I: IntoIterator<Item = (DateTime<FixedOffset>, T)>,
+   {
+      let mut items = items.into_iter().collect::<Vec<_>>();
+      items.sort_by(|(a, _), (b, _)| match order {
+         Order::OldFirst => a.cmp(b),
+         Order::NewFirst => b.cmp(a),
       });
 
-      let mut year_map = HashMap::new();
-
-      for page in pages {
-         if let Some(date) = &page.data.date {
-            let year = date.year_ce().1;
-
-            let month = date.month();
-            let month = Month::try_from(u8::try_from(month).unwrap())
-               .map_err(|source| Error::BadMonth { raw: month, source })?;
-
-            let day = Day::try_from(date.day()).map_err(Error::from)?;
-
-            let month_map = year_map.entry(year).or_insert_with(HashMap::new);
-            let day_map = month_map.entry(month).or_insert_with(HashMap::new);
-            day_map.entry(day).or_insert_with(Vec::new).push(page);
+      let mut years = Vec::<Year<T>>::new();
+      for (date, item) in items {
+         let year = date.year_ce().1;
+         let month = Month::try_from(u8::try_from(date.month()).unwrap())
+            .expect("chrono months are always 1 through 12");
+
+         if years.last().is_none_or(|last| last.year != year) {
+            years.push(Year {
+               year,
+               months: Vec::new(),
+            });
+         }
+         let months = &mut years.last_mut().expect("just pushed a year").months;
+
+         match months.last_mut() {
+            Some(last) if last.month == month => last.items.push(item),
+            _ => months.push(MonthItems {
+               month,
+               items: vec![item],
+            }),
          }
       }
 
-      Ok(Archive(year_map))
+      Archive { years }
+   }
+}
+
+impl<T> Year<T> {
+   /// Every item from the year, in the archive's order.
+   pub fn into_items(self) -> impl Iterator<Item = T> {
+      self.months.into_iter().flat_map(|month| month.items)
    }
 }
 
@@ -52,47 +66,3 @@ pub enum Order {
    OldFirst,
    NewFirst,
 }
-
-#[derive(Debug, Error)]
-pub enum Error {
-   #[error("nonsense month value: '{raw}")]
-   BadMonth {
-      raw: u32,
-      source: chrono::OutOfRange,
-   },
-
-   #[error(transparent)]
-   BadDay {
-      #[from]
-      source: BadDay,
-   },
-}
-
-type Year = u32;
-
-type MonthMap<'p> = HashMap<Month, DayMap<'p>>;
-
-type DayMap<'p> = HashMap<Day, Vec<&'p Page<'p>>>;
-
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub struct Day {
-   raw: u8,
-}
-
-impl TryFrom<u32> for Day {
-   type Error = BadDay;
-
-   fn try_from(value: u32) -> Result<Self, Self::Error> {
-      match value {
-         // SAFETY: this cast will never truncate because 1..=31 < 256.
-         legit @ 1..=31 => Ok(Day { raw: legit as u8 }),
-         wat => Err(BadDay { raw: wat }),
-      }
-   }
-}
-
-#[derive(Debug, Error)]
-#[error("nonsense day value: '{raw}'")]
-pub struct BadDay {
-   raw: u32,
-}
@@ -0,0 +1,206 @@
+//! Compute which pages link to which other pages, from their rendered content. Since
+//! this works on the final HTML, it catches every kind of link to a page: resolved
+//! internal links, hand-written root-relative URLs, and full URLs on the site.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, FixedOffset};
+use lazy_static::lazy_static;
+use lx_md::has_scheme;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{data::config::Config, page::Page};
+
+lazy_static! {
+   static ref HREF: Regex = Regex::new(r#"href=(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/// A page which links to some other page.
+#[derive(Debug, Clone, Serialize)]
+pub struct Backlink {
+   pub title: String,
+   pub url: String,
+   pub date: Option<DateTime<FixedOffset>>,
+}
+
+/// Every page's backlinks, keyed by the root-relative URL of the linked-to page.
+#[derive(Debug, Serialize)]
+pub struct Backlinks(BTreeMap<String, Vec<Backlink>>);
+
+impl Backlinks {
+   pub fn new(pages: &[Page], config: &Config) -> Backlinks {
+      let linking = pages
+         .iter()
+         .map(|page| Linking {
+            url: page.path.root_relative_url(),
+            title: &page.data.title,
+            date: page.data.date,
+            html: page.content.html(),
+         })
+         .collect::<Vec<_>>();
+      Backlinks::collect(&linking, &config.url)
+   }
+
+   fn collect(pages: &[Linking], site_url: &str) -> Backlinks {
+      let by_path = pages
+         .iter()
+         .map(|page| (path_key(&page.url), page))
+         .collect::<HashMap<_, _>>();
+
+      let mut backlinks = BTreeMap::<String, Vec<Backlink>>::new();
+      for page in pages {
+         let mut targets = HREF
+            .captures_iter(page.html)
+            .filter_map(|captures| captures.get(1).or(captures.get(2)))
+            .filter_map(|href| site_path(href.as_str(), &page.url, site_url))
+            .filter_map(|path| by_path.get(path_key(&path).as_str()))
+            .map(|target| target.url.clone())
+            .filter(|target| *target != page.url)
+            .collect::<Vec<_>>();
+
+         targets.sort();
+         targets.dedup();
+
+         for target in targets {
+            backlinks.entry(target).or_default().push(Backlink {
+               title: page.title.to_string(),
+               url: page.url.clone(),
+               date: page.date,
+            });
+         }
+      }
+
+      for links in backlinks.values_mut() {
+         // Newest first, and undated pages (which are usually more "evergreen") last.
+         links.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+      }
+
+      Backlinks(backlinks)
+   }
+
+   pub fn for_page(&self, page: &Page) -> &[Backlink] {
+      self
+         .0
+         .get(&page.path.root_relative_url())
+         .map(Vec::as_slice)
+         .unwrap_or_default()
+   }
+}
+
+/// What backlinks need from each page: where it is, what to call it, and its content.
+struct Linking<'p> {
+   /// The root-relative URL, e.g. `/essays/jj-init/`.
+   url: String,
+   title: &'p str,
+   date: Option<DateTime<FixedOffset>>,
+   html: &'p str,
+}
+
+/// Given an `href` on the page at `page_url`, get the root-relative path it points to if
+/// it points to a page on this site, whether it was written as a full URL, a
+/// root-relative one, or one relative to the page (e.g. `../other/`).
+fn site_path(href: &str, page_url: &str, site_url: &str) -> Option<String> {
+   let href = href
+      .strip_prefix(site_url.trim_end_matches('/'))
+      .unwrap_or(href);
+
+   let path = href.split(['#', '?']).next().unwrap_or_default();
+   if path.starts_with('/') {
+      return (!path.starts_with("//")).then(|| path.to_string());
+   }
+
+   if path.is_empty() || has_scheme(path) {
+      return None;
+   }
+
+   // Page URLs always end with a slash, so relative URLs resolve inside the page's own
+   // directory, as in a browser.
+   let mut segments = page_url
+      .split('/')
+      .filter(|segment| !segment.is_empty())
+      .collect::<Vec<_>>();
+   for segment in path.split('/') {
+      match segment {
+         "" | "." => {}
+         ".." => {
+            segments.pop();
+         }
+         segment => segments.push(segment),
+      }
+   }
+   Some(format!("/{}", segments.join("/")))
+}
+
+fn path_key(path: &str) -> String {
+   path.trim_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const SITE: &str = "https://example.com/";
+
+   #[test]
+   fn hrefs_resolve_to_site_paths() {
+      let page = "/essays/foo/";
+      let resolve = |href| site_path(href, page, SITE);
+      assert_eq!(resolve("/notes/bar/#top").as_deref(), Some("/notes/bar/"));
+      assert_eq!(
+         resolve("https://example.com/notes/bar/?q=1").as_deref(),
+         Some("/notes/bar/")
+      );
+      assert_eq!(resolve("../other/").as_deref(), Some("/essays/other"));
+      assert_eq!(resolve("./child").as_deref(), Some("/essays/foo/child"));
+      assert_eq!(resolve("../../../up/").as_deref(), Some("/up"));
+      assert_eq!(resolve("https://elsewhere.com/notes/"), None);
+      assert_eq!(resolve("mailto:me@example.com"), None);
+      assert_eq!(resolve("//elsewhere.com/"), None);
+      assert_eq!(resolve("#fn-1"), None);
+   }
+
+   #[test]
+   fn pages_collect_their_backlinks() {
+      let page = |url: &str, title, date: Option<&str>, html| Linking {
+         url: url.to_string(),
+         title,
+         date: date.map(|date| DateTime::parse_from_rfc3339(date).unwrap()),
+         html,
+      };
+      let pages = [
+         page(
+            "/essays/target/",
+            "Target",
+            None,
+            r#"<a href="/essays/target/">self</a>"#,
+         ),
+         page(
+            "/essays/old/",
+            "Old",
+            Some("2023-01-01T00:00:00Z"),
+            r#"<a href="../target/">one</a> <a href='/essays/target/#x'>two</a>"#,
+         ),
+         page(
+            "/notes/new/",
+            "New",
+            Some("2024-01-01T00:00:00Z"),
+            r#"<a href="https://example.com/essays/target/">it</a> <a href="/nowhere/">no</a>"#,
+         ),
+         page(
+            "/undated/",
+            "Undated",
+            None,
+            r#"<a href="../essays/target">it</a>"#,
+         ),
+      ];
+
+      let Backlinks(backlinks) = Backlinks::collect(&pages, SITE);
+      assert_eq!(backlinks.keys().collect::<Vec<_>>(), ["/essays/target/"]);
+      let titles = backlinks["/essays/target/"]
+         .iter()
+         .map(|link| link.title.as_str())
+         .collect::<Vec<_>>();
+      assert_eq!(titles, ["New", "Old", "Undated"]);
+   }
+}
@@ -1,6 +1,8 @@
 use std::{
+   collections::{HashMap, HashSet},
    error, fmt, fs, io,
    path::{Path, PathBuf},
+   sync::Arc,
 };
 
 use lazy_static::lazy_static;
@@ -9,28 +11,108 @@ use rayon::iter::Either;
 use rayon::prelude::*;
 use thiserror::Error;
 
+use json_feed::JSONFeed;
 use lx_md::Markdown;
+use minijinja::{Environment, Value};
+use syntect::parsing::SyntaxSet;
 
 use crate::{
    archive::{Archive, Order},
+   backlinks::{Backlink, Backlinks},
    canonicalized::Canonicalized,
+   citations::{self, Citations},
    data::{
       config::{self, Config},
       item::cascade::{Cascade, CascadeLoadError},
    },
    error::write_to_fmt,
+   feed::{self, Feed},
+   links::Targets,
    page::{self, Page, Source},
-   templates,
+   paginate::{paginate, Listing},
+   scripture, templates,
 };
 
 pub fn build_in(directory: Canonicalized) -> Result<(), Error> {
    let config = config_for(&directory)?;
-   let md = Markdown::new(None);
+   let md = markdown_for(&directory, &config)?;
 
    // TODO: further split this apart.
    build(directory, &config, &md)
 }
 
+/// The Markdown renderer for the site: configured by its config, and highlighting any
+/// extra syntaxes it defines (as `.sublime-syntax` files in `_syntaxes`) as well as the
+/// default ones.
+pub fn markdown_for(
+   directory: &Canonicalized,
+   config: &Config,
+) -> Result<Markdown, Error> {
+   let syntaxes_dir = directory.as_ref().join(&*SYNTAXES_DIR);
+   let syntax_set = if syntaxes_dir.is_dir() {
+      let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+      builder
+         .add_from_folder(&syntaxes_dir, true)
+         .map_err(|source| Error::Syntaxes {
+            dir: syntaxes_dir.clone(),
+            source,
+         })?;
+      Some(builder.build())
+   } else {
+      None
+   };
+
+   let mut md = Markdown::new(syntax_set)
+      .with_excerpt(config.excerpt.clone())
+      .with_math(config.math.clone())
+      .with_gemtext(config.gemini)
+      .with_languages(config.languages.clone())
+      .with_site_url(&config.url)
+      .with_external_links(config.external_links.clone());
+   if let Some(glossary) = &config.glossary {
+      md = md.with_glossary(glossary.clone());
+   }
+   if !config.replacements.is_empty() {
+      md = md.with_transform(config.replacements.clone());
+   }
+
+   Ok(md)
+}
+
+/// The data cascade for the site's content.
+pub fn cascade_for(directory: &Canonicalized) -> Result<Cascade, Error> {
+   let site_files = SiteFiles::in_dir(directory.as_ref())?;
+   Cascade::new(&site_files.data).map_err(|source| Error::Cascade { source })
+}
+
+/// The site's styles, compiled to CSS, each with its path relative to `_styles`.
+pub fn styles_for(directory: &Canonicalized) -> Result<Vec<(PathBuf, String)>, Error> {
+   let site_files = SiteFiles::in_dir(directory.as_ref())?;
+   compile_styles(directory.as_ref(), site_files.styles)
+}
+
+fn compile_styles(
+   input_dir: &Path,
+   styles: Vec<PathBuf>,
+) -> Result<Vec<(PathBuf, String)>, Error> {
+   styles
+      .into_iter()
+      // only build the “root” files
+      .filter(|path| !path.starts_with("_"))
+      .map(|sass_file| {
+         let converted = grass::from_path(&sass_file, &grass::Options::default())?;
+         let relative_path =
+            sass_file
+               .strip_prefix(input_dir.join("_styles"))
+               .map_err(|_| Error::StripPrefix {
+                  prefix: input_dir.to_owned(),
+                  path: sass_file.clone(),
+               })?;
+         Ok((relative_path.to_owned(), converted))
+      })
+      .collect()
+}
+
 pub fn config_for(source_dir: &Canonicalized) -> Result<Config, Error> {
    let config_path = source_dir.as_ref().join("config.lx.yaml");
    debug!("source path: {}", source_dir.as_ref().display());
@@ -39,140 +121,307 @@ pub fn config_for(source_dir: &Canonicalized) -> Result<Config, Error> {
    Ok(config)
 }
 
-// TODO: further split this apart.
 pub fn build(
    directory: Canonicalized,
    config: &Config,
    md: &Markdown,
 ) -> Result<(), Error> {
    trace!("Building in {directory}");
-   trace!("Removing output directory {}", config.output.display());
+   let mut site = Site::load(directory.as_ref())?;
+   let sources = site.load_sources()?;
+   let pages = site.render(&sources, config, md)?;
+   site.write(config, &pages)
+}
 
-   if let Err(io_err) = fs::remove_dir_all(&config.output) {
-      if io_err.kind() != io::ErrorKind::NotFound {
-         return Err(Error::RemoveDir {
-            source: io_err,
-            path: config.output.clone(),
-         });
-      }
-   }
+/// A site's files and templates, loaded and ready to render its sources.
+pub struct Site {
+   dir: PathBuf,
+   files: SiteFiles,
+   shared_dir: Option<PathBuf>,
+   shared_files: Option<SharedFiles>,
+   jinja_env: Environment<'static>,
+   components: templates::Components,
+   /// Every page, for listings; available once the pages are prepared.
+   pages: Option<Arc<templates::Pages>>,
+   /// The backlinks between the pages; available once the pages are rendered.
+   backlinks: Option<Backlinks>,
+   /// The name of every template loaded while rendering the pages' content, e.g. with
+   /// `{% include %}`: changing one of these changes the content, not just the layout.
+   content_templates: HashSet<String>,
+}
 
-   let input_dir = directory.as_ref();
-   let site_files = SiteFiles::in_dir(input_dir)?;
-   trace!("Site files: {site_files}");
-
-   let shared_dir = input_dir.parent().map(|parent| parent.join("_shared"));
-   let mut shared_files = shared_dir
-      .as_ref()
-      .map(|dir| SharedFiles::in_dir(dir))
-      .transpose()?;
-
-   trace!(
-      "Shared files: {}",
-      match &shared_files {
-         Some(files) => format!("{files}"),
-         None => "none".into(),
-      }
-   );
+impl Site {
+   pub fn load(input_dir: &Path) -> Result<Site, Error> {
+      let site_files = SiteFiles::in_dir(input_dir)?;
+      trace!("Site files: {site_files}");
+
+      let shared_dir = input_dir.parent().map(|parent| parent.join("_shared"));
+      let shared_files = shared_dir
+         .as_ref()
+         .map(|dir| SharedFiles::in_dir(dir))
+         .transpose()?;
+
+      trace!(
+         "Shared files: {}",
+         match &shared_files {
+            Some(files) => format!("{files}"),
+            None => "none".into(),
+         }
+      );
 
-   let mut all_templates = site_files.templates;
-   if let Some(shared_files) = shared_files.as_mut() {
-      all_templates.append(&mut shared_files.templates);
+      let site_ui_dir = input_dir.join(&*UI_DIR);
+      let shared_ui_dir = shared_dir.as_ref().map(|dir| dir.join(&*UI_DIR));
+      let jinja_env = templates::load(&site_ui_dir, shared_ui_dir.as_deref())?;
+      let components = templates::Components::new(
+         &jinja_env,
+         templates::Templates {
+            dir: &site_ui_dir,
+            paths: &site_files.templates,
+         },
+         shared_files
+            .as_ref()
+            .zip(shared_ui_dir.as_deref())
+            .map(|(files, dir)| templates::Templates {
+               dir,
+               paths: &files.templates,
+            }),
+      )?;
+
+      Ok(Site {
+         dir: input_dir.to_owned(),
+         files: site_files,
+         shared_dir,
+         shared_files,
+         jinja_env,
+         components,
+         pages: None,
+         backlinks: None,
+         content_templates: HashSet::new(),
+      })
    }
 
-   trace!("all templates: {all_templates:?}");
+   /// Whether the template at `path` is one of the site's (or the shared) templates
+   /// which only affects the layout of rendered pages: not a component, and not loaded
+   /// by any page's content when it last rendered.
+   pub fn is_layout_template(&self, path: &Path) -> bool {
+      if path.extension().is_none_or(|ext| ext != "jinja") {
+         return false;
+      }
 
-   let jinja_env = templates::load(all_templates, |path| {
-      let site_ui_dir = input_dir.join(&*UI_DIR);
-      if path.starts_with(&site_ui_dir) {
-         Ok(path.strip_prefix(&site_ui_dir).unwrap())
-      } else if let Some(shared_dir) = shared_dir.as_ref() {
-         let shared_ui_dir = shared_dir.join(&*UI_DIR);
-         if path.starts_with(&shared_ui_dir) {
-            Ok(path.strip_prefix(&shared_ui_dir).unwrap())
-         } else {
-            Err(Box::new(Error::TemplatePath {
-               path: path.to_owned(),
-            }))
-         }
+      let shared_ui_dir = self.shared_dir.as_ref().map(|dir| dir.join(&*UI_DIR));
+      let names = if let Ok(relative) = path.strip_prefix(self.dir.join(&*UI_DIR)) {
+         vec![templates::name_of(relative)]
+      } else if let Some(relative) =
+         shared_ui_dir.and_then(|dir| path.strip_prefix(dir).ok())
+      {
+         // A shared template can be loaded by its own name (if the site does not
+         // override it) as well as with the `shared/` prefix.
+         let name = templates::name_of(relative);
+         vec![format!("{}{name}", templates::SHARED_PREFIX), name]
       } else {
-         Err(Box::new(Error::TemplatePath {
-            path: path.to_owned(),
-         }))
-      }
-   })?;
+         return false;
+      };
 
-   // TODO: actual error handling here, please.
-   fs::create_dir_all(&config.output).expect("Can create output dir");
+      names.iter().all(|name| {
+         !templates::is_component(name) && !self.content_templates.contains(name)
+      })
+   }
 
-   let sources = load_sources(&site_files.content)?;
+   /// Drop every loaded template, so they are loaded afresh the next time they render.
+   pub fn reload_templates(&mut self) {
+      self.jinja_env.clear_templates();
+   }
 
-   debug!("loaded {count} pages", count = sources.len());
+   pub fn load_sources(&self) -> Result<Vec<Source>, Error> {
+      let sources = load_sources(&self.files.content)?;
+      debug!("loaded {count} pages", count = sources.len());
+      Ok(sources)
+   }
 
-   let cascade =
-      Cascade::new(&site_files.data).map_err(|source| Error::Cascade { source })?;
+   /// Prepare and render the content of every page. Once every page is prepared, the
+   /// templates can query all of them (see [`templates::Pages`]).
+   pub fn render<'s>(
+      &mut self,
+      sources: &'s [Source],
+      config: &Config,
+      md: &Markdown,
+   ) -> Result<Vec<Page<'s>>, Error> {
+      let cascade =
+         Cascade::new(&self.files.data).map_err(|source| Error::Cascade { source })?;
+
+      let citations = Citations::load(&config.citations)?;
+
+      let (errors, prepared_pages): (Vec<_>, Vec<_>) = sources
+         .par_iter()
+         // NOTE: this is where I will want to add handling for `<page>.lx.yaml` files; when
+         // I add support for that this will not be a filter but will do different things in
+         // the map call depending on what kind of file it is.
+         .filter(|source| source.path.extension().is_some_and(|ext| ext == "md"))
+         .map(|source| {
+            page::prepare(md, source, &cascade)
+               .map(|prepared| (prepared, source))
+               .map_err(|e| (source.path.clone(), e))
+         })
+         .partition_map(Either::from);
+
+      if !errors.is_empty() {
+         return Err(Error::preparing_page(errors));
+      }
 
-   let (errors, prepared_pages): (Vec<_>, Vec<_>) = sources
-      .par_iter()
-      // NOTE: this is where I will want to add handling for `<page>.lx.yaml` files; when
-      // I add support for that this will not be a filter but will do different things in
-      // the map call depending on what kind of file it is.
-      .filter(|source| source.path.extension().is_some_and(|ext| ext == "md"))
-      .map(|source| {
-         page::prepare(md, source, &cascade)
-            .map(|prepared| (prepared, source))
-            .map_err(|e| (source.path.clone(), e))
-      })
-      .partition_map(Either::from);
+      debug!("prepared {count} pages", count = prepared_pages.len());
+
+      // TODO: build taxonomies. Structurally, I *think* the best thing to do is
+      // provide a top-level `Archive` and then filter on its results, since that
+      // avoids having to do the sorting more than once. So build the taxonomies
+      // *second*, as filtered versions of the Archive?
+
+      let content_dir = self.dir.join("content");
+
+      let link_targets = Arc::new(
+         Targets::new(
+            prepared_pages
+               .iter()
+               .map(|(prepared, source)| (*source, prepared.data())),
+            &content_dir,
+         )
+         .map_err(Error::preparing_page)?,
+      );
+
+      let all_pages = Arc::new(
+         templates::Pages::new(
+            prepared_pages
+               .iter()
+               .map(|(prepared, source)| (*source, prepared.data())),
+            &content_dir,
+            Arc::clone(&link_targets),
+         )
+         .map_err(Error::preparing_page)?,
+      );
+      all_pages.add_to(&mut self.jinja_env);
+      self.pages = Some(all_pages);
+
+      let jinja_env = &self.jinja_env;
+      let components = &self.components;
+
+      let (errors, pages): (Vec<_>, Vec<_>) = prepared_pages
+         .into_par_iter()
+         .map(|(prepared, source)| {
+            // TODO: once the taxonomies exist, pass them here.
+            prepared
+               .resolve_links(|target| link_targets.resolve(target))
+               .and_then(|prepared| {
+                  prepared.resolve_relative_links(|path| {
+                     link_targets.resolve_relative(&source.path, path)
+                  })
+               })
+               .and_then(|prepared| prepared.cite(&citations))
+               .and_then(|prepared| prepared.scripture(config.scripture.as_ref()))
+               .and_then(|prepared| {
+                  // The template syntax in the content, including shortcodes, sees the
+                  // page's metadata.
+                  let context = Value::from_serialize(prepared.data());
+                  prepared.render(
+                     md,
+                     |text| {
+                        let after_jinja =
+                           templates::rewrite(jinja_env, text, &context)
+                              .map_err(|source| Error::rewrite(source, text))?;
+                        // TODO: smarten the typography!
+                        Ok(after_jinja)
+                     },
+                     |shortcode, content| {
+                        templates::expand_shortcode(
+                           jinja_env, components, shortcode, content, &context,
+                        )
+                        .map_err(Into::into)
+                     },
+                  )
+               })
+               .and_then(|rendered| Page::from_rendered(rendered, source, &content_dir))
+               .map_err(|e| (source.path.clone(), e))
+         })
+         .partition_map(Either::from);
+
+      if !errors.is_empty() {
+         return Err(Error::rendering_page(errors));
+      }
 
-   if !errors.is_empty() {
-      return Err(Error::preparing_page(errors));
-   }
+      // Templates are only loaded on demand, and layouts have not rendered yet, so every
+      // template loaded so far was loaded by the content (or is a component).
+      self.content_templates = self
+         .jinja_env
+         .templates()
+         .map(|(name, _)| name.to_string())
+         .collect();
 
-   debug!("prepared {count} pages", count = prepared_pages.len());
-
-   // TODO: build taxonomies. Structurally, I *think* the best thing to do is
-   // provide a top-level `Archive` and then filter on its results, since that
-   // avoids having to do the sorting more than once. So build the taxonomies
-   // *second*, as filtered versions of the Archive?
-
-   let content_dir = input_dir.join("content");
-
-   let (errors, pages): (Vec<_>, Vec<_>) = prepared_pages
-      .into_par_iter()
-      .map(|(prepared, source)| {
-         // TODO: once the taxonomies exist, pass them here.
-         prepared
-            .render(md, |text, metadata| {
-               let after_jinja = jinja_env
-                  .render_str(text, metadata)
-                  .map_err(|source| Error::rewrite(source, text))?;
-               // TODO: smarten the typography!
-               Ok(after_jinja)
-            })
-            .and_then(|rendered| Page::from_rendered(rendered, source, &content_dir))
-            .map_err(|e| (source.path.clone(), e))
-      })
-      .partition_map(Either::from);
+      self.backlinks = Some(Backlinks::new(&pages, config));
 
-   if !errors.is_empty() {
-      return Err(Error::rendering_page(errors));
+      Ok(pages)
    }
 
-   // TODO: this is the wrong spot for this. There is enough info to generate this and
-   // other such views above, now that I have split the phases apart.
-   let _archive = Archive::new(&pages, Order::NewFirst);
-
-   // TODO: this and the below are identical, except for the directory from which they
-   // come. This is suggestive: maybe extract into a function for handling both, and
-   // implement a trait for both to use. In that case, it would also very likely make
-   // sense to include at least a reference to the source directory in the `shared_files`
-   // and `site_files` structs.
-   if let Some(shared) = shared_files.as_mut() {
-      debug!("Copying {} shared static files", shared.static_files.len());
-      for static_file in shared.static_files.iter() {
+   /// Write the whole site to the output directory: static files, styles, backlinks,
+   /// and every page.
+   pub fn write(&self, config: &Config, pages: &[Page<'_>]) -> Result<(), Error> {
+      let input_dir = self.dir.as_path();
+      let shared_dir = &self.shared_dir;
+      let site_files = &self.files;
+
+      trace!("Removing output directory {}", config.output.display());
+      if let Err(io_err) = fs::remove_dir_all(&config.output) {
+         if io_err.kind() != io::ErrorKind::NotFound {
+            return Err(Error::RemoveDir {
+               source: io_err,
+               path: config.output.clone(),
+            });
+         }
+      }
+
+      // TODO: actual error handling here, please.
+      fs::create_dir_all(&config.output).expect("Can create output dir");
+
+      // TODO: this is the wrong spot for this. There is enough info to generate this and
+      // other such views above, now that I have split the phases apart.
+      let _archive = Archive::new(
+         pages
+            .iter()
+            .filter_map(|page| page.data.date.map(|date| (date, page))),
+         Order::NewFirst,
+      );
+
+      // TODO: this and the below are identical, except for the directory from which they
+      // come. This is suggestive: maybe extract into a function for handling both, and
+      // implement a trait for both to use. In that case, it would also very likely make
+      // sense to include at least a reference to the source directory in the `shared_files`
+      // and `site_files` structs.
+      if let Some(shared) = &self.shared_files {
+         debug!("Copying {} shared static files", shared.static_files.len());
+         for static_file in shared.static_files.iter() {
+            let relative_path = static_file
+               .strip_prefix(shared_dir.as_ref().unwrap().join("_static"))
+               .map_err(|_| Error::StripPrefix {
+                  prefix: input_dir.to_owned(),
+                  path: static_file.clone(),
+               })?;
+            let path = config.output.join(relative_path);
+            let output_dir = path.parent().expect("must have a real parent");
+            fs::create_dir_all(output_dir).map_err(|source| {
+               Error::CreateOutputDirectory {
+                  path: output_dir.to_owned(),
+                  source,
+               }
+            })?;
+            fs::copy(static_file, &path).map_err(|source| Error::CopyFile {
+               from: static_file.clone(),
+               to: path,
+               source,
+            })?;
+         }
+      }
+
+      debug!("Copying {} static files", site_files.static_files.len());
+      for static_file in site_files.static_files.iter() {
          let relative_path = static_file
-            .strip_prefix(shared_dir.as_ref().unwrap().join("_static"))
+            .strip_prefix(input_dir.join("_static"))
             .map_err(|_| Error::StripPrefix {
                prefix: input_dir.to_owned(),
                path: static_file.clone(),
@@ -191,73 +440,213 @@ pub fn build(
             source,
          })?;
       }
-   }
 
-   debug!("Copying {} static files", site_files.static_files.len());
-   for static_file in site_files.static_files.iter() {
-      let relative_path = static_file
-         .strip_prefix(input_dir.join("_static"))
-         .map_err(|_| Error::StripPrefix {
-            prefix: input_dir.to_owned(),
-            path: static_file.clone(),
-         })?;
-      let path = config.output.join(relative_path);
-      let output_dir = path.parent().expect("must have a real parent");
-      fs::create_dir_all(output_dir).map_err(|source| Error::CreateOutputDirectory {
-         path: output_dir.to_owned(),
-         source,
-      })?;
-      fs::copy(static_file, &path).map_err(|source| Error::CopyFile {
-         from: static_file.clone(),
-         to: path,
+      let backlinks = self
+         .backlinks
+         .as_ref()
+         .expect("pages are always rendered before they are written");
+      let backlinks_path = config.output.join("backlinks.json");
+      let backlinks_json = serde_json::to_vec_pretty(&backlinks)
+         .map_err(|source| Error::SerializeBacklinks { source })?;
+      fs::write(&backlinks_path, backlinks_json).map_err(|source| Error::WriteFile {
+         path: backlinks_path,
          source,
       })?;
+
+      for page in pages {
+         if let Some(gemtext) = page.content.gemtext() {
+            let path = config.output.join(page.path.as_ref()).join("index.gmi");
+            trace!("writing gemtext to {}", path.display());
+            let containing_dir = path.parent().expect("must have a real parent");
+            fs::create_dir_all(containing_dir).map_err(|source| {
+               Error::CreateOutputDirectory {
+                  path: containing_dir.to_owned(),
+                  source,
+               }
+            })?;
+            let gemtext = format!("# {}\n\n{gemtext}", page.data.title);
+            fs::write(&path, gemtext)
+               .map_err(|source| Error::WriteFile { path, source })?;
+         }
+      }
+
+      self.render_layouts(config, pages)?;
+
+      for (relative_path, converted) in
+         compile_styles(input_dir, site_files.styles.clone())?
+      {
+         let path = config.output.join(relative_path).with_extension("css");
+         fs::write(&path, converted)
+            .map_err(|source| Error::WriteFile { path, source })?;
+      }
+
+      Ok(())
    }
 
-   // TODO: this can and probably should use async?
-   for page in pages {
-      let relative_path = page.path.as_ref().join("index.html");
+   /// Render every page (and the glossary and scripture index, if the site has them)
+   /// into its layout template and write it out. The pages' content is used as it is,
+   /// so this is all that needs to run again when only layout templates change.
+   pub fn render_layouts(
+      &self,
+      config: &Config,
+      pages: &[Page<'_>],
+   ) -> Result<(), Error> {
+      let jinja_env = &self.jinja_env;
+      let backlinks = self
+         .backlinks
+         .as_ref()
+         .expect("pages are always rendered before their layouts render");
+
+      // TODO: this can and probably should use async?
+      for page in pages {
+         let relative_path = page.path.as_ref().join("index.html");
 
-      let path = config.output.join(relative_path);
+         let path = config.output.join(relative_path);
 
-      trace!("writing page {} to {}", page.data.title, path.display());
-      let containing_dir = path
-         .parent()
-         .unwrap_or_else(|| panic!("{} should have a containing dir!", path.display()));
+         trace!("writing page {} to {}", page.data.title, path.display());
+         let containing_dir = path.parent().unwrap_or_else(|| {
+            panic!("{} should have a containing dir!", path.display())
+         });
 
-      fs::create_dir_all(containing_dir).map_err(|e| Error::CreateOutputDirectory {
-         path: containing_dir.to_owned(),
-         source: e,
-      })?;
+         fs::create_dir_all(containing_dir).map_err(|e| {
+            Error::CreateOutputDirectory {
+               path: containing_dir.to_owned(),
+               source: e,
+            }
+         })?;
 
-      let mut buf = Vec::new();
-      templates::render(&jinja_env, &page, config, &mut buf)?;
+         if let Some(listing) = &page.data.paginate {
+            self.render_listing(
+               config,
+               pages,
+               page,
+               listing,
+               backlinks.for_page(page),
+            )?;
+            continue;
+         }
 
-      fs::write(&path, buf).map_err(|source| Error::WriteFile { path, source })?;
-   }
+         let mut buf = Vec::new();
+         templates::render(jinja_env, page, config, backlinks.for_page(page), &mut buf)?;
 
-   for sass_file in site_files
-      .styles
-      .into_iter()
-      // only build the “root” files
-      .filter(|path| !path.starts_with("_"))
-   {
-      let converted = grass::from_path(&sass_file, &grass::Options::default())?;
-      let relative_path =
-         sass_file
-            .strip_prefix(input_dir.join("_styles"))
-            .map_err(|_| Error::StripPrefix {
-               prefix: input_dir.to_owned(),
-               path: sass_file.clone(),
-            })?;
+         fs::write(&path, buf).map_err(|source| Error::WriteFile { path, source })?;
+      }
 
-      let path = config.output.join(relative_path).with_extension("css");
-      fs::write(&path, converted).map_err(|source| Error::WriteFile { path, source })?;
+      if let Some(glossary) = &config.glossary {
+         let dir = config.output.join(config::serial::GLOSSARY_PATH);
+         fs::create_dir_all(&dir).map_err(|source| Error::CreateOutputDirectory {
+            path: dir.clone(),
+            source,
+         })?;
+
+         let mut buf = Vec::new();
+         templates::render_glossary(jinja_env, glossary, config, &mut buf)?;
+         let path = dir.join("index.html");
+         fs::write(&path, buf).map_err(|source| Error::WriteFile { path, source })?;
+      }
+
+      if config.scripture.is_some() {
+         let index = scripture::Index::new(pages);
+         let dir = config.output.join(scripture::INDEX_PATH);
+         fs::create_dir_all(&dir).map_err(|source| Error::CreateOutputDirectory {
+            path: dir.clone(),
+            source,
+         })?;
+
+         let mut buf = Vec::new();
+         templates::render_scripture_index(jinja_env, &index, config, &mut buf)?;
+         let path = dir.join("index.html");
+         fs::write(&path, buf).map_err(|source| Error::WriteFile { path, source })?;
+      }
+
+      Ok(())
    }
 
-   Ok(())
+   /// Render a listing page once for each page of what it lists: the first at its own
+   /// path, the rest at `page/<n>/` under it. Each of those also gets a JSON Feed of its
+   /// items, linked to the feed for the next one.
+   fn render_listing(
+      &self,
+      config: &Config,
+      pages: &[Page<'_>],
+      page: &Page<'_>,
+      listing: &Listing,
+      backlinks: &[Backlink],
+   ) -> Result<(), Error> {
+      let all_pages = self
+         .pages
+         .as_ref()
+         .expect("pages are always prepared before their layouts render");
+
+      let items = all_pages
+         .query(
+            listing.section.as_deref(),
+            listing.tag.as_deref(),
+            listing.sort.as_deref().unwrap_or("-date"),
+            None,
+         )
+         .map_err(|source| Error::Listing {
+            path: page.source.path.clone(),
+            source,
+         })?;
+
+      let by_id = pages
+         .iter()
+         .map(|page| (page.id.to_string(), page))
+         .collect::<HashMap<_, _>>();
+
+      let size = listing.size.unwrap_or(config.pagination.size);
+      for paginator in paginate(&items, size, &page.path) {
+         let dir = config.output.join(&paginator.path);
+         fs::create_dir_all(&dir).map_err(|source| Error::CreateOutputDirectory {
+            path: dir.clone(),
+            source,
+         })?;
+
+         let mut buf = Vec::new();
+         templates::render_listing(
+            &self.jinja_env,
+            page,
+            config,
+            backlinks,
+            &paginator,
+            &mut buf,
+         )?;
+         let path = dir.join("index.html");
+         fs::write(&path, buf).map_err(|source| Error::WriteFile { path, source })?;
+
+         let feed_items = paginator
+            .items
+            .iter()
+            .map(|summary| {
+               *by_id
+                  .get(&summary.id)
+                  .expect("every listed page has been rendered")
+            })
+            .collect::<Vec<_>>();
+         let path = dir.join(LISTING_FEED);
+         let feed = Feed::new(page.data.title.clone(), config, &feed_items)
+            .paginated(&paginator, LISTING_FEED);
+         let json_feed = JSONFeed::try_from(feed).map_err(|source| Error::BuildFeed {
+            path: path.clone(),
+            source,
+         })?;
+         let json = serde_json::to_vec_pretty(&json_feed).map_err(|source| {
+            Error::SerializeFeed {
+               path: path.clone(),
+               source,
+            }
+         })?;
+         fs::write(&path, json).map_err(|source| Error::WriteFile { path, source })?;
+      }
+
+      Ok(())
+   }
 }
 
+/// The file name of the JSON Feed for each page of a listing.
+const LISTING_FEED: &str = "feed.json";
+
 fn load_sources<S>(source_files: S) -> Result<Vec<Source>, Error>
 where
    S: IntoIterator,
@@ -306,6 +695,12 @@ pub enum Error {
       source: CascadeLoadError,
    },
 
+   #[error(transparent)]
+   Citations {
+      #[from]
+      source: citations::Error,
+   },
+
    #[error("could not load site config: {source}")]
    Config {
       #[from]
@@ -331,6 +726,18 @@ pub enum Error {
    #[error("could not write to {path}")]
    WriteFile { path: PathBuf, source: io::Error },
 
+   #[error("could not serialize backlinks")]
+   SerializeBacklinks { source: serde_json::Error },
+
+   #[error("could not build the feed {path}")]
+   BuildFeed { path: PathBuf, source: feed::Error },
+
+   #[error("could not serialize the feed {path}")]
+   SerializeFeed {
+      path: PathBuf,
+      source: serde_json::Error,
+   },
+
    #[error("bad glob pattern: '{pattern}'")]
    GlobPattern {
       pattern: String,
@@ -349,8 +756,17 @@ pub enum Error {
       source: Box<grass::Error>,
    },
 
-   #[error("invalid template path {path}")]
-   TemplatePath { path: PathBuf },
+   #[error("could not load syntaxes from '{dir}'")]
+   Syntaxes {
+      dir: PathBuf,
+      source: syntect::LoadingError,
+   },
+
+   #[error("could not list the pages for the listing at {path}")]
+   Listing {
+      path: PathBuf,
+      source: minijinja::Error,
+   },
 
    #[error("could not delete directory '{path}'")]
    RemoveDir { path: PathBuf, source: io::Error },
@@ -438,6 +854,7 @@ pub struct ContentError {
 
 lazy_static! {
    static ref UI_DIR: PathBuf = PathBuf::from("_ui");
+   static ref SYNTAXES_DIR: PathBuf = PathBuf::from("_syntaxes");
 }
 
 struct SiteFiles {
@@ -467,7 +884,7 @@ impl SiteFiles {
          config: in_dir.join("config.lx.yaml"),
          content,
          data,
-         templates: resolved_paths_for(&format!("{root}/{}/*.jinja", UI_DIR.display()))?,
+         templates: ui_templates(&root)?,
          static_files: resolved_paths_for(&format!("{root}/_static/**/*"))?,
          styles: resolved_paths_for(&format!("{root}/_styles/**/*.scss"))?,
       };
@@ -518,7 +935,7 @@ impl SharedFiles {
       let root = dir.display();
 
       let site_files = SharedFiles {
-         templates: resolved_paths_for(&format!("{root}/{}/*.jinja", UI_DIR.display()))?,
+         templates: ui_templates(&root)?,
          static_files: resolved_paths_for(&format!("{root}/_static/**/*"))?,
          styles: resolved_paths_for(&format!("{root}/_styles/**/*.scss"))?,
       };
@@ -555,6 +972,12 @@ impl fmt::Display for SharedFiles {
    }
 }
 
+/// The templates in `_ui` and every directory within it, e.g. `_ui/components`.
+fn ui_templates(root: &impl fmt::Display) -> Result<Vec<PathBuf>, Error> {
+   let ui_dir = UI_DIR.display();
+   resolved_paths_for(&format!("{root}/{ui_dir}/**/*.jinja"))
+}
+
 fn resolved_paths_for(glob_src: &str) -> Result<Vec<PathBuf>, Error> {
    glob::glob(glob_src)
       .map_err(|source| Error::GlobPattern {
@@ -570,3 +993,71 @@ fn resolved_paths_for(glob_src: &str) -> Result<Vec<PathBuf>, Error> {
       })
       .map(|paths| paths.into_iter().filter(|path| path.is_file()).collect())
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn listing_feeds_link_to_the_next_page() {
+      let root =
+         std::env::temp_dir().join(format!("lx-build-{}-feeds", std::process::id()));
+      let _ = fs::remove_dir_all(&root);
+
+      let dir = root.join("site");
+      for (path, contents) in [
+         (
+            "config.lx.yaml",
+            "url: 'https://example.com/'\n\
+             repo: 'https://example.com/repo'\n\
+             title: { normal: 'Example', stylized: 'Example' }\n\
+             subtitle: 'by Me'\n\
+             description: 'A test site.'\n\
+             author: { name: 'Me', email: 'me@example.com', links: {} }\n\
+             output: public\n\
+             image: social.png\n",
+         ),
+         (
+            "content/notes.md",
+            "---\ntitle: Notes\nlayout: list.jinja\npaginate: { section: notes, size: 1 }\n---\n",
+         ),
+         (
+            "content/notes/old.md",
+            "---\ntitle: Old\ndate: 2023-01-01T00:00:00Z\n---\n\nOld.\n",
+         ),
+         (
+            "content/notes/new.md",
+            "---\ntitle: New\ndate: 2024-01-01T00:00:00Z\n---\n\nNew.\n",
+         ),
+         ("_ui/list.jinja", "{{ paginator.current }}"),
+         ("_ui/base.jinja", "{{ content }}"),
+      ] {
+         let path = dir.join(path);
+         fs::create_dir_all(path.parent().unwrap()).unwrap();
+         fs::write(&path, contents).unwrap();
+      }
+
+      let dir = Canonicalized::try_from(dir).unwrap();
+      let config = config_for(&dir).unwrap();
+      let md = markdown_for(&dir, &config).unwrap();
+      let output = dir.as_ref().join("public");
+      build(dir, &config, &md).unwrap();
+
+      let feed = |relative: &str| -> serde_json::Value {
+         let json = fs::read_to_string(output.join(relative)).unwrap();
+         serde_json::from_str(&json).unwrap()
+      };
+      let first = feed("notes/feed.json");
+      let second = feed("notes/page/2/feed.json");
+      let _ = fs::remove_dir_all(&root);
+
+      assert_eq!(first["title"], "Notes");
+      assert_eq!(first["items"][0]["title"], "New");
+      assert_eq!(
+         first["next_url"],
+         "https://example.com/notes/page/2/feed.json"
+      );
+      assert_eq!(second["items"][0]["title"], "Old");
+      assert!(second["next_url"].is_null(), "{second}");
+   }
+}
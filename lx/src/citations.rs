@@ -0,0 +1,59 @@
+//! Load the CSL style and site-wide bibliography which citations are formatted with.
+//! Items can supply their own bibliography, which is loaded when they are rendered.
+
+use std::path::Path;
+
+use lx_md::{Bibliography, BibliographyError, Style, StyleError};
+use thiserror::Error;
+
+use crate::data::config::serial;
+
+pub struct Citations {
+   style: Style,
+   bibliography: Option<Bibliography>,
+}
+
+impl Citations {
+   pub fn load(config: &serial::Citations) -> Result<Citations, Error> {
+      let style = if config.is_style_file() {
+         Style::from_path(Path::new(&config.style))
+      } else {
+         Style::named(&config.style)
+      }
+      .map_err(|source| Error::Style {
+         style: config.style.clone(),
+         source,
+      })?;
+
+      let bibliography = config
+         .bibliography
+         .as_deref()
+         .map(Bibliography::from_path)
+         .transpose()?;
+
+      Ok(Citations {
+         style,
+         bibliography,
+      })
+   }
+
+   pub fn style(&self) -> &Style {
+      &self.style
+   }
+
+   pub fn bibliography(&self) -> Option<&Bibliography> {
+      self.bibliography.as_ref()
+   }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+   #[error("could not load citation style '{style}'")]
+   Style { style: String, source: StyleError },
+
+   #[error("could not load site bibliography")]
+   Bibliography {
+      #[from]
+      source: BibliographyError,
+   },
+}
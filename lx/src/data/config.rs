@@ -16,11 +16,27 @@ pub struct Config {
    pub image: crate::data::image::Image,
    #[serde(default)]
    pub nav: Vec<NavItem>,
+   pub excerpt: lx_md::Excerpt,
+   pub citations: serial::Citations,
+   pub math: lx_md::Math,
+   pub gemini: bool,
+   pub glossary: Option<lx_md::Glossary>,
+   pub scripture: Option<lx_md::Scripture>,
+   pub languages: lx_md::Languages,
+   pub external_links: lx_md::ExternalLinks,
+   pub replacements: crate::replacements::Replacements,
+   pub pagination: crate::paginate::Pagination,
 }
 
 impl Config {
    pub fn from_file(path: &Path) -> Result<Config, Error> {
       let serial_cfg = serial::Config::from_file(path)?;
+      let glossary = serial_cfg
+         .glossary
+         .as_deref()
+         .map(serial::load_glossary)
+         .transpose()?;
+
       Ok(Config {
          url: serial_cfg.url,
          repo: serial_cfg.repo,
@@ -31,6 +47,16 @@ impl Config {
          output: serial_cfg.output,
          image: Image::from(serial_cfg.image),
          nav: serial_cfg.nav,
+         excerpt: serial_cfg.excerpt,
+         citations: serial_cfg.citations,
+         math: serial_cfg.math,
+         gemini: serial_cfg.gemini,
+         glossary,
+         scripture: serial_cfg.scripture,
+         languages: serial_cfg.languages,
+         external_links: serial_cfg.external_links,
+         replacements: serial_cfg.replacements,
+         pagination: serial_cfg.pagination,
       })
    }
 }
@@ -69,6 +95,47 @@ pub mod serial {
       pub image: crate::data::image::serial::Image,
       #[serde(default)]
       pub nav: Vec<NavItem>,
+      /// How to find excerpts for index pages and feeds. See [`lx_md::Excerpt`].
+      #[serde(default)]
+      pub excerpt: lx_md::Excerpt,
+      /// How to format citations. See [`Citations`].
+      #[serde(default)]
+      pub citations: Citations,
+      /// LaTeX macros available to math in every item. See [`lx_md::Math`].
+      #[serde(default)]
+      pub math: lx_md::Math,
+      /// Also write every page as gemtext (`index.gmi`, next to its `index.html`), so
+      /// the output can be served over Gemini.
+      #[serde(default)]
+      pub gemini: bool,
+      /// A YAML file mapping terms of art to their definitions, relative to the config
+      /// file. Each term is marked on its first use in every page, and linked to its
+      /// definition on a generated glossary page (`/glossary/`), rendered with the
+      /// `glossary.jinja` template: the site's own, or else the shared one.
+      pub glossary: Option<PathBuf>,
+      /// How to recognize and link scripture references in content. See
+      /// [`lx_md::Scripture`]. When set, a generated page (`/scripture/`) lists which
+      /// pages discuss each book of the Bible, rendered with the `scripture.jinja`
+      /// template: the site's own, or else the shared one. References in item metadata
+      /// are always normalized.
+      pub scripture: Option<lx_md::Scripture>,
+      /// The language to tag text in each non-Latin script with, e.g. `greek: grc`.
+      /// Replaces the default (Greek as `grc`, Hebrew as `he`), so an empty map turns
+      /// tagging off. See [`lx_md::Languages`].
+      #[serde(default)]
+      pub languages: lx_md::Languages,
+      /// Attributes for links to other sites, e.g. `rel: noopener noreferrer` or
+      /// `class: external`. See [`lx_md::ExternalLinks`].
+      #[serde(default)]
+      pub external_links: lx_md::ExternalLinks,
+      /// Literal text to replace in prose, e.g. `"->": "→"`. See
+      /// [`crate::replacements::Replacements`].
+      #[serde(default)]
+      pub replacements: crate::replacements::Replacements,
+      /// How to split listing pages (those with `paginate` metadata) into pages, e.g.
+      /// `size: 20`. See [`crate::paginate::Pagination`].
+      #[serde(default)]
+      pub pagination: crate::paginate::Pagination,
    }
 
    impl Config {
@@ -84,21 +151,56 @@ pub mod serial {
                source,
             })?;
 
-         config.output = path
-            .parent()
-            .unwrap_or_else(|| {
-               panic!(
-                  "config file at {path} will have a parent dir",
-                  path = path.display()
-               )
-            })
-            .join(&config.output)
-            .normalize();
+         let config_dir = path.parent().unwrap_or_else(|| {
+            panic!(
+               "config file at {path} will have a parent dir",
+               path = path.display()
+            )
+         });
+
+         config.output = config_dir.join(&config.output).normalize();
+
+         if let Some(bibliography) = config.citations.bibliography.as_mut() {
+            *bibliography = config_dir.join(&bibliography).normalize();
+         }
+
+         if let Some(glossary) = config.glossary.as_mut() {
+            *glossary = config_dir.join(&glossary).normalize();
+         }
+
+         if config.citations.is_style_file() {
+            config.citations.style = config_dir
+               .join(&config.citations.style)
+               .normalize()
+               .to_string_lossy()
+               .to_string();
+         }
 
          Ok(config)
       }
    }
 
+   /// The path of the generated glossary page, relative to the site root.
+   pub const GLOSSARY_PATH: &str = "glossary";
+
+   pub fn load_glossary(path: &Path) -> Result<lx_md::Glossary, Error> {
+      let data = std::fs::read_to_string(path).map_err(|source| Error::BadFile {
+         path: path.to_owned(),
+         source,
+      })?;
+
+      let terms =
+         serde_yaml::from_str(&data).map_err(|source| Error::YamlParseError {
+            path: path.to_owned(),
+            source,
+         })?;
+
+      Ok(lx_md::Glossary {
+         url: format!("/{GLOSSARY_PATH}/"),
+         terms,
+      })
+   }
+
    #[derive(Serialize, Deserialize, Debug)]
    pub struct Title {
       pub(crate) normal: String,
@@ -118,6 +220,33 @@ pub mod serial {
       pub links: HashMap<String, String>,
    }
 
+   /// Citations (`[@key]`, `[@key, p. 12]`) are formatted using a bibliography and a CSL
+   /// style. Items can supply their own bibliography, which replaces the site's.
+   #[derive(Serialize, Deserialize, Debug)]
+   #[serde(default)]
+   pub struct Citations {
+      /// A BibTeX (`.bib`) or CSL-JSON (`.json`) file, relative to the config file.
+      pub bibliography: Option<PathBuf>,
+      /// Either one of the styles bundled with `lx`, by name (e.g. `chicago-author-date`
+      /// or `chicago-notes`), or a `.csl` file, relative to the config file.
+      pub style: String,
+   }
+
+   impl Citations {
+      pub fn is_style_file(&self) -> bool {
+         self.style.ends_with(".csl")
+      }
+   }
+
+   impl Default for Citations {
+      fn default() -> Self {
+         Citations {
+            bibliography: None,
+            style: String::from("chicago-author-date"),
+         }
+      }
+   }
+
    #[derive(Serialize, Deserialize, Debug)]
    #[serde(tag = "type", rename_all = "snake_case")]
    pub enum NavItem {
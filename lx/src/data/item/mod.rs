@@ -10,8 +10,10 @@ use std::path::StripPrefixError;
 use chrono::DateTime;
 use chrono::FixedOffset;
 use lx_md::Markdown;
+use serde::ser::SerializeStruct;
 use serde::Deserialize;
 use serde::Serialize;
+use serde::Serializer;
 use slug::slugify;
 use thiserror::Error;
 
@@ -30,7 +32,8 @@ use self::cascade::Cascade;
 /// badly to implement my own type-safe template language…)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Metadata {
-   /// The title of the item.
+   /// The title of the item, as plain text rather than Markdown: it goes where markup
+   /// cannot (`<title>`, feeds, gemtext, the labels of internal links), and is sorted on.
    pub title: String,
 
    /// The date the item was published.
@@ -42,15 +45,31 @@ pub struct Metadata {
    /// Which layout should be used to render this?
    pub layout: String,
 
+   /// The bibliography for citations in this item, if it has its own.
+   pub bibliography: Option<PathBuf>,
+
+   /// LaTeX macros for math in this item, in addition to the site's.
+   pub math: lx_md::Math,
+
+   /// Passages of scripture the item discusses, from its metadata and its content.
+   pub scripture: Vec<lx_md::Passage>,
+
+   /// What the item lists, if it is a listing page.
+   pub paginate: Option<crate::paginate::Listing>,
+
    pub book: Option<Book>,
    pub featured: bool,
    pub image: Option<Image>, // TODO: make it `Image`, not `Option`, and generate it .
    pub qualifiers: Qualifiers,
    pub series: Option<serial::Series>,
    pub subscribe: Option<serial::Subscribe>,
+   /// Rendered as inline Markdown, since it is shown within a heading.
    pub subtitle: Option<Rendered>,
+   /// Rendered as inline Markdown, since it is a one-line description, for listings and
+   /// `<meta>` descriptions.
    pub summary: Option<Rendered>,
    pub tags: Vec<String>,
+   /// Rendered as full Markdown, since acknowledgements can run to several paragraphs.
    pub thanks: Option<Rendered>,
    pub updated: Vec<Update>,
    pub work: Option<MusicalWork>,
@@ -77,6 +96,7 @@ impl Metadata {
       })?;
 
       let render = |s: String| Rendered::as_markdown(&s, md);
+      let render_inline = |s: String| Rendered::as_inline_markdown(&s, md);
 
       let work = MusicalWork::resolved(item.work, cascade.work(dir))?;
 
@@ -92,12 +112,12 @@ impl Metadata {
          title,
          date: item.date,
          slug: Slug::new(permalink.as_deref(), &source.path)?,
-         subtitle: item.subtitle.map(render).transpose()?,
+         subtitle: item.subtitle.map(render_inline).transpose()?,
          layout: item
             .layout
             .or(cascade.layout(dir))
             .unwrap_or(default_template_name),
-         summary: item.summary.map(render).transpose()?,
+         summary: item.summary.map(render_inline).transpose()?,
          qualifiers: {
             let from_item = item.qualifiers.unwrap_or_default();
             let from_cascade = cascade.qualifiers(dir).unwrap_or_default();
@@ -145,6 +165,12 @@ impl Metadata {
             tags
          },
          featured: item.featured,
+         bibliography: item.bibliography.map(|path| dir.join(path)),
+         math: item.math.unwrap_or_default(),
+         // Normalized along with the references in the content; see
+         // `page::Prepared::scripture`.
+         scripture: Vec::new(),
+         paginate: item.paginate,
          image: item.image.or(cascade.image(dir)).map(Image::from),
          book: item.book.or(cascade.book(dir)).map(Book::from),
          series: item.series.or(cascade.series(dir)),
@@ -156,12 +182,27 @@ impl Metadata {
    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Rendered {
    source: String,
    html: String,
 }
 
+/// The HTML is marked safe for templates, so that auto-escaping leaves it as it is.
+impl Serialize for Rendered {
+   fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+      let mut rendered = serializer.serialize_struct("Rendered", 2)?;
+      rendered.serialize_field("source", &self.source)?;
+      if minijinja::value::serializing_for_value() {
+         let html = minijinja::Value::from_safe_string(self.html.clone());
+         rendered.serialize_field("html", &html)?;
+      } else {
+         rendered.serialize_field("html", &self.html)?;
+      }
+      rendered.end()
+   }
+}
+
 impl Rendered {
    fn as_markdown(src: &str, md: &Markdown) -> Result<Rendered, Error> {
       md.render(src, |s| Ok(s.to_string()))
@@ -172,6 +213,15 @@ impl Rendered {
          .map_err(Error::from)
    }
 
+   fn as_inline_markdown(src: &str, md: &Markdown) -> Result<Rendered, Error> {
+      md.render_inline(src, |s| Ok(s.to_string()))
+         .map(|rendered| Rendered {
+            source: src.to_owned(),
+            html: rendered.html().to_string(),
+         })
+         .map_err(Error::from)
+   }
+
    pub fn plain(&self) -> String {
       // TODO: at construction above, create a plain text version as well as an HTML
       // version of the text.
@@ -592,14 +642,66 @@ mod tests {
       assert_eq!(Slug::new(None, &source).unwrap(), Slug::FromPath(expected));
    }
 
+   #[test]
+   fn rendered_html_is_safe_in_templates() {
+      let subtitle = Rendered {
+         source: String::from("*Tom* & Jerry"),
+         html: String::from("<em>Tom</em> &amp; Jerry"),
+      };
+      let html = minijinja::Environment::new()
+         .render_named_str(
+            "content.html",
+            "{{ subtitle.html }}, {{ subtitle.source }}",
+            minijinja::context! { subtitle },
+         )
+         .unwrap();
+      assert_eq!(html, "<em>Tom</em> &amp; Jerry, *Tom* &amp; Jerry");
+   }
+
+   #[test]
+   fn short_fields_are_rendered_inline() {
+      let item = serial::Item {
+         title: Some(String::from("Title")),
+         subtitle: Some(String::from("A *short* one")),
+         summary: Some(String::from("About `lx`")),
+         thanks: Some(String::from("To *you*.\n\nAnd others.")),
+         ..Default::default()
+      };
+      let source = page::Source {
+         path: PathBuf::from("content/essays/title.md"),
+         contents: String::new(),
+      };
+      let cascade = Cascade::new(&[]).unwrap();
+      let md = Markdown::new(None);
+      let resolve = |item| {
+         Metadata::resolved(item, &source, &cascade, String::from("base.jinja"), &md)
+      };
+
+      let metadata = resolve(item).unwrap();
+      let html = |rendered: Option<Rendered>| rendered.unwrap().html;
+      assert_eq!(html(metadata.subtitle), "A <em>short</em> one");
+      assert_eq!(html(metadata.summary), "About <code>lx</code>");
+      assert_eq!(
+         html(metadata.thanks),
+         "<p>To <em>you</em>.</p>\n<p>And others.</p>\n"
+      );
+
+      let item = serial::Item {
+         title: Some(String::from("Title")),
+         summary: Some(String::from("# Not a heading")),
+         ..Default::default()
+      };
+      assert!(resolve(item).is_err());
+   }
+
    #[test]
    fn nice_list_formatting() {
       assert_eq!(
-         nice_list(&["a", "b", "c"]),
+         nice_list(["a", "b", "c"]),
          Some(String::from("a, b, and c"))
       );
-      assert_eq!(nice_list(&["a", "b"]), Some(String::from("a and b")));
-      assert_eq!(nice_list(&["a"]), Some(String::from("a")));
-      assert_eq!(nice_list(&Vec::<String>::new()), None);
+      assert_eq!(nice_list(["a", "b"]), Some(String::from("a and b")));
+      assert_eq!(nice_list(["a"]), Some(String::from("a")));
+      assert_eq!(nice_list(Vec::<String>::new()), None);
    }
 }
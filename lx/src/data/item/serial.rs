@@ -5,6 +5,7 @@
 use std::{
    collections::HashMap,
    fmt::{self},
+   path::PathBuf,
 };
 
 use chrono::{DateTime, FixedOffset};
@@ -26,6 +27,19 @@ pub struct Item {
    pub started: Option<DateTime<FixedOffset>>,
    #[serde(default)]
    pub updated: Vec<Update>,
+   /// A BibTeX (`.bib`) or CSL-JSON (`.json`) file, relative to the item, for its
+   /// citations. Replaces the site-wide bibliography for this item.
+   pub bibliography: Option<PathBuf>,
+   /// LaTeX macros for math in this item, in addition to (and taking precedence over)
+   /// the site-wide macros.
+   pub math: Option<lx_md::Math>,
+   /// Passages of scripture the item discusses, in addition to any referenced in its
+   /// content.
+   #[serde(default)]
+   pub scripture: Vec<BibleRef>,
+   /// Makes the item a listing of other pages, split into pages of its own: see
+   /// [`crate::paginate::Listing`].
+   pub paginate: Option<crate::paginate::Listing>,
    // --- Begin section of fields also available in AmbientMetadata --- //
    pub book: Option<Book>,
    #[serde(default)]
@@ -137,10 +151,13 @@ impl fmt::Display for Authorship {
    }
 }
 
+/// A scripture reference in an item's metadata, e.g. `{ book: Rom, passage: "8:28" }`.
+/// The book is normalized to its canonical name, so any of its usual abbreviations work.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BibleRef {
-   translation: String,
-   book: String,
+   /// The translation to read the passage in, if not the site's.
+   pub translation: Option<String>,
+   pub book: String,
    /// This needs to handle the following:
    ///
    /// - Individual verse references: "John 1:1"
@@ -152,7 +169,7 @@ pub struct BibleRef {
    /// Given all of these, "just use a string" makes far more sense than trying
    /// to build a complex set of alternative types for it. (This is, after all,
    /// not a Bible application, where that would be a thing to parse!)
-   passage: String,
+   pub passage: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
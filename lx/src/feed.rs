@@ -9,6 +9,7 @@ use thiserror::Error;
 use crate::{
    data::config::Config,
    page::{Page, PageAndConfig, Updated},
+   paginate::Paginator,
 };
 
 /// Required resources for a `Feed`.
@@ -23,17 +24,40 @@ pub struct Feed<'a> {
    /// The set of items to render in the feed. A read-only slice because I will
    /// never actually need to *write* to these. I just need the parsed metadata
    /// and rendered HTML contents of the page, to render into the template.
-   items: &'a [Page<'a>],
+   items: &'a [&'a Page<'a>],
+
+   /// The feed for the next page of a paginated listing, if there is one, as JSON Feed's
+   /// `next_url`.
+   next_url: Option<String>,
 }
 
 impl<'a> Feed<'a> {
-   pub fn _new(title: String, site_config: &'a Config, items: &'a [Page]) -> Feed<'a> {
+   pub fn new(
+      title: String,
+      site_config: &'a Config,
+      items: &'a [&'a Page<'a>],
+   ) -> Feed<'a> {
       Feed {
          title,
          site_config,
          items,
+         next_url: None,
       }
    }
+
+   /// Make this the feed for one page of a listing, linked to the feed (`file_name`, e.g.
+   /// `feed.json`) for the next page of the listing, if there is one.
+   pub fn paginated<T>(
+      mut self,
+      paginator: &Paginator<'_, T>,
+      file_name: &str,
+   ) -> Feed<'a> {
+      self.next_url = paginator
+         .next
+         .as_ref()
+         .map(|next| format!("{}{file_name}", next.url(self.site_config)));
+      self
+   }
 }
 
 #[derive(Error, Debug)]
@@ -51,11 +75,16 @@ impl<'a> TryFrom<Feed<'a>> for JSONFeed {
       let items = feed
          .items
          .iter()
-         .map(|page| json_feed::FeedItem::from(PageAndConfig(page, feed.site_config)))
+         .map(|&page| json_feed::FeedItem::from(PageAndConfig(page, feed.site_config)))
          .collect();
 
       // TODO: needs the info for the *feed* URL.
-      let feed = JSONFeed::builder(&feed.title, items)
+      let mut builder = JSONFeed::builder(&feed.title, items);
+      if let Some(next_url) = &feed.next_url {
+         builder = builder.with_next_url(next_url);
+      }
+
+      let feed = builder
          .with_author(&AuthorOptions {
             name: Some(&feed.site_config.author.name),
             url: None,
@@ -0,0 +1,106 @@
+//! Resolve internal links (`[[target]]`, `[[target|label]]`, `[label](lx:target)`)
+//! against every page in the site.
+//!
+//! A page can be the target of a link by any of:
+//!
+//! - its output path, e.g. `essays/jj-init` (with or without surrounding slashes)
+//! - its source path relative to the content directory, with or without the `.md`
+//!   extension, e.g. `essays/jj init.md` or `essays/jj init`
+//! - the last segment of its output path, e.g. `jj-init`, as long as that is unique
+//!
+//! Standard Markdown links to another source file by its path relative to the linking
+//! file (e.g. `[jj init](../essays/jj%20init.md)`) resolve to the same pages.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use lx_md::Resolved;
+use normalize_path::NormalizePath as _;
+
+use crate::{
+   data::item::Metadata,
+   page::{self, RootedPath, Source},
+};
+
+pub struct Targets {
+   by_key: HashMap<String, Resolved>,
+   content_dir: PathBuf,
+}
+
+impl Targets {
+   pub fn new<'a, I>(
+      pages: I,
+      content_dir: &Path,
+   ) -> Result<Targets, Vec<(PathBuf, page::Error)>>
+   where
+      I: IntoIterator<Item = (&'a Source, &'a Metadata)>,
+   {
+      let mut by_key = HashMap::new();
+      let mut ambiguous = HashSet::new();
+      let mut by_name = HashMap::<String, Resolved>::new();
+      let mut errors = Vec::new();
+
+      for (source, data) in pages {
+         let path = match RootedPath::new(&data.slug, content_dir) {
+            Ok(path) => path,
+            Err(e) => {
+               errors.push((source.path.clone(), e));
+               continue;
+            }
+         };
+         let rooted = path.as_ref().to_string_lossy().to_string();
+         let resolved = Resolved {
+            url: path.root_relative_url(),
+            title: data.title.clone(),
+         };
+
+         if let Ok(relative) = source.path.strip_prefix(content_dir) {
+            by_key.insert(relative.to_string_lossy().to_string(), resolved.clone());
+            by_key.insert(
+               relative.with_extension("").to_string_lossy().to_string(),
+               resolved.clone(),
+            );
+         }
+
+         if let Some(name) = path.as_ref().file_name() {
+            let name = name.to_string_lossy().to_string();
+            if by_name.insert(name.clone(), resolved.clone()).is_some() {
+               ambiguous.insert(name);
+            }
+         }
+
+         by_key.insert(rooted, resolved);
+      }
+
+      for (name, resolved) in by_name {
+         if !ambiguous.contains(&name) {
+            by_key.entry(name).or_insert(resolved);
+         }
+      }
+
+      if errors.is_empty() {
+         Ok(Targets {
+            by_key,
+            content_dir: content_dir.to_owned(),
+         })
+      } else {
+         Err(errors)
+      }
+   }
+
+   /// Ambiguous names are never added as keys, so they are reported like any other
+   /// missing target, rather than silently linking to whichever page happened to win.
+   pub fn resolve(&self, target: &str) -> Option<Resolved> {
+      self.by_key.get(target.trim().trim_matches('/')).cloned()
+   }
+
+   /// Resolve a path relative to the source file `from`, e.g. `../intro.md`.
+   pub fn resolve_relative(&self, from: &Path, path: &str) -> Option<Resolved> {
+      let path = from.parent()?.join(path).normalize();
+      let relative = path.strip_prefix(&self.content_dir).ok()?;
+      self
+         .by_key
+         .get(relative.to_string_lossy().as_ref())
+         .cloned()
+   }
+}
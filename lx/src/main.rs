@@ -15,15 +15,21 @@ use syntect::html::{css_for_theme_with_class_style, ClassStyle};
 use thiserror::Error;
 
 mod archive;
+mod backlinks;
 mod build;
 mod canonicalized;
+mod citations;
 mod collection;
 mod data;
 mod error;
 mod feed;
+mod links;
 mod md;
 mod page;
+mod paginate;
+mod replacements;
 mod sass;
+mod scripture;
 mod server;
 mod templates;
 
@@ -57,7 +63,10 @@ fn main() -> Result<(), anyhow::Error> {
          Ok(())
       }
 
-      Command::Develop { site_directory } => {
+      Command::Develop {
+         site_directory,
+         math_fallback,
+      } => {
          let directory = site_directory.unwrap_or_else(|| {
             info!(
                "No directory passed, using current working directory ({}) instead",
@@ -73,7 +82,7 @@ fn main() -> Result<(), anyhow::Error> {
             ));
          }
 
-         serve(&directory)?;
+         serve(&directory, math_fallback)?;
          Ok(())
       }
 
@@ -81,15 +90,25 @@ fn main() -> Result<(), anyhow::Error> {
          paths,
          include_metadata,
          full_html_output,
+         format,
+         site_directory,
       } => {
+         let site = match site_directory {
+            Some(directory) => Some(md::Site::load(directory.try_into()?)?),
+            None => None,
+         };
+         let input_path = paths.input.clone();
          let (input, output, dest) = parse_paths(paths)?;
          md::convert(
             input,
+            input_path.as_deref(),
             output,
             md::Include {
                metadata: include_metadata,
                wrapping_html: full_html_output,
             },
+            format,
+            site.as_ref(),
          )
          .map_err(|source| Error::Markdown { dest, source })?;
          Ok(())
@@ -285,7 +304,13 @@ enum Command {
    },
 
    /// Build and serve the site for development
-   Develop { site_directory: Option<PathBuf> },
+   Develop {
+      site_directory: Option<PathBuf>,
+
+      /// Render math which fails to convert as highlighted source instead of failing
+      #[arg(long)]
+      math_fallback: bool,
+   },
 
    /// Straight to the config. Give me completions for my own dang tool
    Completions,
@@ -306,6 +331,17 @@ enum Command {
          default_missing_value("true")
       )]
       full_html_output: bool,
+
+      /// What to emit: HTML, or a JSON document with the metadata, HTML, plain text,
+      /// table of contents, and footnotes. (`--metadata` and `--full-html` only apply
+      /// to HTML.)
+      #[arg(long, value_enum, default_value_t = md::Format::Html)]
+      format: md::Format,
+
+      /// Convert as part of this site, using its config, syntaxes, data cascade (when
+      /// the input is one of its content files), and styles (with `--full-html`).
+      #[arg(long = "site")]
+      site_directory: Option<PathBuf>,
    },
 
    /// Work with theme SCSS.
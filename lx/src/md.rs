@@ -1,41 +1,129 @@
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
+use lx_md::{Footnote, Heading, Markdown};
+use pulldown_cmark_escape::escape_html_body_text;
+use serde::Serialize;
 use serde_yaml::Value;
 
+use crate::build;
+use crate::canonicalized::Canonicalized;
+use crate::data::item::{self, cascade::Cascade, serial, Metadata};
+use crate::page::Source;
+
 pub struct Include {
    pub metadata: bool,
    pub wrapping_html: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+   /// The rendered HTML, optionally with the metadata as a table.
+   Html,
+   /// One JSON document with the metadata, HTML, plain text, TOC, and footnotes.
+   Json,
+}
+
+/// A site to convert content as part of: its configuration (including any extra
+/// syntaxes), its data cascade, and its styles.
+pub struct Site {
+   md: Markdown,
+   cascade: Cascade,
+   /// The CSS for each of the site's root stylesheets.
+   styles: Vec<String>,
+}
+
+impl Site {
+   pub fn load(directory: Canonicalized) -> Result<Site, Error> {
+      let config = build::config_for(&directory)?;
+      Ok(Site {
+         md: build::markdown_for(&directory, &config)?,
+         cascade: build::cascade_for(&directory)?,
+         styles: build::styles_for(&directory)?
+            .into_iter()
+            .map(|(_, css)| css)
+            .collect(),
+      })
+   }
+}
+
+/// Everything about the converted content, for `--format json`.
+#[derive(Serialize)]
+struct Document<'a> {
+   metadata: Option<Value>,
+   html: &'a str,
+   text: String,
+   toc: &'a [Heading],
+   footnotes: &'a [Footnote],
+}
+
+/// Convert the Markdown from `input`. With a `site`, this renders it the way that site
+/// would and, when `path` is the content's location in that site, resolves its metadata
+/// with the site's data cascade.
 pub fn convert(
    mut input: Box<dyn Read>,
+   path: Option<&Path>,
    mut output: Box<dyn Write>,
    include: Include,
+   format: Format,
+   site: Option<&Site>,
 ) -> Result<(), Error> {
    let mut src = String::new();
    input
       .read_to_string(&mut src)
       .map_err(|source| Error::ReadBuffer { source })?;
 
-   let (meta, rendered) = lx_md::Markdown::new(None)
-      .render(&src, |s| Ok(s.to_string()))
-      .map_err(Error::from)?;
+   let default_md;
+   let md = match site {
+      Some(site) => &site.md,
+      None => {
+         default_md = Markdown::new(None);
+         &default_md
+      }
+   };
+
+   let prepared = md.prepare(&src)?;
+   let meta = match (prepared.metadata_src, site.zip(path)) {
+      (Some(metadata_src), Some((site, path))) => {
+         Some(resolved(&metadata_src, &src, path, site)?)
+      }
+      (Some(metadata_src), None) => Some(serde_yaml::from_str::<Value>(&metadata_src)?),
+      (None, _) => None,
+   };
+
+   let rendered = md
+      .emit(prepared.to_render, |s| Ok(s.to_string()))
+      .map_err(lx_md::Error::from)?;
+
+   if format == Format::Json {
+      let document = Document {
+         metadata: meta,
+         html: rendered.html(),
+         text: lx_md::strip_tags(rendered.html()).trim().to_string(),
+         toc: rendered.toc(),
+         footnotes: rendered.footnotes(),
+      };
+      serde_json::to_writer_pretty(&mut output, &document)
+         .map_err(|source| Error::Json { source })?;
+      return write("\n", &mut output);
+   }
 
    if include.wrapping_html {
       write(
-         r#"<html>
-          <head>
-              <link rel="stylesheet" href="/light.css" media="(prefers-color-scheme: light)" />
-              <link rel="stylesheet" href="/dark.css" media="(prefers-color-scheme: dark)" />
-          </head>
-          <body>"#,
+         "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
          &mut output,
       )?;
+      for css in site.iter().flat_map(|site| &site.styles) {
+         write("<style>\n", &mut output)?;
+         write(css, &mut output)?;
+         write("</style>\n", &mut output)?;
+      }
+      write("</head>\n<body>\n", &mut output)?;
    }
 
    if include.metadata {
       if let Some(metadata) = meta {
-         let metadata_table = match serde_yaml::from_str::<Value>(&metadata)? {
+         let metadata_table = match metadata {
             // Allowed, carry on. Uses `value` so that `yaml_to_value` below can simply be
             // a recursive function, with no special casing for `value`; I handle that
             // here.
@@ -75,18 +163,48 @@ pub fn convert(
    write(rendered.html(), &mut output)?;
 
    if include.wrapping_html {
-      write("</body></html>", &mut output)?;
+      write("</body>\n</html>\n", &mut output)?;
    }
 
    Ok(())
 }
 
+/// The metadata for the content at `path` as the site would resolve it, including
+/// anything from its data cascade.
+fn resolved(
+   metadata_src: &str,
+   src: &str,
+   path: &Path,
+   site: &Site,
+) -> Result<Value, Error> {
+   let path = path.canonicalize().map_err(|source| Error::Path {
+      path: path.to_owned(),
+      source,
+   })?;
+   let source = Source {
+      path,
+      contents: src.to_string(),
+   };
+
+   let item = serial::Item::try_parse(metadata_src)?;
+   let metadata = Metadata::resolved(
+      item,
+      &source,
+      &site.cascade,
+      String::from("base.jinja"),
+      &site.md,
+   )?;
+   Ok(serde_yaml::to_value(&metadata)?)
+}
+
 fn write(src: &str, dest: &mut Box<dyn Write>) -> Result<(), Error> {
    dest
       .write_all(src.as_bytes())
       .map_err(|source| Error::WriteBuffer { source })
 }
 
+/// Render metadata as HTML: a mapping is a table with a row for each key, and a sequence
+/// is a list, with any nested mappings and sequences rendered the same way inside them.
 fn yaml_to_html(
    source: &serde_yaml::Value,
    output: &mut Box<dyn Write>,
@@ -95,43 +213,39 @@ fn yaml_to_html(
       Value::Null => write("(null)", output),
       Value::Bool(bool) => write(&bool.to_string(), output),
       Value::Number(number) => write(&number.to_string(), output),
-      Value::String(string) => write(string, output),
+      Value::String(string) => write(&escape(string), output),
       Value::Sequence(values) => {
          write("<ul>", output)?;
          for value in values {
+            write("<li>", output)?;
             yaml_to_html(value, output)?;
+            write("</li>", output)?;
          }
          write("</ul>", output)?;
          Ok(())
       }
       Value::Mapping(mapping) => {
-         write("<table>", output)?;
-         let (keys, values) = mapping.into_iter().collect::<(Vec<_>, Vec<_>)>();
-         if !keys.is_empty() {
-            write("<thead><tr>", output)?;
-            for key in keys {
-               write("<th>", output)?;
-               yaml_to_html(key, output)?;
-               write("</th>", output)?;
-            }
-            write("</tr></thead>", output)?;
-
-            write("<tbody><tr>", output)?;
-            for value in values {
-               write("<td>", output)?;
-               yaml_to_html(value, output)?;
-               write("</td>", output)?;
-            }
-            write("</tr></tbody>", output)?;
+         write("<table><tbody>", output)?;
+         for (key, value) in mapping {
+            write("<tr><th>", output)?;
+            yaml_to_html(key, output)?;
+            write("</th><td>", output)?;
+            yaml_to_html(value, output)?;
+            write("</td></tr>", output)?;
          }
-
-         write("</table>", output)?;
+         write("</tbody></table>", output)?;
          Ok(())
       }
-      Value::Tagged(tagged_value) => write(&format!("{tagged_value:?}"), output),
+      Value::Tagged(tagged_value) => write(&escape(&format!("{tagged_value:?}")), output),
    }
 }
 
+fn escape(text: &str) -> String {
+   let mut escaped = String::with_capacity(text.len());
+   escape_html_body_text(&mut escaped, text).expect("writing to a String cannot fail");
+   escaped
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
    #[error("could not read buffer")]
@@ -152,6 +266,33 @@ pub enum Error {
       source: lx_md::Error,
    },
 
+   #[error("could not load site")]
+   Site {
+      #[from]
+      source: build::Error,
+   },
+
+   #[error("could not find '{path}'")]
+   Path {
+      path: PathBuf,
+      source: std::io::Error,
+   },
+
+   #[error(transparent)]
+   ParseMetadata {
+      #[from]
+      source: serial::ItemParseError,
+   },
+
+   #[error("could not resolve metadata")]
+   Metadata {
+      #[from]
+      source: item::Error,
+   },
+
+   #[error("could not write JSON")]
+   Json { source: serde_json::Error },
+
    #[error(
       "Could not render YAML metadata as an HTML table. Instead of a table it was: {src}"
    )]
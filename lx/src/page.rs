@@ -7,11 +7,13 @@ use std::{
 };
 
 use chrono::{DateTime, FixedOffset};
-use lx_md::{self, Markdown, RenderError, ToRender};
+use log::warn;
+use lx_md::{self, Bibliography, Markdown, RenderError, ToRender};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::citations::Citations;
 use crate::data::{
    config::Config,
    item::{self, cascade::Cascade, serial, Metadata, Slug},
@@ -25,12 +27,14 @@ pub fn prepare<'e>(
    let lx_md::Prepared {
       metadata_src,
       to_render,
-   } = lx_md::prepare(&source.contents)?;
+   } = md.prepare(&source.contents)?;
 
+   let mut scripture = Vec::new();
    let data = metadata_src
       .ok_or(Error::MissingMetadata)
       .and_then(|src| serial::Item::try_parse(&src).map_err(Error::from))
-      .and_then(|item_metadata| {
+      .and_then(|mut item_metadata| {
+         scripture = std::mem::take(&mut item_metadata.scripture);
          Metadata::resolved(
             item_metadata,
             source,
@@ -41,27 +45,105 @@ pub fn prepare<'e>(
          .map_err(Error::from)
       })?;
 
-   Ok(Prepared { data, to_render })
+   Ok(Prepared {
+      data,
+      scripture,
+      to_render,
+   })
 }
 
 pub struct Prepared<'e> {
    /// The fully-parsed metadata associated with the page.
    data: Metadata,
 
+   /// Scripture references from the metadata, not yet normalized.
+   scripture: Vec<serial::BibleRef>,
+
    to_render: ToRender<'e>,
 }
 
 impl Prepared<'_> {
+   pub fn data(&self) -> &Metadata {
+      &self.data
+   }
+
+   pub fn resolve_links(
+      mut self,
+      resolve: impl Fn(&str) -> Option<lx_md::Resolved>,
+   ) -> Result<Self, Error> {
+      self.to_render.resolve_links(resolve)?;
+      Ok(self)
+   }
+
+   pub fn resolve_relative_links(
+      mut self,
+      resolve: impl Fn(&str) -> Option<lx_md::Resolved>,
+   ) -> Result<Self, Error> {
+      self.to_render.resolve_relative_links(resolve)?;
+      Ok(self)
+   }
+
+   /// Format citations with the item's own bibliography if it has one, or the site's
+   /// otherwise. Without either, citations are left as they were written.
+   pub fn cite(mut self, citations: &Citations) -> Result<Self, Error> {
+      let own = self
+         .data
+         .bibliography
+         .as_deref()
+         .map(Bibliography::from_path)
+         .transpose()?;
+
+      if let Some(bibliography) = own.as_ref().or(citations.bibliography()) {
+         self.to_render.cite(bibliography, citations.style())?;
+      }
+
+      Ok(self)
+   }
+
+   /// Normalize the scripture references in the item's metadata and, when the site
+   /// configures scripture references, link those in its content, collecting both.
+   pub fn scripture(
+      mut self,
+      scripture: Option<&lx_md::Scripture>,
+   ) -> Result<Self, Error> {
+      let default = lx_md::Scripture::default();
+      let normalize = scripture.unwrap_or(&default);
+      for reference in std::mem::take(&mut self.scripture) {
+         let passage = normalize.passage(
+            &reference.book,
+            &reference.passage,
+            reference.translation.as_deref(),
+         )?;
+         if !self.data.scripture.contains(&passage) {
+            self.data.scripture.push(passage);
+         }
+      }
+
+      if let Some(scripture) = scripture {
+         for passage in self.to_render.link_scripture(scripture) {
+            if !self.data.scripture.contains(&passage) {
+               self.data.scripture.push(passage);
+            }
+         }
+      }
+
+      Ok(self)
+   }
+
+   /// Render the content, rewriting its text with `rewrite` and expanding its
+   /// shortcodes with `expand`, each of which also gets the item's metadata.
    pub fn render(
-      self,
+      mut self,
       md: &Markdown,
-      rewrite: impl Fn(
+      rewrite: impl Fn(&str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
+      expand: impl Fn(
+         &lx_md::Shortcode,
          &str,
-         &Metadata,
       ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>,
    ) -> Result<Rendered, Error> {
+      self.to_render.define_macros(&self.data.math.macros);
       Ok(Rendered {
-         content: md.emit(self.to_render, |text| rewrite(text, &self.data))?,
+         content: md.emit_with_shortcodes(self.to_render, rewrite, expand)?,
          data: self.data,
       })
    }
@@ -85,6 +167,15 @@ pub struct Source {
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Deserialize, Serialize)]
 pub struct Id(Uuid);
 
+impl Id {
+   pub fn for_source(source: &Source) -> Id {
+      Id(Uuid::new_v5(
+         &Uuid::NAMESPACE_OID,
+         source.path.as_os_str().as_bytes(),
+      ))
+   }
+}
+
 impl fmt::Display for Id {
    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
       write!(f, "{}", self.0)
@@ -121,10 +212,7 @@ impl<'s> Page<'s> {
       // TODO: This is the right idea for where I want to take this, but ultimately I
       // don't want to do it based on the source path (or if I do, *only* initially as
       // a way of generating it to start).
-      let id = Id(Uuid::new_v5(
-         &Uuid::NAMESPACE_OID,
-         source.path.as_os_str().as_bytes(),
-      ));
+      let id = Id::for_source(source);
 
       let path = RootedPath::new(&rendered.data.slug, in_dir)?;
 
@@ -167,6 +255,30 @@ pub enum Error {
       source: RenderError,
    },
 
+   #[error(transparent)]
+   Links {
+      #[from]
+      source: lx_md::UnresolvedLinks,
+   },
+
+   #[error("could not load item bibliography")]
+   Bibliography {
+      #[from]
+      source: lx_md::BibliographyError,
+   },
+
+   #[error(transparent)]
+   Citations {
+      #[from]
+      source: lx_md::UnknownCitations,
+   },
+
+   #[error("could not read scripture reference in metadata")]
+   Scripture {
+      #[from]
+      source: lx_md::UnknownBook,
+   },
+
    #[error("Invalid combination of root '{root}' and slug '{slug}'")]
    BadSlugRoot {
       source: std::path::StripPrefixError,
@@ -175,7 +287,7 @@ pub enum Error {
    },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RootedPath(PathBuf);
 
 impl RootedPath {
@@ -193,11 +305,26 @@ impl RootedPath {
       }
    }
 
-   /// Given a config, generate the (canonicalized) URL for the rooted path
+   /// A path within this one, e.g. `essays/page/2` for `page/2` within `essays`.
+   pub fn join(&self, path: impl AsRef<Path>) -> RootedPath {
+      RootedPath(self.0.join(path))
+   }
+
+   /// Given a config, generate the (canonicalized) URL for the rooted path, e.g.
+   /// `https://example.com/essays/hello/`. Like a root-relative URL, it ends with a
+   /// slash, so relative URLs in the page's content resolve against it correctly.
    pub fn url(&self, config: &Config) -> String {
-      String::from(config.url.trim_end_matches('/'))
-         + "/"
-         + self.0.to_str().expect("All paths are UTF-8")
+      self.url_on(&config.url)
+   }
+
+   fn url_on(&self, site_url: &str) -> String {
+      String::from(site_url.trim_end_matches('/')) + &self.root_relative_url()
+   }
+
+   /// The root-relative URL for the rooted path, e.g. `/essays/hello/`, which works
+   /// for both the live site and local development.
+   pub fn root_relative_url(&self) -> String {
+      format!("/{}/", self.0.to_str().expect("All paths are UTF-8"))
    }
 }
 
@@ -220,8 +347,16 @@ impl From<PageAndConfig<'_, '_, '_>> for json_feed::FeedItem {
          external_url: None, // TODO: support for page.link etc.
          title: Some(page.data.title.clone()),
          content_text: None, // TODO: use this for microblogging?
-         content_html: Some(page.content.html().to_string()),
-         summary: page.data.summary.as_ref().map(|summary| summary.plain()),
+         content_html: Some(absolute_html(page, config)),
+         summary: page
+            .data
+            .summary
+            .as_ref()
+            .map(|summary| summary.plain())
+            .or_else(|| {
+               let excerpt = lx_md::strip_tags(page.content.excerpt()).trim().to_string();
+               (!excerpt.is_empty()).then_some(excerpt)
+            }),
          image: None,        // TODO: add support for images to metadata
          banner_image: None, // TODO: add support for these if I care?
          date_published: page.data.date.map(|date| date.to_rfc3339()),
@@ -233,6 +368,17 @@ impl From<PageAndConfig<'_, '_, '_>> for json_feed::FeedItem {
    }
 }
 
+/// Feed readers show content away from its page, so its relative URLs would break.
+fn absolute_html(page: &Page, config: &Config) -> String {
+   match page.content.to_absolute(&page.path.url(config)) {
+      Ok(absolute) => absolute.html().to_string(),
+      Err(e) => {
+         warn!("{e}; leaving URLs in '{}' relative", page.data.title);
+         page.content.html().to_string()
+      }
+   }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Collections(HashMap<Id, crate::collection::Id>);
 
@@ -240,7 +386,7 @@ pub trait Updated {
    fn updated(&self) -> DateTime<FixedOffset>;
 }
 
-impl Updated for [Page<'_>] {
+impl Updated for [&Page<'_>] {
    fn updated(&self) -> chrono::DateTime<chrono::FixedOffset> {
       self
          .iter()
@@ -257,3 +403,27 @@ impl Updated for [Page<'_>] {
          .expect("should always be a latest date!")
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn relative_urls_resolve_against_the_page() {
+      let path = RootedPath(PathBuf::from("essays/foo"));
+      let url = path.url_on("https://example.com/");
+      assert_eq!(url, "https://example.com/essays/foo/");
+
+      let md = Markdown::new(None);
+      let (_, rendered) = md
+         .render("![pic](diagram.png) [o](../other/)", |s| Ok(s.to_string()))
+         .unwrap();
+      let absolute = rendered.to_absolute(&url).unwrap();
+      assert!(absolute
+         .html()
+         .contains(r#"src="https://example.com/essays/foo/diagram.png""#));
+      assert!(absolute
+         .html()
+         .contains(r#"href="https://example.com/essays/other/""#));
+   }
+}
@@ -0,0 +1,128 @@
+//! Split a listing into pages of a fixed size: the first at the listing's own path, and
+//! each of the rest at `page/<n>/` under it, e.g. `/essays/page/2/`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::page::RootedPath;
+
+/// Site-wide pagination settings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Pagination {
+   /// How many items each page of a listing shows, unless the listing sets its own.
+   pub size: usize,
+}
+
+impl Default for Pagination {
+   fn default() -> Self {
+      Pagination { size: 10 }
+   }
+}
+
+/// What a listing page lists, from its `paginate` metadata, e.g.
+/// `paginate: { section: essays, sort: -date, size: 20 }`. Takes the same arguments as
+/// the `pages()` template function.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Listing {
+   pub section: Option<String>,
+   pub tag: Option<String>,
+   pub sort: Option<String>,
+   /// Overrides the site's page size for this listing.
+   pub size: Option<usize>,
+}
+
+/// One page of a listing, as the `paginator` in its template context.
+#[derive(Serialize, Debug)]
+pub struct Paginator<'i, T> {
+   /// The items on this page.
+   pub items: &'i [T],
+   /// The number of this page, starting from 1.
+   pub current: usize,
+   /// How many pages there are, always at least 1.
+   pub total: usize,
+   /// The root-relative URL of the previous page, if there is one.
+   pub prev_url: Option<String>,
+   /// The root-relative URL of the next page, if there is one.
+   pub next_url: Option<String>,
+   /// Where this page goes in the output.
+   #[serde(skip)]
+   pub path: RootedPath,
+   /// Where the next page goes in the output, e.g. for a feed's `next_url`.
+   #[serde(skip)]
+   pub next: Option<RootedPath>,
+}
+
+/// Split `items` into pages of `size` items (at least one per page) for the listing at
+/// `base`. An empty listing still gets its first page.
+pub fn paginate<'i, T>(
+   items: &'i [T],
+   size: usize,
+   base: &RootedPath,
+) -> Vec<Paginator<'i, T>> {
+   let size = size.max(1);
+   let total = items.len().div_ceil(size).max(1);
+   let path_for = |number: usize| match number {
+      1 => base.clone(),
+      _ => base.join(format!("page/{number}")),
+   };
+
+   (1..=total)
+      .map(|current| {
+         let start = ((current - 1) * size).min(items.len());
+         let end = (start + size).min(items.len());
+         let prev = (current > 1).then(|| path_for(current - 1));
+         let next = (current < total).then(|| path_for(current + 1));
+         Paginator {
+            items: &items[start..end],
+            current,
+            total,
+            prev_url: prev.as_ref().map(RootedPath::root_relative_url),
+            next_url: next.as_ref().map(RootedPath::root_relative_url),
+            path: path_for(current),
+            next,
+         }
+      })
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::data::item::Slug;
+
+   #[test]
+   fn pages_link_to_each_other() {
+      let base = RootedPath::new(&Slug::Permalink("essays".into()), "".as_ref()).unwrap();
+      let items = [1, 2, 3, 4, 5];
+      let pages = paginate(&items, 2, &base);
+
+      let summary = pages
+         .iter()
+         .map(|page| {
+            (
+               page.items,
+               page.prev_url.as_deref(),
+               page.next_url.as_deref(),
+            )
+         })
+         .collect::<Vec<_>>();
+      assert_eq!(
+         summary,
+         [
+            (&items[0..2], None, Some("/essays/page/2/")),
+            (&items[2..4], Some("/essays/"), Some("/essays/page/3/")),
+            (&items[4..5], Some("/essays/page/2/"), None),
+         ]
+      );
+      assert_eq!(
+         pages[2].path.as_ref(),
+         std::path::Path::new("essays/page/3")
+      );
+      assert!(pages.iter().all(|page| page.total == 3));
+
+      let empty = paginate::<u8>(&[], 2, &base);
+      assert_eq!(
+         (empty.len(), empty[0].total, empty[0].next_url.clone()),
+         (1, 1, None)
+      );
+   }
+}
@@ -0,0 +1,94 @@
+//! Site-wide text replacements, e.g. `replacements: { "->": "→", "(c)": "©" }` in the
+//! site config: a [`Transform`] which `lx` registers with the Markdown renderer (see
+//! [`crate::build::markdown_for`]) for every site which configures any.
+
+use std::collections::BTreeMap;
+
+use lx_md::{Context, Located, Transform};
+use pulldown_cmark::{Event, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+
+/// Literal text to replace in prose, mapped to what to replace it with. Code (inline
+/// or in blocks) is left alone. Longer patterns are replaced first, so `->>` can mean
+/// something other than `->`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Replacements(BTreeMap<String, String>);
+
+impl Replacements {
+   pub fn is_empty(&self) -> bool {
+      self.0.is_empty()
+   }
+
+   fn apply(&self, text: &str) -> Option<String> {
+      let mut patterns = self
+         .0
+         .iter()
+         .filter(|(from, _)| !from.is_empty() && text.contains(from.as_str()))
+         .collect::<Vec<_>>();
+      if patterns.is_empty() {
+         return None;
+      }
+
+      patterns.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+      let mut replaced = String::with_capacity(text.len());
+      let mut rest = text;
+      'text: while !rest.is_empty() {
+         for (from, to) in &patterns {
+            if let Some(after) = rest.strip_prefix(from.as_str()) {
+               replaced.push_str(to);
+               rest = after;
+               continue 'text;
+            }
+         }
+
+         let c = rest.chars().next().expect("rest is not empty");
+         replaced.push(c);
+         rest = &rest[c.len_utf8()..];
+      }
+      Some(replaced)
+   }
+}
+
+impl Transform for Replacements {
+   fn transform<'e>(
+      &self,
+      mut events: Vec<Located<'e>>,
+      _context: &mut Context<'_, 'e>,
+   ) -> Result<Vec<Located<'e>>, Box<dyn std::error::Error + Send + Sync>> {
+      let mut in_code_block = false;
+      for located in &mut events {
+         match &located.event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Text(text) if !in_code_block => {
+               if let Some(replaced) = self.apply(text) {
+                  located.event = Event::Text(replaced.into());
+               }
+            }
+            _ => {}
+         }
+      }
+      Ok(events)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn replaces_text_but_not_code() {
+      let replacements = Replacements(BTreeMap::from([
+         (String::from("->"), String::from("→")),
+         (String::from("->>"), String::from("↠")),
+      ]));
+      let md = lx_md::Markdown::new(None).with_transform(replacements);
+      let src = "a -> b ->> c `x -> y`\n\n```\nx -> y\n```\n";
+      let (_, rendered) = md.render(src, |s| Ok(s.to_string())).unwrap();
+      assert_eq!(
+         rendered.html(),
+         "<p>a → b ↠ c <code>x -&gt; y</code></p>\n<pre><code>x -&gt; y\n</code></pre>"
+      );
+   }
+}
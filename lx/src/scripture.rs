@@ -0,0 +1,139 @@
+//! Index which pages discuss each book of the Bible, from the scripture references in
+//! their metadata and content.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use crate::page::Page;
+
+/// The path of the generated scripture index page, relative to the site root.
+pub const INDEX_PATH: &str = "scripture";
+
+/// A book of the Bible, with every page which discusses it.
+#[derive(Debug, Serialize)]
+pub struct Book {
+   pub name: String,
+   pub pages: Vec<Discussion>,
+}
+
+/// A page which discusses some book, with the passages from it the page references.
+#[derive(Debug, Serialize)]
+pub struct Discussion {
+   pub title: String,
+   pub url: String,
+   pub date: Option<DateTime<FixedOffset>>,
+   pub passages: Vec<lx_md::Passage>,
+}
+
+/// Every book at least one page discusses, in canonical order.
+#[derive(Debug, Serialize)]
+pub struct Index(Vec<Book>);
+
+impl Index {
+   pub fn new(pages: &[Page]) -> Index {
+      Index::collect(pages.iter().map(|page| Discussing {
+         title: &page.data.title,
+         url: page.path.root_relative_url(),
+         date: page.data.date,
+         passages: &page.data.scripture,
+      }))
+   }
+
+   fn collect<'p>(pages: impl IntoIterator<Item = Discussing<'p>>) -> Index {
+      let mut by_book = HashMap::<&str, Vec<Discussion>>::new();
+      for page in pages {
+         let mut passages = HashMap::<&str, Vec<lx_md::Passage>>::new();
+         for passage in page.passages {
+            passages
+               .entry(&passage.book)
+               .or_default()
+               .push(passage.clone());
+         }
+
+         for (book, passages) in passages {
+            by_book.entry(book).or_default().push(Discussion {
+               title: page.title.to_string(),
+               url: page.url.clone(),
+               date: page.date,
+               passages,
+            });
+         }
+      }
+
+      let books = lx_md::Scripture::books()
+         .filter_map(|name| {
+            let mut pages = by_book.remove(name.as_str())?;
+            // Newest first, as with backlinks, and undated pages last.
+            pages.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+            Some(Book { name, pages })
+         })
+         .collect();
+
+      Index(books)
+   }
+}
+
+/// What the index needs from each page: where it is, what to call it, and the passages
+/// it references.
+struct Discussing<'p> {
+   title: &'p str,
+   url: String,
+   date: Option<DateTime<FixedOffset>>,
+   passages: &'p [lx_md::Passage],
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn passage(book: &str, passage: &str) -> lx_md::Passage {
+      lx_md::Passage {
+         book: book.to_string(),
+         passage: passage.to_string(),
+         translation: String::from("ESV"),
+         url: String::new(),
+      }
+   }
+
+   #[test]
+   fn books_are_in_canonical_order_with_newest_pages_first() {
+      let old = [passage("Romans", "1:1"), passage("Genesis", "1:1")];
+      let new = [passage("Romans", "8:28"), passage("Romans", "12:1")];
+      let undated = [passage("Romans", "3:23")];
+      let pages = [
+         ("Old", Some("2023-01-01T00:00:00Z"), &old[..]),
+         ("Undated", None, &undated[..]),
+         ("New", Some("2024-01-01T00:00:00Z"), &new[..]),
+         ("None", Some("2025-01-01T00:00:00Z"), &[][..]),
+      ];
+
+      let Index(books) =
+         Index::collect(pages.iter().map(|&(title, date, passages)| Discussing {
+            title,
+            url: format!("/{}/", title.to_lowercase()),
+            date: date.map(|date| DateTime::parse_from_rfc3339(date).unwrap()),
+            passages,
+         }));
+
+      let summary = books
+         .iter()
+         .map(|book| {
+            let pages = book
+               .pages
+               .iter()
+               .map(|page| (page.title.as_str(), page.passages.len()))
+               .collect::<Vec<_>>();
+            (book.name.as_str(), pages)
+         })
+         .collect::<Vec<_>>();
+      assert_eq!(
+         summary,
+         [
+            ("Genesis", vec![("Old", 1)]),
+            ("Romans", vec![("New", 2), ("Old", 1), ("Undated", 1)]),
+         ]
+      );
+   }
+}
@@ -4,6 +4,7 @@ use std::{
    net::SocketAddr,
    path::{Path, PathBuf},
    pin::pin,
+   sync::mpsc as std_mpsc,
    time::Duration,
 };
 
@@ -23,64 +24,185 @@ use futures::{
 use log::{debug, error, info, trace};
 use lx_md::Markdown;
 use notify::RecursiveMode;
-use notify_debouncer_full::DebouncedEvent;
+use notify_debouncer_full::{DebounceEventResult, DebouncedEvent};
 use serde::Serialize;
 use tokio::{
    net::TcpListener,
    runtime::Runtime,
    sync::{
       broadcast::{self, error::RecvError, Sender},
-      mpsc,
+      mpsc, oneshot,
    },
    task::JoinError,
 };
 use tower_http::services::{ServeDir, ServeFile};
 use watchexec::error::CriticalError;
 
-// Initially, just rebuild everything. This can get smarter later!
-use crate::build::{self, config_for};
+use crate::{
+   build::{self, config_for, Site},
+   canonicalized::Canonicalized,
+   data::config::Config,
+};
 
 /// Serve the site, blocking on the result (i.e. blocking forever until it is
-/// killed by some kind of signal or failure).
-pub fn serve(site_dir: &Path) -> Result<(), Error> {
+/// killed by some kind of signal or failure). With `math_fallback`, math which fails to
+/// render is shown highlighted in the page instead of failing the build.
+pub fn serve(site_dir: &Path, math_fallback: bool) -> Result<(), Error> {
    // Instead of making `main` be `async` (regardless of whether it needs it, as
    // many operations do *not*), make *this* function handle it. An alternative
    // would be to do this same basic wrapping in `main` but only for this.
    let rt = Runtime::new().map_err(|e| Error::Io { source: e })?;
 
-   // This does not presently change for any reason. In principle it *could*, e.g. if I
-   // wanted to reload it when config changed to support reloading syntaxes. For now,
-   // though, this is sufficient.
-   let md = Markdown::new(None);
-
    // 1. Run an initial build.
    // 2. Create a watcher on the *input* directory, *not* the output directory.
-   // 3. When the watcher signals a change, use that to trigger a new *build*, not a
-   //    reload.
-   // 4. When the build finishes, use *that* to trigger a reload.
-   let site_dir = site_dir.try_into()?;
+   // 3. When the watcher signals a change, use that to trigger a new *build* (or, when
+   //    only layout templates changed, just a new render of the pages' layouts).
+   // 4. When the build finishes, its output changes, and *that* triggers a reload.
+   let site_dir: Canonicalized = site_dir.try_into()?;
    trace!("Building in {site_dir:?}");
    let config = config_for(&site_dir)?; // TODO: watch this separately?
    trace!("Computed config: {config:?}");
-   build::build(site_dir, &config, &md).map_err(Error::from)?;
+
+   // This does not presently change for any reason. In principle it *could*, e.g. if I
+   // wanted to reload it when config changed to support reloading syntaxes. For now,
+   // though, this is sufficient.
+   let md = build::markdown_for(&site_dir, &config)?.with_math_fallback(math_fallback);
+
+   let output = config.output.clone();
+   let (built_tx, built_rx) = oneshot::channel();
+   let rebuild_handle =
+      rt.spawn_blocking(move || rebuild_in(site_dir, config, md, built_tx));
+
+   // Only serve once the initial build succeeds; if it fails, the rebuild task ends with
+   // the reason.
+   if rt.block_on(built_rx).is_err() {
+      return match rt.block_on(rebuild_handle) {
+         Ok(result) => result,
+         Err(join_error) => Err(Error::Serve { source: join_error }),
+      };
+   }
 
    // I only need the tx side, since I am going to take advantage of the fact that
    // `broadcast::Sender` implements `Clone` to pass it around and get easy and convenient
    // access to local receivers with `tx.subscribe()`.
    let (tx, _rx) = broadcast::channel(10);
 
-   let serve_handle = rt.spawn(serve_in(config.output.clone(), tx.clone()));
-   let watch_handle = rt.spawn(watch_in(config.output.clone(), tx.clone()));
-   // TODO: actually *use* the handle!
-   let _rebuild_handle = (); // TODO
+   let serve_handle = rt.spawn(serve_in(output.clone(), tx.clone()));
+   let watch_handle = rt.spawn(watch_in(output, tx.clone()));
 
-   match rt.block_on(race_all([serve_handle, watch_handle])) {
+   match rt.block_on(race_all([serve_handle, watch_handle, rebuild_handle])) {
       Ok(Ok(_)) => Ok(()),
       Ok(Err(reason)) => Err(reason),
       Err(join_error) => Err(Error::Serve { source: join_error }),
    }
 }
 
+/// Watch the site's sources (including the shared sources), and build the site with
+/// [`rebuild_on_changes`] for as long as they are watched.
+fn rebuild_in(
+   site_dir: Canonicalized,
+   config: Config,
+   md: Markdown,
+   built: oneshot::Sender<()>,
+) -> Result<(), Error> {
+   let (tx, rx) = std_mpsc::channel();
+   let output = config.output.clone();
+   let mut debouncer = notify_debouncer_full::new_debouncer(
+      Duration::from_secs(1),
+      /*tick_rate */ None,
+      move |result: DebounceEventResult| match result {
+         Ok(events) => {
+            let paths = events
+               .into_iter()
+               // Building reads every source, which must not itself trigger a rebuild,
+               // and neither must writing the output.
+               .filter(|DebouncedEvent { event, .. }| !event.kind.is_access())
+               .flat_map(|DebouncedEvent { event, .. }| event.paths)
+               .filter(|path| !path.starts_with(&output))
+               .collect::<Vec<_>>();
+            if !paths.is_empty() && tx.send(paths).is_err() {
+               eprintln!("Could not send changed source paths.");
+            }
+         }
+         Err(errors) => error!("{}", Error::DebounceErrors(errors)),
+      },
+   )?;
+
+   let site_dir = site_dir.as_ref();
+   debouncer.watch(site_dir, RecursiveMode::Recursive)?;
+   if let Some(shared_dir) = site_dir.parent().map(|parent| parent.join("_shared")) {
+      if shared_dir.is_dir() {
+         debouncer.watch(&shared_dir, RecursiveMode::Recursive)?;
+      }
+   }
+
+   rebuild_on_changes(site_dir, &config, &md, &rx, built)
+}
+
+/// Build the site, signalling `built` once the first build succeeds, and then rebuild it
+/// for every set of changed paths from `changes`, until it stops sending them.
+fn rebuild_on_changes(
+   site_dir: &Path,
+   config: &Config,
+   md: &Markdown,
+   changes: &std_mpsc::Receiver<Vec<PathBuf>>,
+   built: oneshot::Sender<()>,
+) -> Result<(), Error> {
+   let mut built = Some(built);
+   loop {
+      match build_until_change(site_dir, config, md, changes, &mut built) {
+         Ok(Rebuild::Needed) => info!("Rebuilding for changed sources"),
+         Ok(Rebuild::Stopped) => return Ok(()),
+
+         // The initial build has to succeed; after that, a broken build is reported and
+         // then the next change gets a fresh try.
+         Err(reason) if built.is_none() => {
+            error!("{}", Error::from(reason));
+            if changes.recv().is_err() {
+               return Ok(());
+            }
+         }
+         Err(reason) => return Err(reason.into()),
+      }
+   }
+}
+
+enum Rebuild {
+   Needed,
+   Stopped,
+}
+
+/// Build the site, and then re-render its pages' layouts whenever only its layout
+/// templates change, until some other source changes (or the watcher stops).
+fn build_until_change(
+   site_dir: &Path,
+   config: &Config,
+   md: &Markdown,
+   changes: &std_mpsc::Receiver<Vec<PathBuf>>,
+   built: &mut Option<oneshot::Sender<()>>,
+) -> Result<Rebuild, build::Error> {
+   let mut site = Site::load(site_dir)?;
+   let sources = site.load_sources()?;
+   let pages = site.render(&sources, config, md)?;
+   site.write(config, &pages)?;
+   if let Some(built) = built.take() {
+      // Nothing to do if the server already stopped waiting.
+      let _ = built.send(());
+   }
+
+   while let Ok(paths) = changes.recv() {
+      if !paths.iter().all(|path| site.is_layout_template(path)) {
+         return Ok(Rebuild::Needed);
+      }
+
+      info!("Re-rendering layouts for changed templates");
+      site.reload_templates();
+      site.render_layouts(config, &pages)?;
+   }
+
+   Ok(Rebuild::Stopped)
+}
+
 async fn serve_in(path: PathBuf, state: Tx) -> Result<(), Error> {
    // This could be extracted into its own function.
    let serve_dir = ServeDir::new(&path).append_index_html_on_directories(true);
@@ -382,3 +504,138 @@ where
 {
    future::select_all(futures).await.0
 }
+
+#[cfg(test)]
+mod tests {
+   use std::{fs, thread};
+
+   use super::*;
+
+   /// A site with one page, which includes `note.jinja` in its content and renders with
+   /// the `page.jinja` layout, which includes `head.jinja`. Removed again when dropped.
+   struct TestSite {
+      root: PathBuf,
+      dir: Canonicalized,
+   }
+
+   impl TestSite {
+      fn new(name: &str) -> TestSite {
+         let root =
+            std::env::temp_dir().join(format!("lx-server-{}-{name}", std::process::id()));
+         let _ = fs::remove_dir_all(&root);
+
+         let dir = root.join("site");
+         for (path, contents) in [
+            (
+               "config.lx.yaml",
+               "url: 'https://example.com/'\n\
+                repo: 'https://example.com/repo'\n\
+                title: { normal: 'Example', stylized: 'Example' }\n\
+                subtitle: 'by Me'\n\
+                description: 'A test site.'\n\
+                author: { name: 'Me', email: 'me@example.com', links: {} }\n\
+                output: public\n\
+                image: social.png\n",
+            ),
+            // Smart punctuation would curl quotes, so the name comes from the metadata.
+            (
+               "content/index.md",
+               "---\ntitle: Hello\nlayout: page.jinja\ntags: [note.jinja]\n---\n\n\
+                Before {% include tags | first %}.\n",
+            ),
+            ("_ui/page.jinja", "{% include 'head.jinja' %}|{{ content }}"),
+            ("_ui/head.jinja", "head"),
+            ("_ui/note.jinja", "note"),
+            (
+               "_ui/components/aside.jinja",
+               "{% macro aside() %}{% endmacro %}",
+            ),
+         ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+         }
+
+         let dir = Canonicalized::try_from(dir).unwrap();
+         TestSite { root, dir }
+      }
+
+      fn path(&self, relative: &str) -> PathBuf {
+         self.dir.as_ref().join(relative)
+      }
+
+      fn output(&self) -> String {
+         fs::read_to_string(self.path("public/index/index.html")).unwrap_or_default()
+      }
+   }
+
+   impl Drop for TestSite {
+      fn drop(&mut self) {
+         let _ = fs::remove_dir_all(&self.root);
+      }
+   }
+
+   #[test]
+   fn layout_templates_are_only_those_the_content_does_not_load() {
+      let site = TestSite::new("layouts");
+      let config = config_for(&site.dir).unwrap();
+      let md = build::markdown_for(&site.dir, &config).unwrap();
+      let mut loaded = Site::load(site.dir.as_ref()).unwrap();
+      let sources = loaded.load_sources().unwrap();
+      loaded.render(&sources, &config, &md).unwrap();
+
+      assert!(loaded.is_layout_template(&site.path("_ui/page.jinja")));
+      assert!(loaded.is_layout_template(&site.path("_ui/head.jinja")));
+      assert!(!loaded.is_layout_template(&site.path("_ui/note.jinja")));
+      assert!(!loaded.is_layout_template(&site.path("_ui/components/aside.jinja")));
+      assert!(!loaded.is_layout_template(&site.path("content/index.md")));
+   }
+
+   #[test]
+   fn layout_changes_only_re_render_layouts() {
+      let site = TestSite::new("until-change");
+      let config = config_for(&site.dir).unwrap();
+      let md = build::markdown_for(&site.dir, &config).unwrap();
+      let (changes_tx, changes) = std_mpsc::channel();
+      let (built_tx, built_rx) = oneshot::channel();
+
+      let dir = site.dir.as_ref().to_owned();
+      let building = thread::spawn(move || {
+         build_until_change(&dir, &config, &md, &changes, &mut Some(built_tx))
+      });
+      built_rx.blocking_recv().unwrap();
+      assert_eq!(site.output(), "head|<p>Before note.</p>\n");
+
+      // The layout is loaded afresh, and the content used as it is; but the content
+      // loaded this one, so only a full rebuild will do.
+      fs::write(site.path("_ui/head.jinja"), "new head").unwrap();
+      changes_tx.send(vec![site.path("_ui/head.jinja")]).unwrap();
+      changes_tx.send(vec![site.path("_ui/note.jinja")]).unwrap();
+      assert!(matches!(building.join().unwrap(), Ok(Rebuild::Needed)));
+      assert_eq!(site.output(), "new head|<p>Before note.</p>\n");
+   }
+
+   #[test]
+   fn rebuilds_when_sources_change() {
+      let site = TestSite::new("rebuild");
+      let config = config_for(&site.dir).unwrap();
+      let md = build::markdown_for(&site.dir, &config).unwrap();
+      let (changes_tx, changes) = std_mpsc::channel();
+      let (built_tx, built_rx) = oneshot::channel();
+
+      let dir = site.dir.as_ref().to_owned();
+      let building = thread::spawn(move || {
+         rebuild_on_changes(&dir, &config, &md, &changes, built_tx)
+      });
+      built_rx.blocking_recv().unwrap();
+      assert_eq!(site.output(), "head|<p>Before note.</p>\n");
+
+      fs::write(site.path("_ui/note.jinja"), "a new note").unwrap();
+      changes_tx.send(vec![site.path("_ui/note.jinja")]).unwrap();
+
+      // Once the changes stop, so does rebuilding.
+      drop(changes_tx);
+      assert!(matches!(building.join().unwrap(), Ok(())));
+      assert_eq!(site.output(), "head|<p>Before a new note.</p>\n");
+   }
+}
@@ -0,0 +1,402 @@
+//! Filters for formatting dates and numbers, e.g. `{{ data.date | date("%B %-d, %Y") }}`.
+//!
+//! Dates reach templates serialized as RFC 3339 strings (as `Metadata::date` and
+//! `Update::at` do), so every date filter takes one of those.
+
+use chrono::{
+   format::{Item, StrftimeItems},
+   DateTime, Datelike, FixedOffset, Local, Month, TimeDelta, Weekday,
+};
+use minijinja::{value::ViaDeserialize, Error, ErrorKind, Value};
+
+use crate::archive::{Archive, MonthItems, Order, Year};
+
+pub(crate) fn add_all(env: &mut minijinja::Environment<'_>) {
+   env.add_filter("date", date);
+   env.add_filter("rfc3339", rfc3339);
+   env.add_filter("month_name", month_name);
+   env.add_filter("weekday_name", weekday_name);
+   env.add_filter("relative", relative);
+   env.add_filter("ordinal", ordinal);
+   env.add_filter("number", number);
+   env.add_filter("group_by_year", group_by_year);
+   env.add_filter("group_by_month", group_by_month);
+}
+
+type Date = DateTime<FixedOffset>;
+
+/// Format with a `strftime`-style format string (see [`chrono::format::strftime`]),
+/// e.g. `%B %-d, %Y` for `March 1, 2024`.
+fn date(
+   ViaDeserialize(date): ViaDeserialize<Date>,
+   format: Option<&str>,
+) -> Result<String, Error> {
+   let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+   let items = StrftimeItems::new(format).collect::<Vec<_>>();
+   if items.iter().any(|item| matches!(item, Item::Error)) {
+      return Err(invalid(format!("invalid date format '{format}'")));
+   }
+
+   Ok(date.format_with_items(items.into_iter()).to_string())
+}
+
+const DEFAULT_DATE_FORMAT: &str = "%B %-d, %Y";
+
+/// The full RFC 3339 timestamp, e.g. for `<time datetime="…">`.
+fn rfc3339(ViaDeserialize(date): ViaDeserialize<Date>) -> String {
+   date.to_rfc3339()
+}
+
+/// The name of the month of a date, or of a month number (1 for January), in the
+/// `locale` (English by default).
+fn month_name(value: Value, locale: Option<&str>) -> Result<String, Error> {
+   let number = match value.as_i64() {
+      Some(number) => number,
+      None => date_of(&value)?.month() as i64,
+   };
+   let month = u8::try_from(number)
+      .ok()
+      .and_then(|number| Month::try_from(number).ok())
+      .ok_or_else(|| invalid(format!("there is no month {number}")))?;
+
+   let names = names_for(locale.unwrap_or("en"))?;
+   Ok(names.months[month.number_from_month() as usize - 1].to_string())
+}
+
+/// The name of the day of the week of a date, or of a day number (1 for Monday, as in
+/// ISO 8601), in the `locale` (English by default).
+fn weekday_name(value: Value, locale: Option<&str>) -> Result<String, Error> {
+   let weekday = match value.as_i64() {
+      Some(number @ 1..=7) => Weekday::try_from(number as u8 - 1).expect("checked range"),
+      Some(number) => return Err(invalid(format!("there is no weekday {number}"))),
+      None => date_of(&value)?.weekday(),
+   };
+
+   let names = names_for(locale.unwrap_or("en"))?;
+   Ok(names.weekdays[weekday.num_days_from_monday() as usize].to_string())
+}
+
+/// How long before or after `to` (by default, now) the date is, in its largest whole
+/// unit, e.g. `3 days ago` or `in 2 months`.
+fn relative(
+   ViaDeserialize(date): ViaDeserialize<Date>,
+   to: Option<ViaDeserialize<Date>>,
+) -> String {
+   let to = to
+      .map(|ViaDeserialize(to)| to)
+      .unwrap_or_else(|| Local::now().fixed_offset());
+   describe(date - to)
+}
+
+fn describe(delta: TimeDelta) -> String {
+   const UNITS: [(&str, i64); 6] = [
+      ("year", 365 * 24 * 60 * 60),
+      ("month", 30 * 24 * 60 * 60),
+      ("week", 7 * 24 * 60 * 60),
+      ("day", 24 * 60 * 60),
+      ("hour", 60 * 60),
+      ("minute", 60),
+   ];
+
+   let seconds = delta.num_seconds();
+   let Some((unit, count)) = UNITS
+      .iter()
+      .map(|&(unit, size)| (unit, seconds.abs() / size))
+      .find(|&(_, count)| count > 0)
+   else {
+      return String::from("just now");
+   };
+
+   let plural = if count == 1 { "" } else { "s" };
+   if seconds < 0 {
+      format!("{count} {unit}{plural} ago")
+   } else {
+      format!("in {count} {unit}{plural}")
+   }
+}
+
+/// An ordinal number (`1st`, `22nd`, `113th`), or the ordinal day of the month of a
+/// date.
+fn ordinal(value: Value) -> Result<String, Error> {
+   let number = match value.as_i64() {
+      Some(number) => number,
+      None => date_of(&value)?.day() as i64,
+   };
+
+   let suffix = match (number.abs() % 10, number.abs() % 100) {
+      (_, 11..=13) => "th",
+      (1, _) => "st",
+      (2, _) => "nd",
+      (3, _) => "rd",
+      _ => "th",
+   };
+   Ok(format!("{number}{suffix}"))
+}
+
+/// A whole number with its thousands separated by `separator` (`,` by default), e.g.
+/// `12,345`.
+pub(super) fn number(value: i64, separator: Option<&str>) -> String {
+   let separator = separator.unwrap_or(",");
+   let digits = value.unsigned_abs().to_string();
+   let mut grouped = String::new();
+   for (index, digit) in digits.chars().enumerate() {
+      if index > 0 && (digits.len() - index) % 3 == 0 {
+         grouped.push_str(separator);
+      }
+      grouped.push(digit);
+   }
+
+   if value < 0 {
+      format!("-{grouped}")
+   } else {
+      grouped
+   }
+}
+
+/// Group items (e.g. from `pages()`) by the year of their `date`, newest first:
+/// `[{ year, items }]`. Items without a date are left out, as they are from the archive.
+fn group_by_year(items: Vec<Value>) -> Result<Value, Error> {
+   Ok(archive(items)?
+      .years
+      .into_iter()
+      .map(|year| {
+         minijinja::context! {
+            year => year.year,
+            items => year.into_items().collect::<Vec<_>>(),
+         }
+      })
+      .collect())
+}
+
+/// Group items (e.g. from `pages()`) by the year and month of their `date`, newest
+/// first: `[{ year, month, name, items }]`, where `month` is the month number and `name`
+/// its name in the `locale` (English by default), as from `month_name`.
+fn group_by_month(items: Vec<Value>, locale: Option<&str>) -> Result<Value, Error> {
+   let names = names_for(locale.unwrap_or("en"))?;
+   Ok(archive(items)?
+      .years
+      .into_iter()
+      .flat_map(|Year { year, months }| {
+         months.into_iter().map(move |MonthItems { month, items }| {
+            let month = month.number_from_month();
+            let name = names.months[month as usize - 1];
+            minijinja::context! { year, month, name, items }
+         })
+      })
+      .collect())
+}
+
+fn archive(items: Vec<Value>) -> Result<Archive<Value>, Error> {
+   let mut dated = Vec::with_capacity(items.len());
+   for item in items {
+      let date = item.get_attr("date")?;
+      if !(date.is_undefined() || date.is_none()) {
+         dated.push((date_of(&date)?, item));
+      }
+   }
+   Ok(Archive::new(dated, Order::NewFirst))
+}
+
+fn date_of(value: &Value) -> Result<Date, Error> {
+   let text = value
+      .as_str()
+      .ok_or_else(|| invalid(format!("expected a date, not {value}")))?;
+   DateTime::parse_from_rfc3339(text)
+      .map_err(|e| invalid(format!("expected an RFC 3339 date, not '{text}': {e}")))
+}
+
+fn invalid(message: String) -> Error {
+   Error::new(ErrorKind::InvalidOperation, message)
+}
+
+struct Names {
+   months: [&'static str; 12],
+   /// Monday first.
+   weekdays: [&'static str; 7],
+}
+
+fn names_for(locale: &str) -> Result<&'static Names, Error> {
+   // Only the language matters, so e.g. `en-GB` and `en_US` are both English.
+   let language = locale.split(['-', '_']).next().unwrap_or(locale);
+   LOCALES
+      .iter()
+      .find(|(code, _)| code.eq_ignore_ascii_case(language))
+      .map(|(_, names)| names)
+      .ok_or_else(|| invalid(format!("no month or day names for locale '{locale}'")))
+}
+
+static LOCALES: [(&str, Names); 4] = [
+   (
+      "en",
+      Names {
+         months: [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+         ],
+         weekdays: [
+            "Monday",
+            "Tuesday",
+            "Wednesday",
+            "Thursday",
+            "Friday",
+            "Saturday",
+            "Sunday",
+         ],
+      },
+   ),
+   (
+      "de",
+      Names {
+         months: [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+         ],
+         weekdays: [
+            "Montag",
+            "Dienstag",
+            "Mittwoch",
+            "Donnerstag",
+            "Freitag",
+            "Samstag",
+            "Sonntag",
+         ],
+      },
+   ),
+   (
+      "es",
+      Names {
+         months: [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+         ],
+         weekdays: [
+            "lunes",
+            "martes",
+            "miércoles",
+            "jueves",
+            "viernes",
+            "sábado",
+            "domingo",
+         ],
+      },
+   ),
+   (
+      "fr",
+      Names {
+         months: [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+         ],
+         weekdays: [
+            "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+         ],
+      },
+   ),
+];
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn render(template: &str) -> String {
+      let mut env = minijinja::Environment::new();
+      add_all(&mut env);
+      let date = "2024-03-01T09:30:00-07:00";
+      let pages = [
+         minijinja::context! { title => "C", date => "2024-03-02T00:00:00Z" },
+         minijinja::context! { title => "B", date => date },
+         minijinja::context! { title => "Undated" },
+         minijinja::context! { title => "A", date => "2023-12-31T00:00:00Z" },
+      ];
+      env.render_str(template, minijinja::context! { date, pages })
+         .unwrap()
+   }
+
+   #[test]
+   fn dates_and_numbers() {
+      assert_eq!(render("{{ date | date }}"), "March 1, 2024");
+      assert_eq!(render("{{ date | date('%Y-%m-%d') }}"), "2024-03-01");
+      assert_eq!(render("{{ date | rfc3339 }}"), "2024-03-01T09:30:00-07:00");
+      assert_eq!(
+         render("{{ date | month_name('fr') }} {{ date | weekday_name('de-AT') }}"),
+         "mars Freitag"
+      );
+      assert_eq!(
+         render("{{ date | relative('2024-03-04T09:30:00-07:00') }}"),
+         "3 days ago"
+      );
+      assert_eq!(
+         render("{{ 1 | ordinal }} {{ 12 | ordinal }} {{ 22 | ordinal }} {{ date | ordinal }}"),
+         "1st 12th 22nd 1st"
+      );
+      assert_eq!(
+         render("{{ 1234567 | number }} {{ -999 | number }}"),
+         "1,234,567 -999"
+      );
+   }
+
+   #[test]
+   fn grouping() {
+      assert_eq!(
+         render(
+            "{% for g in pages | group_by_month %}{{ g.name }} {{ g.year }}: \
+             {% for p in g.items %}{{ p.title }}{% endfor %}; {% endfor %}"
+         ),
+         "March 2024: CB; December 2023: A; "
+      );
+      assert_eq!(
+         render("{% for g in pages | group_by_month('de') %}{{ g.name }} {% endfor %}"),
+         "März Dezember "
+      );
+      assert_eq!(
+         render("{% for g in pages | group_by_year %}{{ g.year }}={{ g.items | length }} {% endfor %}"),
+         "2024=2 2023=1 "
+      );
+      assert_eq!(
+         render(
+            "{% for g in pages | reverse | group_by_year %}{{ g.year }} {% endfor %}"
+         ),
+         "2024 2023 "
+      );
+   }
+}
@@ -101,27 +101,7 @@ fn approximate_length(source: &str) -> String {
       (actual / 100) * 100
    };
 
-   let formatted = {
-      let formatted_number = rounded.to_string();
-      if formatted_number.len() <= 3 {
-         formatted_number
-      } else {
-         formatted_number
-            .chars()
-            .rev()
-            .enumerate()
-            .fold(String::new(), |mut s, (idx, c)| {
-               if idx > 0 && idx % 3 == 0 {
-                  s.push(',');
-               }
-               s.push(c);
-               s
-            })
-            .chars()
-            .rev()
-            .collect()
-      }
-   };
+   let formatted = super::filters::number(rounded as i64, None);
 
    format!("About {formatted} words")
 }
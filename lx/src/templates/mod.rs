@@ -1,19 +1,27 @@
+mod filters;
 mod functions;
+mod pages;
 mod rendering;
 
 use std::{
+   collections::HashMap,
    io::Write,
    path::{Path, PathBuf},
 };
 
-use log::{debug, trace};
-use minijinja::Environment;
+use log::{debug, trace, warn};
+use minijinja::{context, Environment, Value};
 use serde::Serialize;
 use thiserror::Error;
 
+pub use pages::Pages;
+
 use crate::{
+   backlinks::Backlink,
    data::{config::Config, item::Metadata},
    page::{Page, RootedPath, Source},
+   paginate::Paginator,
+   scripture::Index,
 };
 
 #[derive(Error, Debug)]
@@ -30,44 +38,107 @@ pub enum Error {
       path: PathBuf,
    },
 
-   #[error("could not add template for {path}")]
-   CouldNotAddTemplate {
+   #[error("could not load template for {path}: {source}")]
+   MissingTemplate {
       source: minijinja::Error,
       path: PathBuf,
    },
 
-   #[error("could not load template for {path}: {source}")]
-   MissingTemplate {
+   #[error("template {path} is not in {dir}")]
+   TemplatePath { path: PathBuf, dir: PathBuf },
+
+   #[error("templates in {path} would be hidden: 'shared/' is for shared templates")]
+   ReservedName { path: PathBuf },
+
+   #[error("no macro named '{name}' in any template in _ui/{COMPONENTS_DIR}")]
+   UnknownComponent { name: String },
+
+   #[error("could not expand shortcode '{name}'")]
+   Shortcode {
+      name: String,
       source: minijinja::Error,
-      path: PathBuf,
    },
 
    #[error(transparent)]
    Other(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
-pub fn load<I, F>(templates: I, trim_root: F) -> Result<Environment<'static>, Error>
-where
-   I: IntoIterator,
-   I::Item: AsRef<Path>,
-   for<'a> F: Fn(&'a Path) -> Result<&'a Path, Box<dyn std::error::Error + Send + Sync>>,
-{
+/// A directory of templates, e.g. a site's `_ui`, and the template files within it (at
+/// any depth). Each template is named by its path relative to the directory.
+pub struct Templates<'a> {
+   pub dir: &'a Path,
+   pub paths: &'a [PathBuf],
+}
+
+impl<'a> Templates<'a> {
+   /// Each template's name (see [`name_of`]).
+   fn names(&self) -> Result<Vec<String>, Error> {
+      self
+         .paths
+         .iter()
+         .map(|path| {
+            let Ok(relative) = path.strip_prefix(self.dir) else {
+               return Err(Error::TemplatePath {
+                  path: path.to_owned(),
+                  dir: self.dir.to_owned(),
+               });
+            };
+
+            Ok(name_of(relative))
+         })
+         .collect()
+   }
+}
+
+/// The name of the template at `relative` to its templates directory: its path, always
+/// `/`-separated.
+pub fn name_of(relative: &Path) -> String {
+   relative
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/")
+}
+
+/// The prefix for the name of every shared template, so an override can still reach the
+/// template it overrides, e.g. `{% extends "shared/base.jinja" %}`.
+pub const SHARED_PREFIX: &str = "shared/";
+
+/// Set up the environment to load templates on demand from the site's `_ui` directory
+/// and (if there is one) the shared `_ui` directory. A site template takes precedence
+/// over a shared template of the same name, but every shared template is also available
+/// with the `shared/` prefix.
+///
+/// Templates are cached once loaded; [`Environment::clear_templates`] reloads them.
+pub fn load(
+   site_dir: &Path,
+   shared_dir: Option<&Path>,
+) -> Result<Environment<'static>, Error> {
+   let reserved = site_dir.join(SHARED_PREFIX);
+   if reserved.exists() {
+      return Err(Error::ReservedName { path: reserved });
+   }
+
+   let site = minijinja::path_loader(site_dir);
+   let shared = shared_dir.map(minijinja::path_loader);
+
    let mut env = Environment::new();
    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
-   for path in templates {
-      let path = path.as_ref();
-      let name = trim_root(path)?.to_string_lossy().to_string();
-      let content = std::fs::read_to_string(path)?;
-      trace!("Adding template at {name}");
-      env.add_template_owned(name, content).map_err(|source| {
-         Error::CouldNotAddTemplate {
-            source,
-            path: path.to_owned(),
-         }
-      })?;
-   }
+   env.set_loader(move |name| {
+      trace!("Loading template {name}");
+      match (name.strip_prefix(SHARED_PREFIX), &shared) {
+         (Some(name), Some(shared)) => shared(name),
+         (Some(_), None) => Ok(None),
+         (None, Some(shared)) => match site(name)? {
+            Some(template) => Ok(Some(template)),
+            None => shared(name),
+         },
+         (None, None) => site(name),
+      }
+   });
 
    functions::add_all(&mut env);
+   filters::add_all(&mut env);
 
    Ok(env)
 }
@@ -76,6 +147,34 @@ pub fn render(
    env: &Environment,
    page: &Page,
    site: &Config,
+   backlinks: &[Backlink],
+   into: impl Write,
+) -> Result<(), Error> {
+   render_page(env, page, site, backlinks, None, &page.path, into)
+}
+
+/// Render one page of a listing page: like [`render`], but with the `paginator` in the
+/// context, and with the `path` of that page of the listing.
+pub fn render_listing<T: Serialize>(
+   env: &Environment,
+   page: &Page,
+   site: &Config,
+   backlinks: &[Backlink],
+   paginator: &Paginator<'_, T>,
+   into: impl Write,
+) -> Result<(), Error> {
+   let path = &paginator.path;
+   let paginator = Value::from_serialize(paginator);
+   render_page(env, page, site, backlinks, Some(paginator), path, into)
+}
+
+fn render_page(
+   env: &Environment,
+   page: &Page,
+   site: &Config,
+   backlinks: &[Backlink],
+   paginator: Option<Value>,
+   path: &RootedPath,
    into: impl Write,
 ) -> Result<(), Error> {
    /// Local struct because I just need a convenient way to provide serializable data to
@@ -83,10 +182,14 @@ pub fn render(
    #[derive(Serialize)]
    struct Context<'a> {
       content: &'a str,
+      excerpt: &'a str,
       data: &'a Metadata,
       config: &'a Config,
       path: &'a RootedPath,
       source: &'a Source,
+      backlinks: &'a [Backlink],
+      #[serde(skip_serializing_if = "Option::is_none")]
+      paginator: Option<Value>,
    }
 
    debug!(
@@ -106,10 +209,13 @@ pub fn render(
    tpl.render_to_write(
       Context {
          content: page.content.html(),
+         excerpt: page.content.excerpt(),
          data: &page.data,
          config: site,
-         path: &page.path,
+         path,
          source: page.source,
+         backlinks,
+         paginator,
       },
       into,
    )
@@ -119,3 +225,366 @@ pub fn render(
       path: page.source.path.to_owned(),
    })
 }
+
+/// Rewrite the template syntax in a page's content. Values are HTML-escaped as they are
+/// inserted, unless they are marked safe (as e.g. rendered Markdown in the metadata is),
+/// since the content around them is already HTML.
+pub fn rewrite(
+   env: &Environment,
+   text: &str,
+   context: &Value,
+) -> Result<String, minijinja::Error> {
+   env.render_named_str(CONTENT_TEMPLATE, text, context)
+}
+
+/// The name for a page's content as a template: the extension turns on auto-escaping.
+const CONTENT_TEMPLATE: &str = "content.html";
+
+/// Render the glossary page with the `glossary.jinja` template, which gets every term
+/// (sorted) along with its definition and the `anchor` to use as its `id`.
+pub fn render_glossary(
+   env: &Environment,
+   glossary: &lx_md::Glossary,
+   site: &Config,
+   into: impl Write,
+) -> Result<(), Error> {
+   #[derive(Serialize)]
+   struct Term<'a> {
+      term: &'a str,
+      definition: &'a str,
+      anchor: String,
+   }
+
+   #[derive(Serialize)]
+   struct Context<'a> {
+      terms: Vec<Term<'a>>,
+      config: &'a Config,
+   }
+
+   let terms = glossary
+      .terms
+      .iter()
+      .map(|(term, definition)| Term {
+         term,
+         definition,
+         anchor: lx_md::Glossary::anchor(term),
+      })
+      .collect();
+
+   render_generated(
+      env,
+      GLOSSARY_TEMPLATE,
+      Context {
+         terms,
+         config: site,
+      },
+      into,
+   )
+}
+
+const GLOSSARY_TEMPLATE: &str = "glossary.jinja";
+
+/// Render the scripture index page with the `scripture.jinja` template, which gets
+/// `books` (each with its `name` and the `pages` which discuss it, in canonical order)
+/// and the site `config`.
+pub fn render_scripture_index(
+   env: &Environment,
+   index: &Index,
+   site: &Config,
+   into: impl Write,
+) -> Result<(), Error> {
+   #[derive(Serialize)]
+   struct Context<'a> {
+      books: &'a Index,
+      config: &'a Config,
+   }
+
+   render_generated(
+      env,
+      SCRIPTURE_TEMPLATE,
+      Context {
+         books: index,
+         config: site,
+      },
+      into,
+   )
+}
+
+const SCRIPTURE_TEMPLATE: &str = "scripture.jinja";
+
+/// Render a page which lx generates, rather than one from a source file, with the
+/// `template` of that name.
+fn render_generated(
+   env: &Environment,
+   template: &str,
+   context: impl Serialize,
+   into: impl Write,
+) -> Result<(), Error> {
+   let path = PathBuf::from(template);
+   let tpl = env
+      .get_template(template)
+      .map_err(|source| Error::MissingTemplate {
+         source,
+         path: path.clone(),
+      })?;
+
+   tpl.render_to_write(context, into)
+      .map(|_state| ())
+      .map_err(|source| Error::Render { source, path })
+}
+
+/// The directory (within `_ui`) of templates whose macros are available as shortcodes.
+const COMPONENTS_DIR: &str = "components/";
+
+/// Every macro defined in a template in `_ui/components/`, by name, with the name of the
+/// template which defines it.
+pub struct Components(HashMap<String, String>);
+
+impl Components {
+   /// Find the components among the site's and shared templates (loading them).
+   pub fn new(
+      env: &Environment,
+      site: Templates<'_>,
+      shared: Option<Templates<'_>>,
+   ) -> Result<Components, Error> {
+      let mut names = site.names()?;
+      if let Some(shared) = shared {
+         names.extend(shared.names()?);
+      }
+      names.sort();
+      names.dedup();
+
+      let mut components = HashMap::new();
+      for template_name in names {
+         if !is_component(&template_name) {
+            continue;
+         }
+
+         let template = env.get_template(&template_name).map_err(|source| {
+            Error::MissingTemplate {
+               source,
+               path: PathBuf::from(&template_name),
+            }
+         })?;
+         let state = template.eval_to_state(()).map_err(|source| Error::Render {
+            source,
+            path: PathBuf::from(&template_name),
+         })?;
+         for name in state.exports() {
+            if let Some(other) =
+               components.insert(name.to_string(), template_name.clone())
+            {
+               warn!("'{template_name}' redefines '{name}' from '{other}'");
+            }
+         }
+      }
+
+      Ok(Components(components))
+   }
+}
+
+/// Whether the template (named relative to `_ui`) is a component, i.e. one whose macros
+/// are available as shortcodes.
+pub fn is_component(template_name: &str) -> bool {
+   template_name.starts_with(COMPONENTS_DIR)
+}
+
+/// Expand a shortcode by calling its component's macro with the shortcode's arguments,
+/// with its rendered `content` as the macro's `caller()`. The `context` (the item's
+/// metadata) is available to the arguments, as it is to any other template syntax in
+/// the content.
+pub fn expand_shortcode(
+   env: &Environment,
+   components: &Components,
+   shortcode: &lx_md::Shortcode,
+   content: &str,
+   context: &Value,
+) -> Result<String, Error> {
+   let lx_md::Shortcode {
+      name, arguments, ..
+   } = shortcode;
+   let template = components
+      .0
+      .get(name)
+      .ok_or_else(|| Error::UnknownComponent { name: name.clone() })?;
+
+   // Shortcode names are always identifiers, and the arguments are template syntax.
+   let source = format!(
+      "{{% from __component import {name} %}}\
+       {{% call {name}({arguments}) %}}{{{{ __content }}}}{{% endcall %}}"
+   );
+   env.render_named_str(
+      &format!("shortcode '{name}'"),
+      &source,
+      context! {
+         __component => template,
+         __content => Value::from_safe_string(content.to_string()),
+         ..context.clone()
+      },
+   )
+   .map_err(|source| Error::Shortcode {
+      name: name.clone(),
+      source,
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use std::fs;
+
+   use super::*;
+
+   /// A directory of templates for a test, removed again when it is dropped.
+   struct TempDir {
+      dir: PathBuf,
+      paths: Vec<PathBuf>,
+   }
+
+   impl TempDir {
+      fn new(name: &str, templates: &[(&str, &str)]) -> TempDir {
+         let dir = std::env::temp_dir()
+            .join(format!("lx-templates-{}-{name}", std::process::id()));
+         let _ = fs::remove_dir_all(&dir);
+
+         let paths = templates
+            .iter()
+            .map(|(name, contents)| {
+               let path = dir.join(name);
+               fs::create_dir_all(path.parent().unwrap()).unwrap();
+               fs::write(&path, contents).unwrap();
+               path
+            })
+            .collect();
+         fs::create_dir_all(&dir).unwrap();
+
+         TempDir { dir, paths }
+      }
+
+      fn templates(&self) -> Templates<'_> {
+         Templates {
+            dir: &self.dir,
+            paths: &self.paths,
+         }
+      }
+   }
+
+   impl Drop for TempDir {
+      fn drop(&mut self) {
+         let _ = fs::remove_dir_all(&self.dir);
+      }
+   }
+
+   #[test]
+   fn shortcodes_call_component_macros() {
+      let site = TempDir::new(
+         "components-site",
+         &[
+            (
+               "components/callout.jinja",
+               "{% macro callout(kind) %}<aside class=\"{{ kind }}\">{{ caller() }}</aside>\
+                {% endmacro %}",
+            ),
+            ("page.jinja", "{% macro ignored() %}{% endmacro %}"),
+         ],
+      );
+      let shared = TempDir::new(
+         "components-shared",
+         &[(
+            "components/nested/figure.jinja",
+            "{% macro figure() %}<figure>{{ caller() }}</figure>{% endmacro %}",
+         )],
+      );
+
+      let env = load(&site.dir, Some(&shared.dir)).unwrap();
+      let components =
+         Components::new(&env, site.templates(), Some(shared.templates())).unwrap();
+      let mut names = components.0.iter().collect::<Vec<_>>();
+      names.sort();
+      assert_eq!(
+         names,
+         [
+            (
+               &String::from("callout"),
+               &String::from("components/callout.jinja")
+            ),
+            (
+               &String::from("figure"),
+               &String::from("components/nested/figure.jinja")
+            ),
+         ]
+      );
+
+      let md = lx_md::Markdown::new(None);
+      let context = context! { kind => "warning" };
+      let expand = |src: &str| {
+         let prepared = md.prepare(src).unwrap();
+         md.emit_with_shortcodes(
+            prepared.to_render,
+            |text| Ok(text.to_string()),
+            |shortcode, content| {
+               expand_shortcode(&env, &components, shortcode, content, &context)
+                  .map_err(Into::into)
+            },
+         )
+         .map(|rendered| rendered.html().to_string())
+      };
+
+      assert_eq!(
+         expand("{% callout kind %}\n\nCareful *now*.\n\n{% endcallout %}\n").unwrap(),
+         "<aside class=\"warning\"><p>Careful <em>now</em>.</p>\n</aside>\n"
+      );
+      assert!(expand("{% sidebar %}\n\nHi.\n\n{% endsidebar %}\n").is_err());
+   }
+
+   #[test]
+   fn site_templates_override_shared_ones() {
+      let site = TempDir::new(
+         "override-site",
+         &[
+            ("base.jinja", "site, then {% include 'shared/base.jinja' %}"),
+            ("components/note.jinja", "site note"),
+            ("components/forms/field.jinja", "site field"),
+         ],
+      );
+      let shared = TempDir::new(
+         "override-shared",
+         &[("base.jinja", "shared"), ("head.jinja", "shared head")],
+      );
+
+      let env = load(&site.dir, Some(&shared.dir)).unwrap();
+      assert_eq!(render(&env, "base.jinja"), "site, then shared");
+      assert_eq!(render(&env, "shared/base.jinja"), "shared");
+      assert_eq!(render(&env, "head.jinja"), "shared head");
+      assert_eq!(render(&env, "components/note.jinja"), "site note");
+      assert!(env.get_template("shared/components/note.jinja").is_err());
+
+      // Nested templates are named by their whole path, `/`-separated.
+      assert_eq!(render(&env, "components/forms/field.jinja"), "site field");
+      let mut names = site.templates().names().unwrap();
+      names.sort();
+      assert_eq!(
+         names,
+         [
+            "base.jinja",
+            "components/forms/field.jinja",
+            "components/note.jinja"
+         ]
+      );
+
+      let env = load(&site.dir, None).unwrap();
+      assert!(env.get_template("shared/base.jinja").is_err());
+   }
+
+   #[test]
+   fn a_shared_directory_in_the_site_is_an_error() {
+      let site = TempDir::new("reserved", &[("shared/base.jinja", "")]);
+      assert!(matches!(
+         load(&site.dir, None),
+         Err(Error::ReservedName { path }) if path == site.dir.join("shared/")
+      ));
+   }
+
+   fn render(env: &Environment, name: &str) -> String {
+      env.get_template(name).unwrap().render(()).unwrap()
+   }
+}
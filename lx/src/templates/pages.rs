@@ -0,0 +1,293 @@
+//! Queries over every page in the site, available to templates as functions:
+//!
+//! - `pages(section=, tag=, sort=, limit=)`: summaries of the pages which match, sorted
+//!   by `sort` (`date`, `updated`, or `title`, with a leading `-` for descending order;
+//!   newest first by default) and truncated to `limit`.
+//! - `get_page(id_or_path)`: the summary for the page with that id, or which any link to
+//!   that target (e.g. `essays/jj-init`) would resolve to; `none` if there is none.
+//! - `featured()`: summaries of the featured pages, newest first.
+//!
+//! The summaries come from each page's metadata, before any content renders, so these
+//! work in the template syntax in content as well as in layouts.
+
+use std::{cmp::Ordering, path::Path, sync::Arc};
+
+use chrono::{DateTime, FixedOffset};
+use minijinja::{value::Kwargs, Environment, ErrorKind, Value};
+use serde::Serialize;
+
+use crate::{
+   data::item::{Metadata, Rendered},
+   links::Targets,
+   page::{self, Id, RootedPath, Source},
+};
+
+/// A lightweight summary of a page, for listing or linking to it.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+   pub id: String,
+   pub title: String,
+   pub subtitle: Option<Rendered>,
+   pub summary: Option<Rendered>,
+   pub date: Option<DateTime<FixedOffset>>,
+   /// When the page was most recently updated, if ever.
+   pub updated: Option<DateTime<FixedOffset>>,
+   pub tags: Vec<String>,
+   pub featured: bool,
+   /// The top-level directory of the page's source within the content directory, e.g.
+   /// `essays` for `essays/jj-init.md`. Pages at the root have no section.
+   pub section: Option<String>,
+   pub path: RootedPath,
+   /// The root-relative URL, e.g. `/essays/jj-init/`.
+   pub url: String,
+}
+
+impl Summary {
+   fn new(
+      source: &Source,
+      data: &Metadata,
+      content_dir: &Path,
+   ) -> Result<Summary, page::Error> {
+      let path = RootedPath::new(&data.slug, content_dir)?;
+      let section = source
+         .path
+         .strip_prefix(content_dir)
+         .ok()
+         .and_then(|relative| relative.parent())
+         .and_then(|parent| parent.iter().next())
+         .map(|section| section.to_string_lossy().to_string());
+
+      Ok(Summary {
+         id: Id::for_source(source).to_string(),
+         title: data.title.clone(),
+         subtitle: data.subtitle.clone(),
+         summary: data.summary.clone(),
+         date: data.date,
+         updated: data.updated.iter().map(|update| update.at).max(),
+         tags: data.tags.clone(),
+         featured: data.featured,
+         section,
+         url: path.root_relative_url(),
+         path,
+      })
+   }
+}
+
+pub struct Pages {
+   summaries: Vec<Summary>,
+   targets: Arc<Targets>,
+}
+
+impl Pages {
+   pub fn new<'a, I>(
+      pages: I,
+      content_dir: &Path,
+      targets: Arc<Targets>,
+   ) -> Result<Pages, Vec<(std::path::PathBuf, page::Error)>>
+   where
+      I: IntoIterator<Item = (&'a Source, &'a Metadata)>,
+   {
+      let mut summaries = Vec::new();
+      let mut errors = Vec::new();
+      for (source, data) in pages {
+         match Summary::new(source, data, content_dir) {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => errors.push((source.path.clone(), e)),
+         }
+      }
+
+      if errors.is_empty() {
+         Ok(Pages { summaries, targets })
+      } else {
+         Err(errors)
+      }
+   }
+
+   /// Make `pages()`, `get_page()`, and `featured()` available to templates.
+   pub fn add_to(self: &Arc<Self>, env: &mut Environment<'_>) {
+      let pages = Arc::clone(self);
+
+      let for_query = Arc::clone(&pages);
+      env.add_function("pages", move |kwargs: Kwargs| {
+         let section = kwargs.get::<Option<&str>>("section")?;
+         let tag = kwargs.get::<Option<&str>>("tag")?;
+         let sort = kwargs.get::<Option<&str>>("sort")?;
+         let limit = kwargs.get::<Option<usize>>("limit")?;
+         kwargs.assert_all_used()?;
+
+         let matching =
+            for_query.query(section, tag, sort.unwrap_or(DEFAULT_SORT), limit)?;
+         Ok(Value::from_serialize(matching))
+      });
+
+      let for_get = Arc::clone(&pages);
+      env.add_function("get_page", move |key: &str| {
+         Value::from_serialize(for_get.get(key))
+      });
+
+      env.add_function("featured", move || {
+         let featured = pages
+            .summaries
+            .iter()
+            .filter(|summary| summary.featured)
+            .collect::<Vec<_>>();
+         Value::from_serialize(sorted(featured, DEFAULT_SORT).unwrap_or_default())
+      });
+   }
+
+   /// The summaries of the pages in `section` and with `tag` (if given), sorted by
+   /// `sort` and with at most `limit` of them.
+   pub fn query(
+      &self,
+      section: Option<&str>,
+      tag: Option<&str>,
+      sort: &str,
+      limit: Option<usize>,
+   ) -> Result<Vec<&Summary>, minijinja::Error> {
+      let matching = self
+         .summaries
+         .iter()
+         .filter(|summary| {
+            section.is_none_or(|section| summary.section.as_deref() == Some(section))
+         })
+         .filter(|summary| tag.is_none_or(|tag| summary.tags.iter().any(|t| t == tag)))
+         .collect();
+
+      let mut sorted = sorted(matching, sort)?;
+      if let Some(limit) = limit {
+         sorted.truncate(limit);
+      }
+      Ok(sorted)
+   }
+
+   fn get(&self, key: &str) -> Option<&Summary> {
+      if let Some(summary) = self.summaries.iter().find(|summary| summary.id == key) {
+         return Some(summary);
+      }
+
+      let resolved = self.targets.resolve(key)?;
+      self
+         .summaries
+         .iter()
+         .find(|summary| summary.url == resolved.url)
+   }
+}
+
+const DEFAULT_SORT: &str = "-date";
+
+/// Sort by `date`, `updated`, or `title`, descending with a leading `-`. Pages without a
+/// date come last when sorting newest first, and first otherwise.
+fn sorted<'s>(
+   mut summaries: Vec<&'s Summary>,
+   sort: &str,
+) -> Result<Vec<&'s Summary>, minijinja::Error> {
+   let (key, descending) = match sort.strip_prefix('-') {
+      Some(key) => (key, true),
+      None => (sort, false),
+   };
+
+   let compare: fn(&Summary, &Summary) -> Ordering = match key {
+      "date" => |a, b| a.date.cmp(&b.date),
+      "updated" => |a, b| a.updated.cmp(&b.updated),
+      "title" => |a, b| a.title.cmp(&b.title),
+      _ => {
+         return Err(minijinja::Error::new(
+            ErrorKind::InvalidOperation,
+            format!("cannot sort pages by '{sort}' (use 'date', 'updated', or 'title')"),
+         ))
+      }
+   };
+
+   summaries.sort_by(|a, b| match descending {
+      true => compare(b, a),
+      false => compare(a, b),
+   });
+   Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn summary(
+      title: &str,
+      section: Option<&str>,
+      tags: &[&str],
+      date: Option<&str>,
+   ) -> Summary {
+      let path = RootedPath::new(
+         &crate::data::item::Slug::Permalink(title.to_lowercase()),
+         Path::new("content"),
+      )
+      .unwrap();
+      Summary {
+         id: title.to_lowercase(),
+         title: title.to_string(),
+         subtitle: None,
+         summary: None,
+         date: date.map(|date| DateTime::parse_from_rfc3339(date).unwrap()),
+         updated: None,
+         tags: tags.iter().map(|tag| tag.to_string()).collect(),
+         featured: false,
+         section: section.map(String::from),
+         url: path.root_relative_url(),
+         path,
+      }
+   }
+
+   #[test]
+   fn query_filters_sorts_and_limits() {
+      let targets = Targets::new([], Path::new("content")).unwrap();
+      let pages = Pages {
+         summaries: vec![
+            summary(
+               "Old",
+               Some("essays"),
+               &["rust"],
+               Some("2023-01-01T00:00:00Z"),
+            ),
+            summary("Undated", Some("essays"), &["rust"], None),
+            summary(
+               "New",
+               Some("essays"),
+               &["rust"],
+               Some("2024-01-01T00:00:00Z"),
+            ),
+            summary(
+               "Other",
+               Some("notes"),
+               &["rust"],
+               Some("2025-01-01T00:00:00Z"),
+            ),
+            summary(
+               "Untagged",
+               Some("essays"),
+               &[],
+               Some("2025-01-01T00:00:00Z"),
+            ),
+         ],
+         targets: Arc::new(targets),
+      };
+
+      let titles = |summaries: Vec<&Summary>| {
+         summaries
+            .iter()
+            .map(|summary| summary.title.clone())
+            .collect::<Vec<_>>()
+      };
+
+      let newest = pages
+         .query(Some("essays"), Some("rust"), "-date", None)
+         .unwrap();
+      assert_eq!(titles(newest), ["New", "Old", "Undated"]);
+
+      let limited = pages.query(None, Some("rust"), "title", Some(2)).unwrap();
+      assert_eq!(titles(limited), ["New", "Old"]);
+
+      assert!(pages.query(None, None, "-weight", None).is_err());
+      assert_eq!(
+         pages.get("other").map(|summary| summary.url.as_str()),
+         Some("/other/")
+      );
+   }
+}